@@ -25,6 +25,8 @@ pub fn frame_alloc(count: usize) -> PhysAddr {
     PhysAddr::new(ppn << 12)
 }
 
-pub fn frame_dealloc(paddr: PhysAddr) {
-    LOCK_FRAME_ALLOCATOR.lock().dealloc(paddr.raw() >> 12, 1);
+pub fn frame_dealloc(paddr: PhysAddr, count: usize) {
+    LOCK_FRAME_ALLOCATOR
+        .lock()
+        .dealloc(paddr.raw() >> 12, count);
 }
@@ -31,7 +31,15 @@ impl PageAlloc for PageAllocImpl {
     }
 
     fn dealloc(&self, paddr: PhysAddr) {
-        frame::frame_dealloc(paddr)
+        frame::frame_dealloc(paddr, 1)
+    }
+
+    fn alloc_contiguous(&self, count: usize) -> PhysAddr {
+        frame_alloc(count)
+    }
+
+    fn dealloc_contiguous(&self, paddr: PhysAddr, count: usize) {
+        frame::frame_dealloc(paddr, count)
     }
 }
 
@@ -48,7 +56,7 @@ fn kernel_interrupt(ctx: &mut TrapFrame, trap_type: TrapType) {
         }
         SysCall => {
             // jump to next instruction anyway
-            ctx.syscall_ok();
+            ctx.syscall_abi().skip();
             log::info!("Handle a syscall");
         }
         StorePageFault(paddr) | LoadPageFault(paddr) | InstructionPageFault(paddr) => {
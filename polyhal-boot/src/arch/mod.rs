@@ -6,7 +6,9 @@ use core::{
 use polyhal::{
     common::get_cpu_num,
     ctor::{ph_init_iter, CtorType},
+    pagetable::PAGE_SIZE,
     println,
+    stack::{register_stack_guard, STACK_GUARD_SIZE},
 };
 
 // Define multi-architecture modules and pub use them.
@@ -44,6 +46,7 @@ fn call_real_main(hartid: usize) {
     static IS_BOOT: AtomicBool = AtomicBool::new(true);
     static INIT_DONE: AtomicBool = AtomicBool::new(false);
     extern "Rust" {
+        fn bstack();
         fn _secondary_start();
         pub(crate) fn _main_for_arch(hartid: usize);
         pub(crate) fn _secondary_for_arch(hartid: usize);
@@ -52,11 +55,16 @@ fn call_real_main(hartid: usize) {
     if IS_BOOT.swap(false, Ordering::SeqCst) {
         const SP_SIZE: usize = 0x40_0000;
 
+        // Protect the boot stack and the secondary stacks with guard regions.
+        register_stack_guard(bstack as usize);
         (0..get_cpu_num()).for_each(|x| unsafe {
             if x == hartid {
                 return;
             }
-            let stack_top = polyhal::mem::alloc(SP_SIZE).add(SP_SIZE);
+            let area = polyhal::mem::alloc(SP_SIZE + STACK_GUARD_SIZE + PAGE_SIZE) as usize;
+            let stack_bottom = area.next_multiple_of(PAGE_SIZE) + STACK_GUARD_SIZE;
+            register_stack_guard(stack_bottom);
+            let stack_top = (stack_bottom + SP_SIZE) as *mut u8;
             println!("Boot Core: {}   {:#p}", x, stack_top);
             polyhal::multicore::boot_core(x, _secondary_start as usize, stack_top as usize);
         });
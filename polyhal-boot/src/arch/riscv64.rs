@@ -142,4 +142,6 @@ fn init_cpu() {
         sie::set_sext();
         sie::set_ssoft();
     }
+    // Enable the vector extension if it is supported.
+    polyhal::vector::init();
 }
@@ -12,11 +12,9 @@ use polyhal::{
     mem::{add_memory_region, parse_system_info},
     percpu::set_local_thread_pointer,
 };
-use raw_cpuid::CpuId;
 use x86_64::registers::{
-    control::{Cr0Flags, Cr4, Cr4Flags},
+    control::{Cr0Flags, Cr4Flags},
     model_specific::EferFlags,
-    xcontrol::{XCr0, XCr0Flags},
 };
 
 /// Flags set in the 'flags' member of the multiboot header.
@@ -178,19 +176,10 @@ fn _rust_secondary_main() {
 }
 
 fn init_cpu() {
-    // enable avx extend instruction set and sse if support avx
+    // Enable the extended states supported by the processor, AVX and AVX-512
+    // are enabled if supported.
     // TIPS: QEMU not support avx, so we can't enable avx here
     // IF you want to use avx in the qemu, you can use -cpu IvyBridge-v2 to
     // select a cpu with avx support
-    CpuId::new().get_feature_info().inspect(|features| unsafe {
-        // Add OSXSave flag to cr4 register if supported
-        if features.has_xsave() {
-            Cr4::update(|x| x.insert(Cr4Flags::OSXSAVE));
-        }
-        // XSAVE And Processor Extended States Enable
-        // This bit should open if the processor was supported.
-        if features.has_avx() && features.has_xsave() && features.has_sse() {
-            XCr0::write(XCr0::read() | XCr0Flags::AVX | XCr0Flags::SSE | XCr0Flags::X87);
-        }
-    });
+    polyhal::xsave::init();
 }
@@ -11,8 +11,13 @@ extern crate polyhal;
 
 mod arch;
 
+#[doc(hidden)]
+pub use polyhal::stack::STACK_GUARD_SIZE;
+
 /// Define the entry point.
 ///
+/// The boot stack `bstack` has a guard region of [STACK_GUARD_SIZE] below it.
+///
 /// TODO: Support secondary Entry Point for the application core.
 ///     - Implement MultiCore
 ///     - Jump to _secondary_for_arch function in application core.
@@ -25,11 +30,15 @@ macro_rules! define_entry {
                 .section .bss.bstack
                 .global bstack
                 .global bstack_top
+                .balign 0x1000
+                bstack_guard:
+                .fill {guard_size}
                 bstack:
                 .fill 0x80000
                 .size bstack, . - bstack
                 bstack_top:
-            "
+            ",
+            guard_size = const $crate::STACK_GUARD_SIZE,
         );
         #[export_name = "_main_for_arch"]
         fn _polyhal_defined_main(hart_id: usize) {
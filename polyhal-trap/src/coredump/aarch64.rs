@@ -0,0 +1,41 @@
+use crate::trapframe::TrapFrame;
+
+/// `EM_AARCH64`
+pub(super) const ELF_MACHINE: u16 = 183;
+pub(super) const ELF_FLAGS: u32 = 0;
+
+/// General registers, the layout of the Linux `struct user_pt_regs`.
+#[repr(C)]
+pub(super) struct UserRegs {
+    regs: [usize; 31],
+    sp: usize,
+    pc: usize,
+    pstate: usize,
+}
+
+/// FP/SIMD registers, the layout of the Linux `struct user_fpsimd_state`.
+#[repr(C)]
+pub(super) struct FpRegs {
+    vregs: [u128; 32],
+    fpsr: u32,
+    fpcr: u32,
+    _pad: [u32; 2],
+}
+
+pub(super) fn user_regs(tf: &TrapFrame) -> UserRegs {
+    UserRegs {
+        regs: tf.regs,
+        sp: tf.sp,
+        pc: tf.elr,
+        pstate: tf.spsr,
+    }
+}
+
+pub(super) fn fp_regs(tf: &TrapFrame) -> FpRegs {
+    FpRegs {
+        vregs: tf.fp.q,
+        fpsr: tf.fp.fpsr as _,
+        fpcr: tf.fp.fpcr as _,
+        _pad: [0; 2],
+    }
+}
@@ -0,0 +1,50 @@
+use crate::trapframe::TrapFrame;
+
+/// `EM_LOONGARCH`
+pub(super) const ELF_MACHINE: u16 = 258;
+/// `EF_LOONGARCH_ABI_DOUBLE_FLOAT` and `EF_LOONGARCH_OBJABI_V1`.
+pub(super) const ELF_FLAGS: u32 = 0x43;
+
+/// General registers, the layout of the Linux `elf_gregset_t`.
+#[repr(C)]
+pub(super) struct UserRegs {
+    regs: [usize; 32],
+    orig_a0: usize,
+    era: usize,
+    badv: usize,
+    crmd: usize,
+    prmd: usize,
+    euen: usize,
+    ecfg: usize,
+    estat: usize,
+    _reserved: [usize; 5],
+}
+
+/// Floating-point registers, the layout of the Linux `elf_fpregset_t`,
+/// `f0`-`f31`, `fcc` and `fcsr`.
+#[repr(C)]
+pub(super) struct FpRegs([u64; 34]);
+
+pub(super) fn user_regs(tf: &TrapFrame) -> UserRegs {
+    UserRegs {
+        regs: tf.regs,
+        orig_a0: tf.orig_a0,
+        era: tf.era,
+        badv: 0,
+        crmd: 0,
+        prmd: tf.prmd,
+        euen: 0,
+        ecfg: 0,
+        estat: 0,
+        _reserved: [0; 5],
+    }
+}
+
+pub(super) fn fp_regs(tf: &TrapFrame) -> FpRegs {
+    let fp = &tf.fp;
+    let mut regs = [0; 34];
+    regs[..32].copy_from_slice(&fp.fp);
+    regs[32] = u64::from_le_bytes(fp.fcc);
+    regs[33] = fp.fcsr as _;
+    FpRegs(regs)
+}
@@ -0,0 +1,298 @@
+//! Core dump module.
+//!
+//! Write the ELF core file (`ET_CORE`) of a crashed user task in the layout
+//! of the Linux core dumps, so it can be loaded by GDB with the executable.
+//!
+//! The `PT_NOTE` segment contains the `NT_PRSTATUS` note with the registers
+//! of the [TrapFrame] in the Linux `elf_gregset_t` of each architecture, and
+//! the `NT_PRFPREG` note with the floating-point registers. The pages mapped
+//! in the user half of the [PageTable] are written as the `PT_LOAD` segments,
+//! the device memory is skipped.
+//!
+//! The file is streamed to the [CoreWriter], no memory is allocated.
+//!
+//! ```ignore
+//! use polyhal_trap::coredump::{write_core, CoreWriter};
+//!
+//! struct FileWriter<'a>(&'a File);
+//!
+//! impl CoreWriter for FileWriter<'_> {
+//!     type Error = Errno;
+//!
+//!     fn write_all(&mut self, data: &[u8]) -> Result<(), Errno> {
+//!         self.0.write_all(data)
+//!     }
+//! }
+//!
+//! write_core(&mut FileWriter(&file), tf, &page_table, pid, SIGSEGV)?;
+//! ```
+
+use core::mem::size_of;
+
+use polyhal::{MappingFlags, PageTable, PhysAddr, VirtAddr};
+
+use crate::trapframe::TrapFrame;
+
+polyhal_macro::define_arch_mods!();
+
+const PAGE_SIZE: usize = polyhal::pagetable::PAGE_SIZE;
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRFPREG: u32 = 2;
+
+/// Name of the notes, padded to 4 bytes.
+const NOTE_NAME: &[u8; 8] = b"CORE\0\0\0\0";
+
+/// Destination of the core file.
+pub trait CoreWriter {
+    type Error;
+
+    /// Write all the bytes of `data` at the end of the file.
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// ELF file header, the layout of `Elf64_Ehdr`.
+#[repr(C)]
+struct ElfHeader {
+    ident: [u8; 16],
+    kind: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+/// Program header, the layout of `Elf64_Phdr`.
+#[repr(C)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+/// Note header, the layout of `Elf64_Nhdr`.
+#[repr(C)]
+struct NoteHeader {
+    namesz: u32,
+    descsz: u32,
+    kind: u32,
+}
+
+/// Process status, the layout of the Linux `struct elf_prstatus`.
+#[repr(C)]
+struct PrStatus {
+    /// `si_signo`, `si_code` and `si_errno`.
+    info: [i32; 3],
+    cursig: i16,
+    _pad0: i16,
+    sigpend: u64,
+    sighold: u64,
+    pid: i32,
+    ppid: i32,
+    pgrp: i32,
+    sid: i32,
+    /// `pr_utime`, `pr_stime`, `pr_cutime` and `pr_cstime`.
+    times: [u64; 8],
+    reg: UserRegs,
+    fpvalid: i32,
+    _pad1: i32,
+}
+
+/// A run of the pages with contiguous addresses and the same flags.
+#[derive(Clone, Copy)]
+struct Segment {
+    vaddr: usize,
+    size: usize,
+    flags: u32,
+}
+
+/// View a structure without padding bytes as bytes.
+#[inline]
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+/// The size of a note with its name and the padding.
+const fn note_size<T>() -> usize {
+    size_of::<NoteHeader>() + NOTE_NAME.len() + size_of::<T>().next_multiple_of(4)
+}
+
+/// Walk the user pages to be dumped, the device memory is skipped.
+fn for_each_page(
+    page_table: &PageTable,
+    mut f: impl FnMut(VirtAddr, PhysAddr, usize, MappingFlags),
+) {
+    page_table.walk_user(|vaddr, paddr, size, flags| {
+        if !flags.contains(MappingFlags::Device) {
+            f(vaddr, paddr, size, flags)
+        }
+    });
+}
+
+/// Merge the user pages into the segments.
+fn for_each_segment(page_table: &PageTable, mut f: impl FnMut(Segment)) {
+    let mut current: Option<Segment> = None;
+    for_each_page(page_table, |vaddr, _, size, flags| {
+        let mut pf = 0;
+        if flags.contains(MappingFlags::R) {
+            pf |= PF_R;
+        }
+        if flags.contains(MappingFlags::W) {
+            pf |= PF_W;
+        }
+        if flags.contains(MappingFlags::X) {
+            pf |= PF_X;
+        }
+        match current.as_mut() {
+            Some(seg) if seg.vaddr + seg.size == vaddr.raw() && seg.flags == pf => seg.size += size,
+            _ => {
+                if let Some(seg) = current.replace(Segment {
+                    vaddr: vaddr.raw(),
+                    size,
+                    flags: pf,
+                }) {
+                    f(seg);
+                }
+            }
+        }
+    });
+    if let Some(seg) = current {
+        f(seg);
+    }
+}
+
+fn write_note<W: CoreWriter, T>(writer: &mut W, kind: u32, desc: &T) -> Result<(), W::Error> {
+    let header = NoteHeader {
+        namesz: 5,
+        descsz: size_of::<T>() as u32,
+        kind,
+    };
+    writer.write_all(as_bytes(&header))?;
+    writer.write_all(NOTE_NAME)?;
+    writer.write_all(as_bytes(desc))?;
+    writer.write_all(&[0; 3][..size_of::<T>().next_multiple_of(4) - size_of::<T>()])
+}
+
+/// Write the core file of the user task stopped at `tf`, with the signal `sig`.
+///
+/// The memory of the task is read from the user half of `page_table`.
+/// The single thread of the process `pid` is dumped.
+pub fn write_core<W: CoreWriter>(
+    writer: &mut W,
+    tf: &TrapFrame,
+    page_table: &PageTable,
+    pid: usize,
+    sig: usize,
+) -> Result<(), W::Error> {
+    let mut phnum = 1;
+    for_each_segment(page_table, |_| phnum += 1);
+
+    let notes_offset = size_of::<ElfHeader>() + phnum * size_of::<ProgramHeader>();
+    let notes_size = note_size::<PrStatus>() + note_size::<FpRegs>();
+    let data_offset = (notes_offset + notes_size).next_multiple_of(PAGE_SIZE);
+
+    let mut ident = [0u8; 16];
+    // Magic, 64-bit, little-endian and the current version.
+    ident[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+    let header = ElfHeader {
+        ident,
+        kind: ET_CORE,
+        machine: ELF_MACHINE,
+        version: 1,
+        entry: 0,
+        phoff: size_of::<ElfHeader>() as u64,
+        shoff: 0,
+        flags: ELF_FLAGS,
+        ehsize: size_of::<ElfHeader>() as u16,
+        phentsize: size_of::<ProgramHeader>() as u16,
+        phnum: phnum as u16,
+        shentsize: 0,
+        shnum: 0,
+        shstrndx: 0,
+    };
+    writer.write_all(as_bytes(&header))?;
+
+    let note = ProgramHeader {
+        kind: PT_NOTE,
+        flags: 0,
+        offset: notes_offset as u64,
+        vaddr: 0,
+        paddr: 0,
+        filesz: notes_size as u64,
+        memsz: 0,
+        align: 4,
+    };
+    writer.write_all(as_bytes(&note))?;
+    let mut result = Ok(());
+    let mut offset = data_offset;
+    for_each_segment(page_table, |seg| {
+        let load = ProgramHeader {
+            kind: PT_LOAD,
+            flags: seg.flags,
+            offset: offset as u64,
+            vaddr: seg.vaddr as u64,
+            paddr: 0,
+            filesz: seg.size as u64,
+            memsz: seg.size as u64,
+            align: PAGE_SIZE as u64,
+        };
+        offset += seg.size;
+        if result.is_ok() {
+            result = writer.write_all(as_bytes(&load));
+        }
+    });
+    result?;
+
+    let status = PrStatus {
+        info: [sig as i32, 0, 0],
+        cursig: sig as i16,
+        _pad0: 0,
+        sigpend: 0,
+        sighold: 0,
+        pid: pid as i32,
+        ppid: 0,
+        pgrp: pid as i32,
+        sid: 0,
+        times: [0; 8],
+        reg: user_regs(tf),
+        fpvalid: 1,
+        _pad1: 0,
+    };
+    write_note(writer, NT_PRSTATUS, &status)?;
+    write_note(writer, NT_PRFPREG, &fp_regs(tf))?;
+
+    let padding = [0u8; 64];
+    let mut written = notes_offset + notes_size;
+    while written < data_offset {
+        let len = (data_offset - written).min(padding.len());
+        writer.write_all(&padding[..len])?;
+        written += len;
+    }
+
+    let mut result = Ok(());
+    for_each_page(page_table, |_, paddr, size, _| {
+        if result.is_ok() {
+            result = writer.write_all(paddr.slice_with_len(size));
+        }
+    });
+    result
+}
@@ -0,0 +1,32 @@
+use crate::trapframe::TrapFrame;
+
+/// `EM_RISCV`
+pub(super) const ELF_MACHINE: u16 = 243;
+pub(super) const ELF_FLAGS: u32 = 0;
+
+/// General registers, the layout of the Linux `struct user_regs_struct`,
+/// `pc` followed by `x1`-`x31`.
+#[repr(C)]
+pub(super) struct UserRegs([usize; 32]);
+
+/// Floating-point registers, the layout of the Linux `struct __riscv_d_ext_state`.
+#[repr(C)]
+pub(super) struct FpRegs {
+    f: [u64; 32],
+    fcsr: u32,
+    _pad: u32,
+}
+
+pub(super) fn user_regs(tf: &TrapFrame) -> UserRegs {
+    let mut regs = tf.x;
+    regs[0] = tf.sepc;
+    UserRegs(regs)
+}
+
+pub(super) fn fp_regs(tf: &TrapFrame) -> FpRegs {
+    FpRegs {
+        f: tf.fp.f,
+        fcsr: tf.fp.fcsr as _,
+        _pad: 0,
+    }
+}
@@ -0,0 +1,78 @@
+use crate::trapframe::{FxsaveArea, TrapFrame};
+
+/// `EM_X86_64`
+pub(super) const ELF_MACHINE: u16 = 62;
+pub(super) const ELF_FLAGS: u32 = 0;
+
+/// General registers, the layout of the Linux `struct user_regs_struct`.
+#[repr(C)]
+pub(super) struct UserRegs {
+    r15: usize,
+    r14: usize,
+    r13: usize,
+    r12: usize,
+    rbp: usize,
+    rbx: usize,
+    r11: usize,
+    r10: usize,
+    r9: usize,
+    r8: usize,
+    rax: usize,
+    rcx: usize,
+    rdx: usize,
+    rsi: usize,
+    rdi: usize,
+    orig_rax: usize,
+    rip: usize,
+    cs: usize,
+    eflags: usize,
+    rsp: usize,
+    ss: usize,
+    fs_base: usize,
+    gs_base: usize,
+    ds: usize,
+    es: usize,
+    fs: usize,
+    gs: usize,
+}
+
+/// Floating-point registers, the layout of `FXSAVE`.
+pub(super) type FpRegs = FxsaveArea;
+
+pub(super) fn user_regs(tf: &TrapFrame) -> UserRegs {
+    UserRegs {
+        r15: tf.r15,
+        r14: tf.r14,
+        r13: tf.r13,
+        r12: tf.r12,
+        rbp: tf.rbp,
+        rbx: tf.rbx,
+        r11: tf.r11,
+        r10: tf.r10,
+        r9: tf.r9,
+        r8: tf.r8,
+        rax: tf.rax,
+        rcx: tf.rcx,
+        rdx: tf.rdx,
+        rsi: tf.rsi,
+        rdi: tf.rdi,
+        // Not in a syscall.
+        orig_rax: tf.orig_rax,
+        rip: tf.rip,
+        cs: tf.cs,
+        eflags: tf.rflags,
+        rsp: tf.rsp,
+        ss: tf.ss,
+        fs_base: tf.fs_base,
+        gs_base: tf.gs_base,
+        ds: 0,
+        es: 0,
+        fs: 0,
+        gs: 0,
+    }
+}
+
+/// The initial state is dumped if the task didn't use the floating-point registers.
+pub(super) fn fp_regs(tf: &TrapFrame) -> FpRegs {
+    tf.xstate.legacy().cloned().unwrap_or_default()
+}
@@ -0,0 +1,91 @@
+use core::arch::asm;
+
+use super::{RegReader, Reply};
+use crate::trapframe::{TrapFrame, SPSR_SS, TRAPFRAME_SIZE};
+
+/// The trap handler skips the break instruction.
+pub(super) const BREAK_PC_ADJUST: usize = 4;
+/// The single-step is done by `MDSCR_EL1.SS`.
+pub(super) const CAN_STEP: bool = true;
+
+/// Debug exceptions masked, `SPSR_EL1.D`.
+const SPSR_D: usize = 1 << 9;
+
+/// Get the break instruction of the `kind` given by GDB, the instruction length.
+pub(super) fn break_insn(kind: usize) -> Option<&'static [u8]> {
+    match kind {
+        // brk #0
+        4 => Some(&[0x00, 0x00, 0x20, 0xd4]),
+        _ => None,
+    }
+}
+
+/// Registers `x0`-`x30`, `sp`, `pc` and `cpsr` in 32 bits.
+pub(super) fn read_registers(tf: &TrapFrame, sp: usize, reply: &mut Reply) {
+    tf.regs.iter().for_each(|reg| reply.push_reg(*reg, 8));
+    reply.push_reg(sp, 8);
+    reply.push_reg(tf.elr, 8);
+    reply.push_reg(tf.spsr, 4);
+}
+
+pub(super) fn write_registers(tf: &mut TrapFrame, regs: &mut RegReader) {
+    tf.regs.iter_mut().for_each(|reg| regs.next_into(reg, 8));
+    // The stack of the kernel can't be switched by the trap return.
+    regs.next(8);
+    regs.next_into(&mut tf.elr, 8);
+}
+
+#[inline]
+pub(super) fn pc(tf: &TrapFrame) -> usize {
+    tf.elr
+}
+
+#[inline]
+pub(super) fn set_pc(tf: &mut TrapFrame, pc: usize) {
+    tf.elr = pc;
+}
+
+/// The trap frame is pushed on the kernel stack, `tf.sp` is `SP_EL0`.
+#[inline]
+pub(super) fn stack_pointer(tf: &TrapFrame) -> usize {
+    tf as *const _ as usize + TRAPFRAME_SIZE
+}
+
+/// Step the kernel, the debug exceptions are unmasked for an instruction.
+pub(super) fn set_step(tf: &mut TrapFrame, enable: bool) {
+    polyhal::breakpoint::set_kernel_step(enable);
+    match enable {
+        true => tf.spsr = (tf.spsr | SPSR_SS) & !SPSR_D,
+        false => tf.spsr = (tf.spsr & !SPSR_SS) | SPSR_D,
+    }
+}
+
+/// Clean the data cache and invalidate the instruction cache of the written range.
+pub(super) fn flush_icache(addr: usize, len: usize) {
+    for addr in (addr & !3..addr + len).step_by(4) {
+        unsafe { asm!("dc cvau, {0}", "dsb ish", "ic ivau, {0}", in(reg) addr) };
+    }
+    unsafe { asm!("dsb ish", "isb") };
+}
+
+/// Capture the registers of the caller to report a panic.
+#[inline(always)]
+pub(super) fn current_frame() -> (TrapFrame, usize) {
+    let mut tf = TrapFrame::new();
+    let sp;
+    unsafe {
+        asm!(
+            "adr {pc}, .",
+            "mov {lr}, x30",
+            "mov {sp}, sp",
+            "mov {fp}, x29",
+            pc = out(reg) tf.elr,
+            lr = out(reg) tf.regs[30],
+            sp = out(reg) sp,
+            fp = out(reg) tf.regs[29],
+        );
+    }
+    // EL1h
+    tf.spsr = 0b0101;
+    (tf, sp)
+}
@@ -0,0 +1,76 @@
+use core::arch::asm;
+
+use super::{RegReader, Reply};
+use crate::trapframe::TrapFrame;
+
+/// The trap handler skips the break instruction.
+pub(super) const BREAK_PC_ADJUST: usize = 4;
+/// The single-step is done by GDB through the breakpoints.
+pub(super) const CAN_STEP: bool = false;
+
+/// Get the break instruction of the `kind` given by GDB, the instruction length.
+pub(super) fn break_insn(kind: usize) -> Option<&'static [u8]> {
+    match kind {
+        // break 0
+        4 => Some(&[0x00, 0x00, 0x2a, 0x00]),
+        _ => None,
+    }
+}
+
+/// Registers `r0`-`r31`, `orig_a0`, `pc` and `badv`.
+pub(super) fn read_registers(tf: &TrapFrame, _sp: usize, reply: &mut Reply) {
+    tf.regs.iter().for_each(|reg| reply.push_reg(*reg, 8));
+    reply.push_reg(tf.regs[4], 8);
+    reply.push_reg(tf.era, 8);
+    reply.push_reg(0, 8);
+}
+
+pub(super) fn write_registers(tf: &mut TrapFrame, regs: &mut RegReader) {
+    regs.next(8);
+    tf.regs[1..].iter_mut().for_each(|reg| regs.next_into(reg, 8));
+    regs.next(8);
+    regs.next_into(&mut tf.era, 8);
+}
+
+#[inline]
+pub(super) fn pc(tf: &TrapFrame) -> usize {
+    tf.era
+}
+
+#[inline]
+pub(super) fn set_pc(tf: &mut TrapFrame, pc: usize) {
+    tf.era = pc;
+}
+
+#[inline]
+pub(super) fn stack_pointer(tf: &TrapFrame) -> usize {
+    tf.regs[3]
+}
+
+#[inline]
+pub(super) fn set_step(_tf: &mut TrapFrame, _enable: bool) {}
+
+#[inline]
+pub(super) fn flush_icache(_addr: usize, _len: usize) {
+    unsafe { asm!("ibar 0") };
+}
+
+/// Capture the registers of the caller to report a panic.
+#[inline(always)]
+pub(super) fn current_frame() -> (TrapFrame, usize) {
+    let mut tf = TrapFrame::new();
+    unsafe {
+        asm!(
+            "pcaddi {pc}, 0",
+            "move {ra}, $ra",
+            "move {sp}, $sp",
+            "move {fp}, $fp",
+            pc = out(reg) tf.era,
+            ra = out(reg) tf.regs[1],
+            sp = out(reg) tf.regs[3],
+            fp = out(reg) tf.regs[22],
+        );
+    }
+    let sp = tf.regs[3];
+    (tf, sp)
+}
@@ -0,0 +1,548 @@
+//! GDB remote stub module.
+//!
+//! Speak the GDB remote serial protocol when the kernel stops at a breakpoint,
+//! a single step or a panic, so the kernel can be debugged by `gdb-multiarch`.
+//! The packets go through the [DebugConsole] UART by default, another UART
+//! can be used by [set_connection].
+//!
+//! The stub supports the register read and write in the layout of the
+//! [TrapFrame] of each architecture, the memory read and write, the software
+//! breakpoints, continue and step. The addresses are translated by the current
+//! [PageTable], an unmapped address is reported to GDB as an error.
+//!
+//! | Architecture | Break instruction | Step                 |
+//! | ------------ | ----------------- | -------------------- |
+//! | x86_64       | `int3`            | `RFLAGS.TF`          |
+//! | aarch64      | `brk #0`          | `MDSCR_EL1.SS`       |
+//! | riscv64      | `ebreak`/`c.ebreak` | breakpoints by GDB |
+//! | loongarch64  | `break 0`         | breakpoints by GDB   |
+//!
+//! The kernel waits for GDB at the first breakpoint instruction, only the
+//! traps from the kernel are handled by the stub.
+//!
+//! ```shell
+//! qemu-system-riscv64 -kernel kernel.bin -serial pty ...
+//! gdb-multiarch kernel.elf -ex "target remote /dev/pts/N"
+//! ```
+//!
+//! ```ignore
+//! #[panic_handler]
+//! fn panic(info: &PanicInfo) -> ! {
+//!     polyhal_trap::gdbstub::on_panic(info)
+//! }
+//! ```
+//!
+//! [PageTable]: polyhal::PageTable
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+use polyhal::debug_console::DebugConsole;
+use polyhal::utils::MutexNoIrq;
+use polyhal::{PageTable, VirtAddr};
+
+use crate::trap::TrapType;
+use crate::trapframe::TrapFrame;
+
+polyhal_macro::define_arch_mods!();
+
+/// The maximum size of a packet, given to GDB by `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+/// The maximum number of the software breakpoints.
+const MAX_BREAKPOINTS: usize = 32;
+
+/// Signal reported at a breakpoint or a step.
+const SIGTRAP: u8 = 5;
+/// Signal reported at a panic.
+const SIGABRT: u8 = 6;
+
+/// Byte stream between the stub and GDB.
+pub trait Connection: Sync {
+    /// Read a byte, block until it's received.
+    fn read(&self) -> u8;
+    /// Write a byte.
+    fn write(&self, byte: u8);
+}
+
+/// The default connection through the [DebugConsole].
+struct ConsoleConnection;
+
+impl Connection for ConsoleConnection {
+    fn read(&self) -> u8 {
+        loop {
+            if let Some(byte) = DebugConsole::getchar() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn write(&self, byte: u8) {
+        DebugConsole::putchar(byte);
+    }
+}
+
+/// A software breakpoint inserted by GDB.
+#[derive(Clone, Copy)]
+struct SwBreakpoint {
+    addr: usize,
+    len: usize,
+    orig: [u8; 4],
+}
+
+/// Command of GDB after a packet is handled.
+enum Command {
+    /// Send the reply and wait for the next packet.
+    Reply,
+    /// Resume the kernel.
+    Continue,
+    /// Resume the kernel for an instruction.
+    Step,
+    /// Remove the breakpoints and resume the kernel.
+    Detach,
+}
+
+struct GdbStub {
+    conn: &'static dyn Connection,
+    /// GDB has connected, the stop reason is sent when the kernel stops.
+    attached: bool,
+    /// The kernel is stepped by the stub.
+    stepping: bool,
+    breakpoints: [Option<SwBreakpoint>; MAX_BREAKPOINTS],
+    packet: [u8; PACKET_SIZE],
+    reply: Reply,
+}
+
+static STUB: MutexNoIrq<GdbStub> = MutexNoIrq::new(GdbStub {
+    conn: &ConsoleConnection,
+    attached: false,
+    stepping: false,
+    breakpoints: [None; MAX_BREAKPOINTS],
+    packet: [0; PACKET_SIZE],
+    reply: Reply {
+        buf: [0; PACKET_SIZE],
+        len: 0,
+    },
+});
+
+/// Use another UART for GDB instead of the [DebugConsole].
+pub fn set_connection(conn: &'static dyn Connection) {
+    STUB.lock().conn = conn;
+}
+
+/// Enter the stub if the kernel stopped at a breakpoint or a single step.
+///
+/// Return `true` if the trap was handled by the stub, the trap frame
+/// has been updated by GDB.
+pub(crate) fn handle_trap(tf: &mut TrapFrame, trap_type: TrapType) -> bool {
+    if tf.from_user() {
+        return false;
+    }
+    match trap_type {
+        TrapType::Breakpoint | TrapType::SingleStep => {
+            let sp = stack_pointer(tf);
+            STUB.lock().session(tf, sp, SIGTRAP);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Report the panic to GDB and let it inspect the kernel, never returns.
+///
+/// Call it from the `#[panic_handler]` of the kernel. If the stub is in use,
+/// the panic was raised by the stub or another cpu is being debugged, the
+/// panic is printed on the [DebugConsole] and the machine is shut down.
+pub fn on_panic(info: &PanicInfo) -> ! {
+    let (mut tf, sp) = current_frame();
+    let Some(mut stub) = STUB.try_lock() else {
+        polyhal::println!("{}", info);
+        polyhal::instruction::shutdown();
+    };
+    stub.console_output(format_args!("{}\n", info));
+    loop {
+        stub.session(&mut tf, sp, SIGABRT);
+    }
+}
+
+impl GdbStub {
+    /// Serve GDB until the kernel is resumed.
+    fn session(&mut self, tf: &mut TrapFrame, sp: usize, signal: u8) {
+        if self.stepping {
+            self.stepping = false;
+            set_step(tf, false);
+        }
+        // The break instruction was skipped by the trap handler.
+        let swbreak = signal == SIGTRAP && self.rewind_breakpoint(tf);
+        if self.attached {
+            self.reply.clear();
+            self.reply.stop_reason(signal, swbreak);
+            self.send_reply();
+        }
+        loop {
+            let len = self.recv_packet();
+            self.attached = true;
+            self.reply.clear();
+            let packet = &self.packet[..len];
+            let command = handle_packet(
+                packet,
+                &mut self.reply,
+                &mut self.breakpoints,
+                tf,
+                sp,
+                (signal, swbreak),
+            );
+            match command {
+                Command::Reply => self.send_reply(),
+                Command::Continue => return,
+                Command::Step => {
+                    self.stepping = true;
+                    set_step(tf, true);
+                    return;
+                }
+                Command::Detach => {
+                    self.send_reply();
+                    for bp in self.breakpoints.iter_mut().filter_map(Option::take) {
+                        remove_breakpoint(&bp);
+                    }
+                    self.attached = false;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Move the pc back to the breakpoint if it was inserted by GDB.
+    fn rewind_breakpoint(&self, tf: &mut TrapFrame) -> bool {
+        let addr = pc(tf).wrapping_sub(BREAK_PC_ADJUST);
+        let hit = self.breakpoints.iter().flatten().any(|bp| bp.addr == addr);
+        if hit {
+            set_pc(tf, addr);
+        }
+        hit
+    }
+
+    /// Receive a packet `$data#checksum`, return the length of the data.
+    fn recv_packet(&mut self) -> usize {
+        loop {
+            while self.conn.read() != b'$' {}
+            let mut len = 0;
+            let mut sum = 0u8;
+            loop {
+                let byte = self.conn.read();
+                if byte == b'#' {
+                    break;
+                }
+                if len < PACKET_SIZE {
+                    self.packet[len] = byte;
+                    len += 1;
+                }
+                sum = sum.wrapping_add(byte);
+            }
+            let checksum = [self.conn.read(), self.conn.read()];
+            if len < PACKET_SIZE && parse_hex(&checksum) == Some(sum as usize) {
+                self.conn.write(b'+');
+                return len;
+            }
+            self.conn.write(b'-');
+        }
+    }
+
+    /// Send the reply, resend it until GDB acknowledges it.
+    fn send_reply(&mut self) {
+        let data = &self.reply.buf[..self.reply.len];
+        let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        loop {
+            self.conn.write(b'$');
+            data.iter().for_each(|byte| self.conn.write(*byte));
+            self.conn.write(b'#');
+            self.conn.write(HEX[(sum >> 4) as usize]);
+            self.conn.write(HEX[(sum & 0xf) as usize]);
+            match self.conn.read() {
+                b'-' => continue,
+                _ => break,
+            }
+        }
+    }
+
+    /// Print a message on the GDB console if it's connected, or on the [DebugConsole].
+    fn console_output(&mut self, args: fmt::Arguments) {
+        match self.attached {
+            true => {
+                self.reply.clear();
+                self.reply.push(b"O");
+                let mut hex = HexWriter(&mut self.reply);
+                hex.write_fmt(args).ok();
+                self.send_reply();
+            }
+            false => polyhal::print!("{}", args),
+        }
+    }
+}
+
+/// Handle a packet and build the reply.
+fn handle_packet(
+    packet: &[u8],
+    reply: &mut Reply,
+    breakpoints: &mut [Option<SwBreakpoint>],
+    tf: &mut TrapFrame,
+    sp: usize,
+    (signal, swbreak): (u8, bool),
+) -> Command {
+    let Some((&cmd, args)) = packet.split_first() else {
+        return Command::Reply;
+    };
+    match cmd {
+        b'?' => reply.stop_reason(signal, swbreak),
+        b'g' => read_registers(tf, sp, reply),
+        b'G' => {
+            let mut regs = RegReader(args);
+            write_registers(tf, &mut regs);
+            reply.push(b"OK");
+        }
+        b'm' => {
+            let Some((addr, len)) = parse_range(args) else {
+                reply.push(b"E22");
+                return Command::Reply;
+            };
+            let len = len.min(PACKET_SIZE / 2);
+            match (0..len).all(|i| read_byte(addr + i).is_some()) {
+                true => (0..len).for_each(|i| reply.push_hex(read_byte(addr + i).unwrap())),
+                false => reply.push(b"E14"),
+            }
+        }
+        b'M' => {
+            let mut parts = args.splitn(2, |c| *c == b':');
+            let range = parts.next().and_then(parse_range);
+            let (Some((addr, len)), Some(data)) = (range, parts.next()) else {
+                reply.push(b"E22");
+                return Command::Reply;
+            };
+            let written = (0..len).all(|i| {
+                let byte = data.get(i * 2..i * 2 + 2).and_then(parse_hex);
+                byte.is_some_and(|byte| write_byte(addr + i, byte as u8))
+            });
+            flush_icache(addr, len);
+            reply.push(if written { b"OK" } else { b"E14" });
+        }
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                set_pc(tf, addr);
+            }
+            return match cmd == b's' && CAN_STEP {
+                true => Command::Step,
+                false => Command::Continue,
+            };
+        }
+        b'v' if args == b"Cont?" => match CAN_STEP {
+            true => reply.push(b"vCont;c;C;s;S"),
+            false => reply.push(b"vCont;c;C"),
+        },
+        b'v' if args.starts_with(b"Cont;") => {
+            // All the threads share the action of the kernel.
+            return match args.get(5) {
+                Some(b's' | b'S') if CAN_STEP => Command::Step,
+                _ => Command::Continue,
+            };
+        }
+        b'Z' | b'z' if args.first() == Some(&b'0') => {
+            let mut fields = args[1..].split(|c| *c == b',').skip(1);
+            let addr = fields.next().and_then(parse_hex);
+            let kind = fields.next().and_then(parse_hex);
+            let (Some(addr), Some(kind)) = (addr, kind) else {
+                reply.push(b"E22");
+                return Command::Reply;
+            };
+            let done = match cmd {
+                b'Z' => insert_breakpoint(breakpoints, addr, kind),
+                _ => {
+                    let bp = breakpoints
+                        .iter_mut()
+                        .find(|bp| bp.is_some_and(|bp| bp.addr == addr))
+                        .and_then(Option::take);
+                    bp.is_some_and(|bp| remove_breakpoint(&bp))
+                }
+            };
+            reply.push(if done { b"OK" } else { b"E14" });
+        }
+        b'q' if args.starts_with(b"Supported") => {
+            reply.push(b"PacketSize=1000;swbreak+");
+        }
+        b'q' if args == b"Attached" => reply.push(b"1"),
+        b'q' if args == b"fThreadInfo" => reply.push(b"m1"),
+        b'q' if args == b"sThreadInfo" => reply.push(b"l"),
+        b'q' if args == b"C" => reply.push(b"QC1"),
+        b'H' | b'T' => reply.push(b"OK"),
+        b'D' => {
+            reply.push(b"OK");
+            return Command::Detach;
+        }
+        // The kernel can't be killed, let it run without GDB.
+        b'k' => return Command::Detach,
+        // Unsupported, reply an empty packet.
+        _ => {}
+    }
+    Command::Reply
+}
+
+/// Insert a software breakpoint, `kind` is the length of the instruction.
+fn insert_breakpoint(breakpoints: &mut [Option<SwBreakpoint>], addr: usize, kind: usize) -> bool {
+    if breakpoints.iter().flatten().any(|bp| bp.addr == addr) {
+        return true;
+    }
+    let (Some(insn), Some(slot)) = (
+        break_insn(kind),
+        breakpoints.iter_mut().find(|bp| bp.is_none()),
+    ) else {
+        return false;
+    };
+    let mut orig = [0u8; 4];
+    for (i, byte) in orig.iter_mut().take(insn.len()).enumerate() {
+        match read_byte(addr + i) {
+            Some(value) => *byte = value,
+            None => return false,
+        }
+    }
+    let bp = SwBreakpoint {
+        addr,
+        len: insn.len(),
+        orig,
+    };
+    let written = insn
+        .iter()
+        .enumerate()
+        .all(|(i, byte)| write_byte(addr + i, *byte));
+    if !written {
+        // Restore the bytes written before the failure.
+        remove_breakpoint(&bp);
+        return false;
+    }
+    flush_icache(addr, bp.len);
+    *slot = Some(bp);
+    true
+}
+
+/// Restore the original instruction of a software breakpoint.
+fn remove_breakpoint(bp: &SwBreakpoint) -> bool {
+    let restored = (0..bp.len).all(|i| write_byte(bp.addr + i, bp.orig[i]));
+    flush_icache(bp.addr, bp.len);
+    restored
+}
+
+/// Get the pointer of a byte translated by the current page table, the
+/// kernel addresses included, `None` if the page isn't mapped.
+fn byte_ptr(addr: usize) -> Option<*mut u8> {
+    PageTable::current()
+        .translate(VirtAddr::new(addr))
+        .filter(|(_, flags)| !flags.is_empty())
+        .map(|(paddr, _)| paddr.get_mut_ptr())
+}
+
+#[inline]
+fn read_byte(addr: usize) -> Option<u8> {
+    byte_ptr(addr).map(|ptr| unsafe { ptr.read_volatile() })
+}
+
+#[inline]
+fn write_byte(addr: usize, byte: u8) -> bool {
+    byte_ptr(addr)
+        .map(|ptr| unsafe { ptr.write_volatile(byte) })
+        .is_some()
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// Parse a big-endian hex number.
+fn parse_hex(hex: &[u8]) -> Option<usize> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    hex.iter().try_fold(0, |value, c| {
+        let digit = (*c as char).to_digit(16)?;
+        Some((value << 4) | digit as usize)
+    })
+}
+
+/// Parse the memory range `addr,length`.
+fn parse_range(args: &[u8]) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, |c| *c == b',');
+    let addr = parse_hex(parts.next()?)?;
+    let len = parse_hex(parts.next()?)?;
+    Some((addr, len))
+}
+
+/// The data of the reply packet.
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    #[inline]
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        let len = data.len().min(PACKET_SIZE - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&data[..len]);
+        self.len += len;
+    }
+
+    #[inline]
+    fn push_hex(&mut self, byte: u8) {
+        self.push(&[HEX[(byte >> 4) as usize], HEX[(byte & 0xf) as usize]]);
+    }
+
+    /// Push a register of `size` bytes in the target byte order.
+    fn push_reg(&mut self, value: usize, size: usize) {
+        value.to_le_bytes()[..size]
+            .iter()
+            .for_each(|byte| self.push_hex(*byte));
+    }
+
+    fn stop_reason(&mut self, signal: u8, swbreak: bool) {
+        match swbreak {
+            true => self.push(b"T"),
+            false => self.push(b"S"),
+        }
+        self.push_hex(signal);
+        if swbreak {
+            self.push(b"swbreak:;");
+        }
+    }
+}
+
+/// Hex encode the formatted text.
+struct HexWriter<'a>(&'a mut Reply);
+
+impl Write for HexWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.0.push_hex(byte));
+        Ok(())
+    }
+}
+
+/// Reader of the registers of the `G` packet.
+struct RegReader<'a>(&'a [u8]);
+
+impl RegReader<'_> {
+    /// Read a register of `size` bytes in the target byte order.
+    fn next(&mut self, size: usize) -> Option<usize> {
+        let hex = self.0.get(..size * 2)?;
+        self.0 = &self.0[size * 2..];
+        let mut bytes = [0u8; 8];
+        for (i, byte) in bytes.iter_mut().take(size).enumerate() {
+            *byte = parse_hex(&hex[i * 2..i * 2 + 2])? as u8;
+        }
+        Some(usize::from_le_bytes(bytes))
+    }
+
+    /// Write the next register of `size` bytes to `reg`, keep it if the packet is short.
+    #[inline]
+    fn next_into(&mut self, reg: &mut usize, size: usize) {
+        if let Some(value) = self.next(size) {
+            *reg = value;
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use core::arch::asm;
+
+use super::{RegReader, Reply};
+use crate::trapframe::TrapFrame;
+
+/// The trap handler skips 2 bytes of the break instruction.
+pub(super) const BREAK_PC_ADJUST: usize = 2;
+/// The single-step is done by GDB through the breakpoints.
+pub(super) const CAN_STEP: bool = false;
+
+/// Get the break instruction of the `kind` given by GDB, the instruction length.
+pub(super) fn break_insn(kind: usize) -> Option<&'static [u8]> {
+    match kind {
+        // c.ebreak
+        2 => Some(&[0x02, 0x90]),
+        // ebreak
+        4 => Some(&[0x73, 0x00, 0x10, 0x00]),
+        _ => None,
+    }
+}
+
+/// Registers `x0`-`x31` and `pc`.
+pub(super) fn read_registers(tf: &TrapFrame, _sp: usize, reply: &mut Reply) {
+    tf.x.iter().for_each(|reg| reply.push_reg(*reg, 8));
+    reply.push_reg(tf.sepc, 8);
+}
+
+pub(super) fn write_registers(tf: &mut TrapFrame, regs: &mut RegReader) {
+    regs.next(8);
+    tf.x[1..].iter_mut().for_each(|reg| regs.next_into(reg, 8));
+    regs.next_into(&mut tf.sepc, 8);
+}
+
+#[inline]
+pub(super) fn pc(tf: &TrapFrame) -> usize {
+    tf.sepc
+}
+
+#[inline]
+pub(super) fn set_pc(tf: &mut TrapFrame, pc: usize) {
+    tf.sepc = pc;
+}
+
+#[inline]
+pub(super) fn stack_pointer(tf: &TrapFrame) -> usize {
+    tf.x[2]
+}
+
+#[inline]
+pub(super) fn set_step(_tf: &mut TrapFrame, _enable: bool) {}
+
+#[inline]
+pub(super) fn flush_icache(_addr: usize, _len: usize) {
+    unsafe { asm!("fence.i") };
+}
+
+/// Capture the registers of the caller to report a panic.
+#[inline(always)]
+pub(super) fn current_frame() -> (TrapFrame, usize) {
+    let mut tf = TrapFrame::new();
+    unsafe {
+        asm!(
+            "auipc {pc}, 0",
+            "mv {ra}, ra",
+            "mv {sp}, sp",
+            "mv {fp}, s0",
+            pc = out(reg) tf.sepc,
+            ra = out(reg) tf.x[1],
+            sp = out(reg) tf.x[2],
+            fp = out(reg) tf.x[8],
+        );
+    }
+    let sp = tf.x[2];
+    (tf, sp)
+}
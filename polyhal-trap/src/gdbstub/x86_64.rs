@@ -0,0 +1,104 @@
+use core::arch::asm;
+
+use super::{RegReader, Reply};
+use crate::trapframe::TrapFrame;
+
+/// The `rip` is after the `int3` instruction.
+pub(super) const BREAK_PC_ADJUST: usize = 1;
+/// The single-step is done by `RFLAGS.TF`.
+pub(super) const CAN_STEP: bool = true;
+
+/// Get the break instruction of the `kind` given by GDB, the instruction length.
+pub(super) fn break_insn(kind: usize) -> Option<&'static [u8]> {
+    match kind {
+        // int3
+        1 => Some(&[0xcc]),
+        _ => None,
+    }
+}
+
+/// Registers `rax`, `rbx`, `rcx`, `rdx`, `rsi`, `rdi`, `rbp`, `rsp`, `r8`-`r15`,
+/// `rip`, then `eflags`, `cs`, `ss`, `ds`, `es`, `fs` and `gs` in 32 bits.
+pub(super) fn read_registers(tf: &TrapFrame, _sp: usize, reply: &mut Reply) {
+    [
+        tf.rax, tf.rbx, tf.rcx, tf.rdx, tf.rsi, tf.rdi, tf.rbp, tf.rsp, tf.r8, tf.r9, tf.r10,
+        tf.r11, tf.r12, tf.r13, tf.r14, tf.r15, tf.rip,
+    ]
+    .iter()
+    .for_each(|reg| reply.push_reg(*reg, 8));
+    [tf.rflags, tf.cs, tf.ss, 0, 0, 0, 0]
+        .iter()
+        .for_each(|reg| reply.push_reg(*reg, 4));
+}
+
+pub(super) fn write_registers(tf: &mut TrapFrame, regs: &mut RegReader) {
+    [
+        &mut tf.rax,
+        &mut tf.rbx,
+        &mut tf.rcx,
+        &mut tf.rdx,
+        &mut tf.rsi,
+        &mut tf.rdi,
+        &mut tf.rbp,
+    ]
+    .into_iter()
+    .for_each(|reg| regs.next_into(reg, 8));
+    // The stack of the kernel can't be switched by the trap return.
+    regs.next(8);
+    [
+        &mut tf.r8,
+        &mut tf.r9,
+        &mut tf.r10,
+        &mut tf.r11,
+        &mut tf.r12,
+        &mut tf.r13,
+        &mut tf.r14,
+        &mut tf.r15,
+        &mut tf.rip,
+    ]
+    .into_iter()
+    .for_each(|reg| regs.next_into(reg, 8));
+    regs.next_into(&mut tf.rflags, 4);
+}
+
+#[inline]
+pub(super) fn pc(tf: &TrapFrame) -> usize {
+    tf.rip
+}
+
+#[inline]
+pub(super) fn set_pc(tf: &mut TrapFrame, pc: usize) {
+    tf.rip = pc;
+}
+
+#[inline]
+pub(super) fn stack_pointer(tf: &TrapFrame) -> usize {
+    tf.rsp
+}
+
+#[inline]
+pub(super) fn set_step(tf: &mut TrapFrame, enable: bool) {
+    tf.set_single_step(enable);
+}
+
+/// The instruction cache is coherent with the stores.
+#[inline]
+pub(super) fn flush_icache(_addr: usize, _len: usize) {}
+
+/// Capture the registers of the caller to report a panic.
+#[inline(always)]
+pub(super) fn current_frame() -> (TrapFrame, usize) {
+    let mut tf = TrapFrame::new();
+    unsafe {
+        asm!(
+            "lea {pc}, [rip]",
+            "mov {sp}, rsp",
+            "mov {fp}, rbp",
+            pc = out(reg) tf.rip,
+            sp = out(reg) tf.rsp,
+            fp = out(reg) tf.rbp,
+        );
+    }
+    let sp = tf.rsp;
+    (tf, sp)
+}
@@ -2,5 +2,9 @@
 #![feature(naked_functions)]
 #![feature(used_with_arg)]
 
+pub mod coredump;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
+pub mod signal;
 pub mod trap;
 pub mod trapframe;
@@ -0,0 +1,130 @@
+use core::mem::size_of;
+
+use super::{SigFrameError, SigInfo, SigStack};
+use crate::trapframe::{FpState, TrapFrame};
+
+/// Magic number of the FP/SIMD record.
+const FPSIMD_MAGIC: u32 = 0x4650_8001;
+
+/// FP/SIMD record in the reserved area, the layout of the Linux `struct fpsimd_context`.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+pub struct FpsimdContext {
+    pub magic: u32,
+    pub size: u32,
+    pub fpsr: u32,
+    pub fpcr: u32,
+    pub vregs: [u128; 32],
+}
+
+/// Machine context, the layout of the Linux `struct sigcontext`.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+pub struct MContext {
+    pub fault_address: u64,
+    /// General registers `x0`-`x30`.
+    pub regs: [usize; 31],
+    pub sp: usize,
+    pub pc: usize,
+    pub pstate: usize,
+    /// The first record of the reserved area.
+    pub fpsimd: FpsimdContext,
+    /// The rest of the reserved area, starts with the terminator record.
+    _reserved: [u8; 4096 - size_of::<FpsimdContext>()],
+}
+
+/// User context, the layout of the Linux `ucontext_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UContext {
+    pub flags: usize,
+    pub link: usize,
+    pub stack: SigStack,
+    pub sigmask: u64,
+    _unused: [u8; 120],
+    pub mcontext: MContext,
+}
+
+/// Signal frame, the layout of the Linux `struct rt_sigframe` and the frame record.
+#[repr(C)]
+struct SigFrame {
+    info: SigInfo,
+    uc: UContext,
+    /// The frame pointer and the link register, makes the stack unwindable.
+    record: [usize; 2],
+}
+
+/// Push the signal frame below `user_stack` and redirect `tf` to the handler.
+///
+/// The handler is called as `handler(sig, &info, &uc)` and returns to `restorer`.
+///
+/// # Safety
+///
+/// The user stack below `user_stack` must be writable.
+pub unsafe fn push_signal_frame(
+    tf: &mut TrapFrame,
+    handler: usize,
+    sig: usize,
+    siginfo: &SigInfo,
+    restorer: usize,
+    user_stack: usize,
+) -> *mut UContext {
+    let frame = ((user_stack - size_of::<SigFrame>()) & !0xf) as *mut SigFrame;
+    frame.write(SigFrame {
+        info: *siginfo,
+        uc: UContext {
+            flags: 0,
+            link: 0,
+            stack: SigStack::default(),
+            sigmask: 0,
+            _unused: [0; 120],
+            mcontext: MContext {
+                fault_address: 0,
+                regs: tf.regs,
+                sp: tf.sp,
+                pc: tf.elr,
+                pstate: tf.spsr,
+                fpsimd: FpsimdContext {
+                    magic: FPSIMD_MAGIC,
+                    size: size_of::<FpsimdContext>() as _,
+                    fpsr: tf.fp.fpsr as _,
+                    fpcr: tf.fp.fpcr as _,
+                    vregs: tf.fp.q,
+                },
+                _reserved: [0; 4096 - size_of::<FpsimdContext>()],
+            },
+        },
+        record: [tf.regs[29], tf.regs[30]],
+    });
+    tf.elr = handler;
+    tf.sp = frame as usize;
+    tf.regs[0] = sig;
+    tf.regs[1] = &raw mut (*frame).info as usize;
+    tf.regs[2] = &raw mut (*frame).uc as usize;
+    tf.regs[29] = &raw mut (*frame).record as usize;
+    tf.regs[30] = restorer;
+    &raw mut (*frame).uc
+}
+
+/// Restore the context saved by [push_signal_frame] at the `rt_sigreturn` syscall.
+///
+/// # Safety
+///
+/// The stack pointer of `tf` must point to the signal frame.
+pub unsafe fn restore_signal_frame(tf: &mut TrapFrame) -> Result<*const UContext, SigFrameError> {
+    let frame = tf.sp as *const SigFrame;
+    let mcontext = &(*frame).uc.mcontext;
+    tf.regs = mcontext.regs;
+    tf.sp = mcontext.sp;
+    tf.elr = mcontext.pc;
+    // Only the condition flags can be changed, stay at EL0t.
+    tf.spsr = mcontext.pstate & 0xf000_0000;
+    if mcontext.fpsimd.magic == FPSIMD_MAGIC {
+        tf.fp = FpState {
+            q: mcontext.fpsimd.vregs,
+            fpcr: mcontext.fpsimd.fpcr as _,
+            fpsr: mcontext.fpsimd.fpsr as _,
+        };
+    }
+    Ok(&raw const (*frame).uc)
+}
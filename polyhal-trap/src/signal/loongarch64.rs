@@ -0,0 +1,134 @@
+use core::mem::size_of;
+
+use super::{SigFrameError, SigInfo, SigStack};
+use crate::trapframe::{FpState, TrapFrame};
+
+/// Magic number of the FPU context record.
+const FPU_CTX_MAGIC: u32 = 0x4650_5501;
+
+/// Header of the extended context record, the layout of the Linux `struct sctx_info`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SctxInfo {
+    pub magic: u32,
+    pub size: u32,
+    _padding: u64,
+}
+
+/// FPU context record, the layout of the Linux `struct fpu_context`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FpuContext {
+    pub regs: [u64; 32],
+    pub fcc: u64,
+    pub fcsr: u32,
+}
+
+/// Machine context, the layout of the Linux `struct sigcontext`
+/// followed by the FPU context record and the terminator record.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+pub struct MContext {
+    pub pc: usize,
+    /// General registers `r0`-`r31`.
+    pub regs: [usize; 32],
+    pub flags: u32,
+    pub fpu_info: SctxInfo,
+    pub fpu: FpuContext,
+    _end: SctxInfo,
+}
+
+/// User context, the layout of the Linux `ucontext_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UContext {
+    pub flags: usize,
+    pub link: usize,
+    pub stack: SigStack,
+    pub sigmask: u64,
+    _unused: [u8; 120],
+    pub mcontext: MContext,
+}
+
+/// Signal frame, the layout of the Linux `struct rt_sigframe`.
+#[repr(C)]
+struct SigFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+/// Push the signal frame below `user_stack` and redirect `tf` to the handler.
+///
+/// The handler is called as `handler(sig, &info, &uc)` and returns to `restorer`.
+///
+/// # Safety
+///
+/// The user stack below `user_stack` must be writable.
+pub unsafe fn push_signal_frame(
+    tf: &mut TrapFrame,
+    handler: usize,
+    sig: usize,
+    siginfo: &SigInfo,
+    restorer: usize,
+    user_stack: usize,
+) -> *mut UContext {
+    let frame = ((user_stack - size_of::<SigFrame>()) & !0xf) as *mut SigFrame;
+    let fp = &tf.fp;
+    frame.write(SigFrame {
+        info: *siginfo,
+        uc: UContext {
+            flags: 0,
+            link: 0,
+            stack: SigStack::default(),
+            sigmask: 0,
+            _unused: [0; 120],
+            mcontext: MContext {
+                pc: tf.era,
+                regs: tf.regs,
+                flags: 0,
+                fpu_info: SctxInfo {
+                    magic: FPU_CTX_MAGIC,
+                    size: (size_of::<SctxInfo>() + size_of::<FpuContext>()) as _,
+                    _padding: 0,
+                },
+                fpu: FpuContext {
+                    regs: fp.fp,
+                    fcc: u64::from_le_bytes(fp.fcc),
+                    fcsr: fp.fcsr as _,
+                },
+                _end: SctxInfo {
+                    magic: 0,
+                    size: 0,
+                    _padding: 0,
+                },
+            },
+        },
+    });
+    tf.era = handler;
+    tf.regs[1] = restorer;
+    tf.regs[3] = frame as usize;
+    tf.regs[4] = sig;
+    tf.regs[5] = &raw mut (*frame).info as usize;
+    tf.regs[6] = &raw mut (*frame).uc as usize;
+    &raw mut (*frame).uc
+}
+
+/// Restore the context saved by [push_signal_frame] at the `rt_sigreturn` syscall.
+///
+/// # Safety
+///
+/// The stack pointer of `tf` must point to the signal frame.
+pub unsafe fn restore_signal_frame(tf: &mut TrapFrame) -> Result<*const UContext, SigFrameError> {
+    let frame = tf.regs[3] as *const SigFrame;
+    let mcontext = &(*frame).uc.mcontext;
+    tf.era = mcontext.pc;
+    tf.regs[1..].copy_from_slice(&mcontext.regs[1..]);
+    if mcontext.fpu_info.magic == FPU_CTX_MAGIC {
+        tf.fp = FpState {
+            fp: mcontext.fpu.regs,
+            fcc: mcontext.fpu.fcc.to_le_bytes(),
+            fcsr: mcontext.fpu.fcsr as _,
+        };
+    }
+    Ok(&raw const (*frame).uc)
+}
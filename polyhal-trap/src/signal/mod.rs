@@ -0,0 +1,82 @@
+//! Signal frame module.
+//!
+//! Build the signal frame on the user stack in the layout of the Linux
+//! `rt_sigframe` of each architecture, so the user libc can access the
+//! `siginfo_t` and `ucontext_t` given to the handler.
+//!
+//! [push_signal_frame] saves the general registers and the floating-point
+//! state of the [TrapFrame] into the `ucontext_t`, then redirects the
+//! [TrapFrame] to the handler which will return to the `restorer`.
+//! The `restorer` should call the `rt_sigreturn` syscall, then
+//! [restore_signal_frame] loads the saved state back.
+//!
+//! The signal mask and the alternate stack are managed by the kernel,
+//! they can be accessed through the returned [UContext].
+//!
+//! ```ignore
+//! use polyhal_trap::signal::{push_signal_frame, restore_signal_frame, SigInfo};
+//!
+//! // Deliver the signal on the current user stack.
+//! let sp = tf[TrapFrameArgs::SP];
+//! let uc = unsafe { push_signal_frame(tf, handler, sig, &SigInfo::new(sig), restorer, sp) };
+//! unsafe { (*uc).sigmask = blocked };
+//!
+//! // Handle rt_sigreturn, a bad frame kills the task by SIGSEGV.
+//! match unsafe { restore_signal_frame(tf) } {
+//!     Ok(uc) => blocked = unsafe { (*uc).sigmask },
+//!     Err(_) => force_sigsegv(),
+//! }
+//! ```
+
+#[cfg(doc)]
+use crate::trapframe::TrapFrame;
+
+polyhal_macro::define_arch_mods!();
+
+/// Signal information, the layout of the Linux `siginfo_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigInfo {
+    /// Signal number
+    pub signo: i32,
+    /// Error number
+    pub errno: i32,
+    /// Signal code
+    pub code: i32,
+    _pad: i32,
+    /// Signal specific fields, like `si_pid`, `si_uid` and `si_addr`.
+    pub fields: [u64; 14],
+}
+
+impl SigInfo {
+    /// Create a signal information with the signal number.
+    pub const fn new(signo: i32) -> Self {
+        Self {
+            signo,
+            errno: 0,
+            code: 0,
+            _pad: 0,
+            fields: [0; 14],
+        }
+    }
+}
+
+/// Error of [restore_signal_frame], the kernel should send `SIGSEGV` to the task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigFrameError {
+    /// The floating-point state at the address isn't in the user space
+    /// or not aligned.
+    BadFpState(usize),
+}
+
+/// Signal stack, the layout of the Linux `stack_t`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SigStack {
+    /// Base address of the stack
+    pub sp: usize,
+    /// Flags of the stack
+    pub flags: i32,
+    /// Size of the stack
+    pub size: usize,
+}
@@ -0,0 +1,101 @@
+use core::mem::size_of;
+
+use riscv::register::sstatus::FS;
+
+use super::{SigFrameError, SigInfo, SigStack};
+use crate::trapframe::{FpState, TrapFrame};
+
+/// Machine context, the layout of the Linux `struct sigcontext`.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+pub struct MContext {
+    /// `pc` and the general registers `x1`-`x31`.
+    pub regs: [usize; 32],
+    /// Floating-point registers `f0`-`f31`.
+    pub fpregs: [u64; 32],
+    /// Floating-point control and status register.
+    pub fcsr: u32,
+    /// Reserved for the Q extension state.
+    _reserved: [u32; 67],
+}
+
+/// User context, the layout of the Linux `ucontext_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UContext {
+    pub flags: usize,
+    pub link: usize,
+    pub stack: SigStack,
+    pub sigmask: u64,
+    _unused: [u8; 120],
+    pub mcontext: MContext,
+}
+
+/// Signal frame, the layout of the Linux `struct rt_sigframe`.
+#[repr(C)]
+struct SigFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+/// Push the signal frame below `user_stack` and redirect `tf` to the handler.
+///
+/// The handler is called as `handler(sig, &info, &uc)` and returns to `restorer`.
+///
+/// # Safety
+///
+/// The user stack below `user_stack` must be writable.
+pub unsafe fn push_signal_frame(
+    tf: &mut TrapFrame,
+    handler: usize,
+    sig: usize,
+    siginfo: &SigInfo,
+    restorer: usize,
+    user_stack: usize,
+) -> *mut UContext {
+    let frame = ((user_stack - size_of::<SigFrame>()) & !0xf) as *mut SigFrame;
+    let mut mcontext = MContext {
+        regs: tf.x,
+        fpregs: tf.fp.f,
+        fcsr: tf.fp.fcsr as _,
+        _reserved: [0; 67],
+    };
+    mcontext.regs[0] = tf.sepc;
+    frame.write(SigFrame {
+        info: *siginfo,
+        uc: UContext {
+            flags: 0,
+            link: 0,
+            stack: SigStack::default(),
+            sigmask: 0,
+            _unused: [0; 120],
+            mcontext,
+        },
+    });
+    tf.sepc = handler;
+    tf.x[1] = restorer;
+    tf.x[2] = frame as usize;
+    tf.x[10] = sig;
+    tf.x[11] = &raw mut (*frame).info as usize;
+    tf.x[12] = &raw mut (*frame).uc as usize;
+    &raw mut (*frame).uc
+}
+
+/// Restore the context saved by [push_signal_frame] at the `rt_sigreturn` syscall.
+///
+/// # Safety
+///
+/// The stack pointer of `tf` must point to the signal frame.
+pub unsafe fn restore_signal_frame(tf: &mut TrapFrame) -> Result<*const UContext, SigFrameError> {
+    let frame = tf.x[2] as *const SigFrame;
+    let mcontext = &(*frame).uc.mcontext;
+    tf.sepc = mcontext.regs[0];
+    tf.x[1..].copy_from_slice(&mcontext.regs[1..]);
+    tf.fp = FpState {
+        f: mcontext.fpregs,
+        fcsr: mcontext.fcsr as _,
+    };
+    // Load the restored state at the next return.
+    tf.sstatus.set_fs(FS::Clean);
+    Ok(&raw const (*frame).uc)
+}
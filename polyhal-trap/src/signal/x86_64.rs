@@ -0,0 +1,238 @@
+use core::mem::size_of;
+
+use polyhal::xsave::{xfeature_mask, xsave_enabled, xstate_size};
+
+use super::{SigFrameError, SigInfo, SigStack};
+use crate::trapframe::{FxsaveArea, TrapFrame};
+
+/// The red zone below the stack pointer which can't be overwritten.
+const RED_ZONE_SIZE: usize = 128;
+
+/// The flags of `rflags` which can be restored by the user task,
+/// AC, OF, DF, TF, SF, ZF, AF, PF, CF and RF.
+const FIX_RFLAGS: usize = 0x50dd5;
+
+/// Direction flag and trap flag, cleared when entering the handler.
+const RFLAGS_DF_TF: usize = 0x500;
+
+/// The end of the user space, the lower half of the canonical addresses.
+const USER_SPACE_END: usize = 1 << 47;
+
+/// Magic number in [FpxSwBytes], the save area is in the `XSAVE` format.
+const FP_XSTATE_MAGIC1: u32 = 0x4650_5853;
+
+/// Magic number after the `XSAVE` area.
+const FP_XSTATE_MAGIC2: u32 = 0x4650_5845;
+
+/// Offset of [FpxSwBytes] in the legacy region, the bytes are ignored by the cpu.
+const SW_BYTES_OFFSET: usize = 464;
+
+/// Description of the `XSAVE` area, the layout of the Linux `struct _fpx_sw_bytes`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FpxSwBytes {
+    magic1: u32,
+    /// Size of the area including [FP_XSTATE_MAGIC2].
+    extended_size: u32,
+    /// Features saved in the area.
+    xfeatures: u64,
+    /// Size of the `XSAVE` area.
+    xstate_size: u32,
+    _padding: [u32; 7],
+}
+
+/// Machine context, the layout of the Linux `struct sigcontext`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MContext {
+    pub r8: usize,
+    pub r9: usize,
+    pub r10: usize,
+    pub r11: usize,
+    pub r12: usize,
+    pub r13: usize,
+    pub r14: usize,
+    pub r15: usize,
+    pub rdi: usize,
+    pub rsi: usize,
+    pub rbp: usize,
+    pub rbx: usize,
+    pub rdx: usize,
+    pub rax: usize,
+    pub rcx: usize,
+    pub rsp: usize,
+    pub rip: usize,
+    pub rflags: usize,
+    pub cs: u16,
+    pub gs: u16,
+    pub fs: u16,
+    pub ss: u16,
+    pub err: usize,
+    pub trapno: usize,
+    pub oldmask: usize,
+    pub cr2: usize,
+    /// Address of the `FXSAVE` or `XSAVE` area, 0 if not saved.
+    pub fpstate: usize,
+    _reserved: [usize; 8],
+}
+
+/// User context, the layout of the Linux `ucontext_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UContext {
+    pub flags: usize,
+    pub link: usize,
+    pub stack: SigStack,
+    pub mcontext: MContext,
+    pub sigmask: u64,
+}
+
+/// Signal frame, the layout of the Linux `struct rt_sigframe`.
+#[repr(C)]
+struct SigFrame {
+    /// Return address of the handler.
+    pretcode: usize,
+    uc: UContext,
+    info: SigInfo,
+}
+
+/// Push the signal frame below `user_stack` and redirect `tf` to the handler.
+///
+/// The handler is called as `handler(sig, &info, &uc)` and returns to `restorer`.
+/// The extended state is saved in the `XSAVE` layout followed by the magic
+/// number if the cpu supports `XSAVE`, otherwise in the `FXSAVE` layout.
+///
+/// # Safety
+///
+/// The user stack below `user_stack` must be writable.
+pub unsafe fn push_signal_frame(
+    tf: &mut TrapFrame,
+    handler: usize,
+    sig: usize,
+    siginfo: &SigInfo,
+    restorer: usize,
+    user_stack: usize,
+) -> *mut UContext {
+    let xstate_size = xstate_size();
+    let extended_size = xstate_size + size_of::<u32>();
+    let fpstate = (user_stack - RED_ZONE_SIZE - extended_size) & !0x3f;
+    tf.xstate.copy_to(fpstate as *mut u8);
+    if xsave_enabled() {
+        ((fpstate + SW_BYTES_OFFSET) as *mut FpxSwBytes).write(FpxSwBytes {
+            magic1: FP_XSTATE_MAGIC1,
+            extended_size: extended_size as _,
+            xfeatures: xfeature_mask(),
+            xstate_size: xstate_size as _,
+            _padding: [0; 7],
+        });
+        ((fpstate + xstate_size) as *mut u32).write(FP_XSTATE_MAGIC2);
+    }
+    // The stack is aligned to 16 bytes before the return address is pushed.
+    let frame = (((fpstate - size_of::<SigFrame>()) & !0xf) - 8) as *mut SigFrame;
+    frame.write(SigFrame {
+        pretcode: restorer,
+        uc: UContext {
+            flags: 0,
+            link: 0,
+            stack: SigStack::default(),
+            mcontext: MContext {
+                r8: tf.r8,
+                r9: tf.r9,
+                r10: tf.r10,
+                r11: tf.r11,
+                r12: tf.r12,
+                r13: tf.r13,
+                r14: tf.r14,
+                r15: tf.r15,
+                rdi: tf.rdi,
+                rsi: tf.rsi,
+                rbp: tf.rbp,
+                rbx: tf.rbx,
+                rdx: tf.rdx,
+                rax: tf.rax,
+                rcx: tf.rcx,
+                rsp: tf.rsp,
+                rip: tf.rip,
+                rflags: tf.rflags,
+                cs: tf.cs as _,
+                gs: 0,
+                fs: 0,
+                ss: tf.ss as _,
+                err: tf.error_code,
+                trapno: tf.vector,
+                oldmask: 0,
+                cr2: 0,
+                fpstate,
+                _reserved: [0; 8],
+            },
+            sigmask: 0,
+        },
+        info: *siginfo,
+    });
+    tf.rip = handler;
+    tf.rsp = frame as usize;
+    tf.rdi = sig;
+    tf.rsi = &raw mut (*frame).info as usize;
+    tf.rdx = &raw mut (*frame).uc as usize;
+    tf.rax = 0;
+    tf.rflags &= !RFLAGS_DF_TF;
+    &raw mut (*frame).uc
+}
+
+/// Check whether `[addr, addr + size)` is in the user space.
+fn in_user_space(addr: usize, size: usize) -> bool {
+    addr.checked_add(size)
+        .is_some_and(|end| end <= USER_SPACE_END)
+}
+
+/// Restore the context saved by [push_signal_frame] at the `rt_sigreturn` syscall.
+///
+/// The whole `XSAVE` area is restored if its magic numbers are found,
+/// otherwise only the legacy region.
+/// Return [SigFrameError::BadFpState] without changing `tf` if the
+/// floating-point state isn't in the user space or aligned to 64 bytes.
+///
+/// # Safety
+///
+/// The stack pointer of `tf` must point above the return address of the signal frame.
+pub unsafe fn restore_signal_frame(tf: &mut TrapFrame) -> Result<*const UContext, SigFrameError> {
+    // The return address was popped by the handler.
+    let frame = (tf.rsp - 8) as *const SigFrame;
+    let mcontext = &(*frame).uc.mcontext;
+    let fpstate = mcontext.fpstate;
+    if fpstate != 0 {
+        if fpstate % 64 != 0 || !in_user_space(fpstate, size_of::<FxsaveArea>()) {
+            return Err(SigFrameError::BadFpState(fpstate));
+        }
+        let sw = ((fpstate + SW_BYTES_OFFSET) as *const FpxSwBytes).read();
+        let xstate_size = xstate_size();
+        let xsave = xsave_enabled()
+            && sw.magic1 == FP_XSTATE_MAGIC1
+            && sw.xstate_size as usize == xstate_size
+            && in_user_space(fpstate, xstate_size + size_of::<u32>())
+            && ((fpstate + xstate_size) as *const u32).read() == FP_XSTATE_MAGIC2;
+        match xsave {
+            true => tf.xstate.copy_from(fpstate as *const u8),
+            false => tf.xstate.set_legacy(&*(fpstate as *const FxsaveArea)),
+        }
+    }
+    tf.r8 = mcontext.r8;
+    tf.r9 = mcontext.r9;
+    tf.r10 = mcontext.r10;
+    tf.r11 = mcontext.r11;
+    tf.r12 = mcontext.r12;
+    tf.r13 = mcontext.r13;
+    tf.r14 = mcontext.r14;
+    tf.r15 = mcontext.r15;
+    tf.rdi = mcontext.rdi;
+    tf.rsi = mcontext.rsi;
+    tf.rbp = mcontext.rbp;
+    tf.rbx = mcontext.rbx;
+    tf.rdx = mcontext.rdx;
+    tf.rax = mcontext.rax;
+    tf.rcx = mcontext.rcx;
+    tf.rsp = mcontext.rsp;
+    tf.rip = mcontext.rip;
+    tf.rflags = (tf.rflags & !FIX_RFLAGS) | (mcontext.rflags & FIX_RFLAGS);
+    Ok(&raw const (*frame).uc)
+}
@@ -1,14 +1,19 @@
 use core::arch::{global_asm, naked_asm};
 
-use aarch64_cpu::registers::{Writeable, ESR_EL1, FAR_EL1, VBAR_EL1};
-use tock_registers::interfaces::Readable;
+use aarch64_cpu::registers::{Writeable, CPACR_EL1, ESR_EL1, FAR_EL1, VBAR_EL1};
+use tock_registers::interfaces::{ReadWriteable, Readable};
 
-use crate::trapframe::TrapFrame;
-use polyhal::irq::{get_irq, TIMER_IRQ_NUM};
+use crate::trapframe::{TrapFrame, SPSR_SS};
+use polyhal::irq::{
+    count_irq, get_irq, take_ipi, IrqSource, IPI_IRQ_NUM, SPURIOUS_IRQ_NUM, TIMER_IRQ_NUM,
+};
 
 use super::{EscapeReason, TrapType};
 
-global_asm!(include_str!("aarch64/trap.S"));
+global_asm!(
+    include_str!("aarch64/trap.S"),
+    trapframe_size = const crate::trapframe::TRAPFRAME_SIZE,
+);
 
 #[repr(u8)]
 #[derive(Debug, PartialEq)]
@@ -39,12 +44,23 @@ fn handle_exception(tf: &mut TrapFrame, kind: TrapKind, source: TrapSource) -> T
                 irq.ack();
                 TrapType::Timer
             }
+            IPI_IRQ_NUM => {
+                irq.ack();
+                TrapType::Ipi(take_ipi())
+            }
+            // Not acknowledged, there is nothing to complete.
+            SPURIOUS_IRQ_NUM => {
+                count_irq(IrqSource::Spurious);
+                return TrapType::Unknown;
+            }
             _ => TrapType::Irq(irq),
         };
+        super::count_interrupt(trap_type);
         unsafe { super::_interrupt_for_arch(tf, trap_type, 0) };
         return trap_type;
     }
     if kind != TrapKind::Synchronous {
+        super::dump_backtrace(tf);
         panic!(
             "Invalid exception {:?} from {:?}:\n{:#x?}",
             kind, source, tf
@@ -56,7 +72,19 @@ fn handle_exception(tf: &mut TrapFrame, kind: TrapKind, source: TrapSource) -> T
             tf.elr += 4;
             TrapType::Breakpoint
         }
-        Some(ESR_EL1::EC::Value::SVC64) => TrapType::SysCall,
+        Some(ESR_EL1::EC::Value::SVC64) => {
+            tf.orig_x0 = tf.regs[0];
+            TrapType::SysCall
+        }
+        // The step of the kernel by the GDB stub.
+        Some(ESR_EL1::EC::Value::SoftwareStepCurrentEL) => TrapType::SingleStep,
+        Some(ESR_EL1::EC::Value::SoftwareStepLowerEL) => {
+            // The step is completed, step the next instruction after returning.
+            tf.spsr |= SPSR_SS;
+            TrapType::SingleStep
+        }
+        Some(ESR_EL1::EC::Value::BreakpointLowerEL) => TrapType::Breakpoint,
+        Some(ESR_EL1::EC::Value::WatchpointLowerEL) => TrapType::Watchpoint(FAR_EL1.get() as _),
         Some(ESR_EL1::EC::Value::DataAbortLowerEL)
         | Some(ESR_EL1::EC::Value::InstrAbortLowerEL) => {
             let iss = esr.read(ESR_EL1::ISS);
@@ -81,6 +109,7 @@ fn handle_exception(tf: &mut TrapFrame, kind: TrapKind, source: TrapSource) -> T
             TrapType::InstructionPageFault(FAR_EL1.get() as _)
         }
         _ => {
+            super::dump_backtrace(tf);
             panic!(
                 "Unhandled synchronous exception @ {:#x}: ESR={:#x} (EC {:#08b}, ISS {:#x})",
                 tf.elr,
@@ -90,6 +119,10 @@ fn handle_exception(tf: &mut TrapFrame, kind: TrapKind, source: TrapSource) -> T
             );
         }
     };
+    #[cfg(feature = "gdbstub")]
+    if crate::gdbstub::handle_trap(tf, trap_type) {
+        return trap_type;
+    }
     unsafe { super::_interrupt_for_arch(tf, trap_type, 0) };
     trap_type
 }
@@ -146,7 +179,37 @@ extern "C" fn user_restore(context: *mut TrapFrame) -> TrapKind {
     }
 }
 
+/// Run the user task until it traps, return the trap type.
+///
+/// The trap has been passed to the interrupt handler of the kernel.
+pub fn run_user_task_trap(cx: &mut TrapFrame) -> TrapType {
+    // Trap the FP/SIMD access of the user task, load its state at the first access.
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapEl0);
+    polyhal::breakpoint::set_single_step(cx.single_step);
+    loop {
+        let trap_kind = user_restore(cx);
+        if CPACR_EL1.matches_all(CPACR_EL1::FPEN::TrapNothing) {
+            cx.fp.save();
+        }
+        if trap_kind == TrapKind::Synchronous
+            && ESR_EL1.matches_all(ESR_EL1::EC::TrappedFP)
+        {
+            cx.fp.restore();
+            CPACR_EL1.modify(CPACR_EL1::FPEN::TrapNothing);
+            continue;
+        }
+        return handle_exception(cx, trap_kind, TrapSource::LowerAArch64);
+    }
+}
+
+/// Return EscapeReson related to interrupt type.
 pub fn run_user_task(cx: &mut TrapFrame) -> EscapeReason {
-    let trap_kind = user_restore(cx);
-    handle_exception(cx, trap_kind, TrapSource::LowerAArch64).into()
+    run_user_task_trap(cx).into()
+}
+
+/// Run the user task forever, the traps are only passed to the interrupt handler.
+pub fn run_user_task_forever(cx: &mut TrapFrame) -> ! {
+    loop {
+        run_user_task_trap(cx);
+    }
 }
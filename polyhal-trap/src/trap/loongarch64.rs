@@ -9,9 +9,12 @@ use loongArch64::register::estat::{self, Exception, Trap};
 use loongArch64::register::{
     badv, ecfg, eentry, prmd, pwch, pwcl, stlbps, ticlr, tlbidx, tlbrehi, tlbrentry,
 };
-use polyhal::irq::TIMER_IRQ;
+use polyhal::irq::{count_irq, get_irq, take_ipi, IrqSource, EXT_IRQ, IPI_IRQ, TIMER_IRQ};
 use unaligned::emulate_load_store_insn;
 
+/// The exception code of the watchpoint exceptions, not decoded by [estat::Estat::cause].
+const ECODE_WATCH: usize = 0x13;
+
 #[naked]
 pub unsafe extern "C" fn user_vec() {
     naked_asm!(
@@ -88,9 +91,36 @@ pub fn disable_irq() {
     prmd::set_pie(false);
 }
 
-pub fn run_user_task(cx: &mut TrapFrame) -> EscapeReason {
+/// Run the user task until it traps, return the trap type.
+///
+/// The trap has been passed to the interrupt handler of the kernel.
+pub fn run_user_task_trap(cx: &mut TrapFrame) -> TrapType {
+    if cx.single_step {
+        polyhal::breakpoint::enable_single_step();
+    }
+    // Restore the state pending from the lazy context switch and enable the
+    // floating-point unit, or restoring the user state raises an exception.
+    #[cfg(feature = "fp_lazy")]
+    polyhal::kcontext::handle_fp_trap();
+    cx.fp.restore();
     user_restore(cx);
-    loongarch64_trap_handler(cx).into()
+    cx.fp.save();
+    if cx.single_step {
+        polyhal::breakpoint::disable_single_step();
+    }
+    loongarch64_trap_handler(cx)
+}
+
+/// Return EscapeReson related to interrupt type.
+pub fn run_user_task(cx: &mut TrapFrame) -> EscapeReason {
+    run_user_task_trap(cx).into()
+}
+
+/// Run the user task forever, the traps are only passed to the interrupt handler.
+pub fn run_user_task_forever(cx: &mut TrapFrame) -> ! {
+    loop {
+        run_user_task_trap(cx);
+    }
 }
 
 #[naked]
@@ -111,7 +141,16 @@ pub unsafe extern "C" fn trap_vector_base() {
             // save the registers.
 
             SAVE_REGS
-        
+
+            // Switch to the emergency stack if the kernel stack was overflowed.
+            move    $a0, $sp
+            bl      kernel_stack_check
+            beqz    $a0, 1f
+            move    $a1, $sp
+            move    $sp, $a0
+            move    $a0, $a1
+            bl      kernel_stack_overflow
+        1:
             move    $a0, $sp
             bl      {trap_handler}
         
@@ -208,10 +247,29 @@ fn loongarch64_trap_handler(tf: &mut TrapFrame) -> TrapType {
                     ticlr::clear_timer_interrupt();
                     TrapType::Timer
                 }
+                EXT_IRQ => get_irq().map_or_else(
+                    || {
+                        count_irq(IrqSource::Spurious);
+                        TrapType::Unknown
+                    },
+                    TrapType::Irq,
+                ),
+                IPI_IRQ => TrapType::Ipi(take_ipi()),
                 _ => panic!("unknown interrupt: {}", irq_num),
             }
         }
-        Trap::Exception(Exception::Syscall) => TrapType::SysCall,
+        Trap::Exception(Exception::Syscall) => {
+            tf.orig_a0 = tf.regs[4];
+            tf.orig_era = tf.era;
+            TrapType::SysCall
+        }
+        // The first floating-point instruction after a lazy context switch.
+        #[cfg(feature = "fp_lazy")]
+        Trap::Exception(Exception::FloatingPointUnavailable)
+            if polyhal::kcontext::handle_fp_trap() =>
+        {
+            return TrapType::Unknown
+        }
         Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::PageModifyFault) => {
             TrapType::StorePageFault(badv::read().vaddr())
@@ -225,9 +283,13 @@ fn loongarch64_trap_handler(tf: &mut TrapFrame) -> TrapType {
         | Trap::Exception(Exception::PageNonReadableFault) => {
             TrapType::LoadPageFault(badv::read().vaddr())
         }
+        Trap::Unknown if estat.ecode() == ECODE_WATCH => {
+            polyhal::breakpoint::take_hit().map_or(TrapType::Unknown, Into::into)
+        }
         Trap::MachineError(_) => todo!(),
         Trap::Unknown => todo!(),
         _ => {
+            super::dump_backtrace(tf);
             panic!(
                 "Unhandled trap {:?} @ {:#x} BADV: {:#x}:\n{:#x?}",
                 estat.cause(),
@@ -238,6 +300,11 @@ fn loongarch64_trap_handler(tf: &mut TrapFrame) -> TrapType {
         }
     };
     // info!("return to addr: {:#x}", tf.era);
+    super::count_interrupt(trap_type);
+    #[cfg(feature = "gdbstub")]
+    if crate::gdbstub::handle_trap(tf, trap_type) {
+        return trap_type;
+    }
     unsafe { super::_interrupt_for_arch(tf, trap_type, 0) };
     trap_type
 }
@@ -3,7 +3,12 @@
 //!
 
 use super::trapframe::TrapFrame;
-use polyhal::{ctor::CtorType, irq::IRQVector, ph_ctor};
+use polyhal::{
+    breakpoint::DebugHit,
+    ctor::CtorType,
+    irq::{count_irq, IRQVector, IrqSource},
+    ph_ctor,
+};
 
 polyhal_macro::define_arch_mods!();
 
@@ -19,30 +24,121 @@ pub enum TrapType {
     InstructionPageFault(usize),
     IllegalInstruction(usize),
     Irq(IRQVector),
+    /// An IPI was received, contains the bitmap of the pending kinds, see `polyhal::irq::send_ipi`.
+    Ipi(usize),
+    /// A single step of the user task was completed.
+    SingleStep,
+    /// A hardware watchpoint was hit, contains the address of the watched data.
+    ///
+    /// Like the hardware breakpoints, the access is executed again after
+    /// returning except on x86_64, clear the watchpoint to continue.
+    Watchpoint(usize),
 }
 
+impl From<DebugHit> for TrapType {
+    fn from(value: DebugHit) -> Self {
+        match value {
+            DebugHit::Breakpoint => TrapType::Breakpoint,
+            DebugHit::Watchpoint(addr) => TrapType::Watchpoint(addr),
+            DebugHit::SingleStep => TrapType::SingleStep,
+        }
+    }
+}
+
+/// The reason why the user task returned to the kernel, see `run_user_task`.
+///
+/// Use `run_user_task_trap` to get the full [TrapType].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EscapeReason {
     NoReason,
     IRQ,
     Timer,
     SysCall,
+    /// A load, store or instruction page fault, contains the faulting address.
+    PageFault(usize),
+    /// An illegal instruction, a breakpoint, a single step or a watchpoint.
+    Exception,
 }
 
-// TODO: Add more trap types as needed
 impl From<TrapType> for EscapeReason {
     fn from(value: TrapType) -> Self {
         match value {
             TrapType::SysCall => EscapeReason::SysCall,
             TrapType::Timer => EscapeReason::Timer,
-            TrapType::Irq(_) => EscapeReason::IRQ,
-            _ => EscapeReason::NoReason,
+            TrapType::Irq(_) | TrapType::Ipi(_) | TrapType::SupervisorExternal => EscapeReason::IRQ,
+            TrapType::StorePageFault(addr)
+            | TrapType::LoadPageFault(addr)
+            | TrapType::InstructionPageFault(addr) => EscapeReason::PageFault(addr),
+            TrapType::IllegalInstruction(_)
+            | TrapType::Breakpoint
+            | TrapType::SingleStep
+            | TrapType::Watchpoint(_) => EscapeReason::Exception,
+            TrapType::Unknown => EscapeReason::NoReason,
         }
     }
 }
 
+/// Count the interrupt in the statistics of the current CPU before it's dispatched.
+#[inline]
+pub(crate) fn count_interrupt(trap_type: TrapType) {
+    let source = match trap_type {
+        TrapType::Irq(irq) => IrqSource::Irq(irq.irq_num()),
+        TrapType::Timer => IrqSource::Timer,
+        TrapType::Ipi(_) => IrqSource::Ipi,
+        // The external interrupt was claimed by another hart.
+        TrapType::SupervisorExternal => IrqSource::Spurious,
+        _ => return,
+    };
+    count_irq(source);
+}
+
 extern "Rust" {
     pub(crate) fn _interrupt_for_arch(ctx: &mut TrapFrame, trap_type: TrapType, token: usize);
 }
 
 ph_ctor!(TRAP_INIT, CtorType::Cpu, init);
+
+/// Print the backtrace of the trap context before panicking in the trap handler.
+#[inline]
+pub(crate) fn dump_backtrace(_ctx: &TrapFrame) {
+    #[cfg(feature = "backtrace")]
+    polyhal::println!("{}", _ctx.backtrace());
+}
+
+/// The size of the emergency stack used to report the kernel stack overflow.
+#[cfg(not(target_arch = "x86_64"))]
+const EMERGENCY_STACK_SIZE: usize = 0x4000;
+
+/// Check whether the kernel stack pointer falls into the guard region.
+///
+/// This is called in the kernel trap entry after saving the context.
+/// Return the top of the emergency stack if the stack was overflowed, 0 otherwise.
+/// Only one core is allowed to use the emergency stack, others will spin here.
+#[cfg(not(target_arch = "x86_64"))]
+#[no_mangle]
+extern "C" fn kernel_stack_check(sp: usize) -> usize {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    // The overflowed stack can't be used to run the handler.
+    static mut EMERGENCY_STACK: [u128; EMERGENCY_STACK_SIZE / 16] = [0; EMERGENCY_STACK_SIZE / 16];
+    static EMERGENCY_USED: AtomicBool = AtomicBool::new(false);
+
+    if polyhal::stack::stack_guard_hit(sp).is_none() {
+        return 0;
+    }
+    while EMERGENCY_USED.swap(true, Ordering::AcqRel) {
+        core::hint::spin_loop();
+    }
+    (&raw mut EMERGENCY_STACK) as usize + EMERGENCY_STACK_SIZE
+}
+
+/// Report the kernel stack overflow, run on the emergency stack.
+#[cfg(not(target_arch = "x86_64"))]
+#[no_mangle]
+extern "C" fn kernel_stack_overflow(ctx: &TrapFrame) -> ! {
+    let sp = ctx as *const TrapFrame as usize;
+    let bottom = polyhal::stack::stack_guard_hit(sp).unwrap_or(0);
+    panic!(
+        "Kernel stack overflow, sp={:#x} stack_bottom={:#x}:\n{:#x?}",
+        sp, bottom, ctx
+    );
+}
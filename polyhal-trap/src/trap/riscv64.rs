@@ -1,10 +1,13 @@
 #[macro_use]
 mod macros;
+#[cfg(feature = "misaligned")]
+mod misaligned;
 
 use super::{EscapeReason, TrapType};
 use crate::trapframe::TrapFrame;
 use core::arch::naked_asm;
 use polyhal::consts::VIRT_ADDR_START;
+use polyhal::irq::{get_irq, take_ipi};
 use riscv::{
     interrupt::{Exception, Interrupt},
     register::{
@@ -35,17 +38,25 @@ fn kernel_callback(context: &mut TrapFrame) -> TrapType {
 
     let trap_type = match scause.cause().try_into().unwrap() {
         // 中断异常
-        Trap::Exception(Exception::Breakpoint) => {
-            context.sepc += 2;
-            TrapType::Breakpoint
-        }
+        Trap::Exception(Exception::Breakpoint) => match polyhal::breakpoint::take_hit() {
+            Some(hit) => hit.into(),
+            None => {
+                context.sepc += 2;
+                TrapType::Breakpoint
+            }
+        },
         Trap::Exception(Exception::LoadFault) => {
             if stval > VIRT_ADDR_START {
+                super::dump_backtrace(context);
                 panic!("kernel error: {:#x}", stval);
             }
             TrapType::Unknown
         }
-        Trap::Exception(Exception::UserEnvCall) => TrapType::SysCall,
+        Trap::Exception(Exception::UserEnvCall) => {
+            context.orig_a0 = context.x[10];
+            context.orig_sepc = context.sepc;
+            TrapType::SysCall
+        }
         // 时钟中断
         Trap::Interrupt(Interrupt::SupervisorTimer) => TrapType::Timer,
         Trap::Exception(Exception::StorePageFault) => TrapType::StorePageFault(stval),
@@ -53,7 +64,16 @@ fn kernel_callback(context: &mut TrapFrame) -> TrapType {
         Trap::Exception(Exception::InstructionPageFault) => TrapType::InstructionPageFault(stval),
         Trap::Exception(Exception::IllegalInstruction) => TrapType::IllegalInstruction(stval),
         Trap::Exception(Exception::LoadPageFault) => TrapType::LoadPageFault(stval),
-        Trap::Interrupt(Interrupt::SupervisorExternal) => TrapType::SupervisorExternal,
+        #[cfg(feature = "misaligned")]
+        Trap::Exception(Exception::LoadMisaligned | Exception::StoreMisaligned) => {
+            misaligned::emulate_misaligned(context)
+        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => TrapType::Ipi(take_ipi()),
+        Trap::Interrupt(Interrupt::SupervisorExternal) => match get_irq() {
+            irq if irq.is_ipi() => TrapType::Ipi(take_ipi()),
+            irq if irq.irq_num() != 0 => TrapType::Irq(irq),
+            _ => TrapType::SupervisorExternal,
+        },
         _ => {
             log::error!(
                 "内核态中断发生: {:#x} {:?}  stval {:#x}  sepc: {:#x}",
@@ -62,9 +82,15 @@ fn kernel_callback(context: &mut TrapFrame) -> TrapType {
                 stval,
                 context.sepc
             );
+            super::dump_backtrace(context);
             panic!("未知中断: {:#x?}", context);
         }
     };
+    super::count_interrupt(trap_type);
+    #[cfg(feature = "gdbstub")]
+    if crate::gdbstub::handle_trap(context, trap_type) {
+        return trap_type;
+    }
     unsafe { super::_interrupt_for_arch(context, trap_type, 0) };
     trap_type
 }
@@ -87,6 +113,15 @@ pub unsafe extern "C" fn kernelvec() {
             SAVE_GENERAL_REGS
             csrw    sscratch, x0
 
+            // Switch to the emergency stack if the kernel stack was overflowed.
+            mv      a0, sp
+            call    kernel_stack_check
+            beqz    a0, 1f
+            mv      a1, sp
+            mv      sp, a0
+            mv      a0, a1
+            call    kernel_stack_overflow
+        1:
             mv      a0, sp
 
             call kernel_callback
@@ -134,9 +169,6 @@ extern "C" fn user_restore(context: *mut TrapFrame) {
                 csrw     sscratch, a0
                 mv       sp, a0
 
-                .short   0x2452      # fld  fs0, 272(sp)
-                .short   0x24f2      # fld  fs1, 280(sp)
-
                 LOAD_GENERAL_REGS
                 sret
             ",
@@ -155,9 +187,6 @@ pub unsafe extern "C" fn uservec() {
         SAVE_GENERAL_REGS
         csrw    sscratch, x0
 
-        .word   0x10813827          # fsd fs0, 272(sp)
-        .word   0x10913c27          # fsd fs1, 280(sp)
-
         mv      a0, sp
         ld      sp, 0*8(a0)
         sd      x0, 0*8(a0)
@@ -189,16 +218,37 @@ pub unsafe extern "C" fn uservec() {
     );
 }
 
+/// Return to the user task, the floating-point and vector states are saved lazily.
+fn run_user(context: &mut TrapFrame) {
+    context.restore_fp();
+    context.restore_vector();
+    if context.single_step {
+        polyhal::breakpoint::enable_single_step();
+    }
+    user_restore(context);
+    if context.single_step {
+        polyhal::breakpoint::disable_single_step();
+    }
+    context.save_fp();
+    context.save_vector();
+}
+
+/// Run the user task until it traps, return the trap type.
+///
+/// The trap has been passed to the interrupt handler of the kernel.
+pub fn run_user_task_trap(context: &mut TrapFrame) -> TrapType {
+    run_user(context);
+    kernel_callback(context)
+}
+
 /// Return EscapeReson related to interrupt type.
 pub fn run_user_task(context: &mut TrapFrame) -> EscapeReason {
-    user_restore(context);
-    kernel_callback(context).into()
+    run_user_task_trap(context).into()
 }
 
-/// Run user task until interrupt is received.
+/// Run the user task forever, the traps are only passed to the interrupt handler.
 pub fn run_user_task_forever(context: &mut TrapFrame) -> ! {
     loop {
-        user_restore(context);
-        kernel_callback(context);
+        run_user_task_trap(context);
     }
 }
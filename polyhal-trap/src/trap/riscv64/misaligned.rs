@@ -0,0 +1,214 @@
+//! Emulate the misaligned loads and stores which aren't handled by the
+//! hardware or the SBI.
+//!
+//! The instruction at `sepc` is decoded, the base, compressed and
+//! floating-point loads and stores are supported. The memory is accessed
+//! byte by byte, the user addresses are translated by the current page table
+//! so the access can't fault in the trap handler. If a page isn't mapped or
+//! isn't writable, the page fault is reported instead and the instruction
+//! is emulated again after the kernel resolves it.
+
+use polyhal::consts::VIRT_ADDR_START;
+use polyhal::{MappingFlags, PageTable, VirtAddr};
+
+use crate::trap::TrapType;
+use crate::trapframe::TrapFrame;
+
+const OPCODE_LOAD: u32 = 0x03;
+const OPCODE_LOAD_FP: u32 = 0x07;
+const OPCODE_STORE: u32 = 0x23;
+const OPCODE_STORE_FP: u32 = 0x27;
+
+/// The register of the misaligned access.
+#[derive(Clone, Copy)]
+enum Reg {
+    X(usize),
+    F(usize),
+}
+
+/// A decoded load or store instruction.
+struct Access {
+    reg: Reg,
+    addr: usize,
+    len: usize,
+    /// Sign-extend the loaded value.
+    signed: bool,
+    store: bool,
+    insn_len: usize,
+}
+
+#[inline]
+fn bits(insn: u32, hi: u32, lo: u32) -> usize {
+    ((insn >> lo) & ((1 << (hi - lo + 1)) - 1)) as usize
+}
+
+/// Get the pointer of a byte which can be accessed without a fault.
+fn byte_ptr(tf: &TrapFrame, addr: usize, store: bool) -> Result<*mut u8, TrapType> {
+    let fault = match store {
+        true => TrapType::StorePageFault(addr),
+        false => TrapType::LoadPageFault(addr),
+    };
+    if !tf.from_user() {
+        return match addr >= VIRT_ADDR_START {
+            true => Ok(addr as *mut u8),
+            false => Err(fault),
+        };
+    }
+    let required = match store {
+        true => MappingFlags::U | MappingFlags::W,
+        false => MappingFlags::U | MappingFlags::R,
+    };
+    match PageTable::current().translate(VirtAddr::new(addr)) {
+        Some((paddr, flags)) if flags.contains(MappingFlags::P | required) => {
+            Ok(paddr.get_mut_ptr())
+        }
+        _ => Err(fault),
+    }
+}
+
+fn read_bytes(tf: &TrapFrame, addr: usize, len: usize) -> Result<u64, TrapType> {
+    let mut bytes = [0u8; 8];
+    for (i, byte) in bytes.iter_mut().take(len).enumerate() {
+        *byte = unsafe { byte_ptr(tf, addr.wrapping_add(i), false)?.read_volatile() };
+    }
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_bytes(tf: &TrapFrame, addr: usize, len: usize, value: u64) -> Result<(), TrapType> {
+    // Check all the bytes before writing any of them.
+    for i in 0..len {
+        byte_ptr(tf, addr.wrapping_add(i), true)?;
+    }
+    for (i, byte) in value.to_le_bytes().iter().take(len).enumerate() {
+        unsafe { byte_ptr(tf, addr.wrapping_add(i), true)?.write_volatile(*byte) };
+    }
+    Ok(())
+}
+
+/// Fetch the instruction at `sepc`, which may be compressed.
+fn fetch(tf: &TrapFrame) -> Result<u32, TrapType> {
+    let fetch_half = |addr| {
+        read_bytes(tf, addr, 2)
+            .map(|half| half as u32)
+            .map_err(|_| TrapType::InstructionPageFault(addr))
+    };
+    let low = fetch_half(tf.sepc)?;
+    match low & 0b11 {
+        0b11 => Ok(low | (fetch_half(tf.sepc.wrapping_add(2))? << 16)),
+        _ => Ok(low),
+    }
+}
+
+/// Decode the load or store instruction.
+fn decode(tf: &TrapFrame, insn: u32) -> Option<Access> {
+    let x = |reg: usize| tf.x[reg];
+    let access = |reg, addr, len, signed, store, insn_len| {
+        Some(Access {
+            reg,
+            addr,
+            len,
+            signed,
+            store,
+            insn_len,
+        })
+    };
+    if insn & 0b11 == 0b11 {
+        let rd = bits(insn, 11, 7);
+        let rs1 = bits(insn, 19, 15);
+        let rs2 = bits(insn, 24, 20);
+        let funct3 = bits(insn, 14, 12);
+        let load_addr = x(rs1).wrapping_add((insn as i32 >> 20) as usize);
+        let store_imm = ((insn as i32 >> 25) << 5) as usize | bits(insn, 11, 7);
+        let store_addr = x(rs1).wrapping_add(store_imm);
+        return match (insn & 0x7f, funct3) {
+            // lh, lw, ld, lhu, lwu
+            (OPCODE_LOAD, 1) => access(Reg::X(rd), load_addr, 2, true, false, 4),
+            (OPCODE_LOAD, 2) => access(Reg::X(rd), load_addr, 4, true, false, 4),
+            (OPCODE_LOAD, 3) => access(Reg::X(rd), load_addr, 8, false, false, 4),
+            (OPCODE_LOAD, 5) => access(Reg::X(rd), load_addr, 2, false, false, 4),
+            (OPCODE_LOAD, 6) => access(Reg::X(rd), load_addr, 4, false, false, 4),
+            // sh, sw, sd
+            (OPCODE_STORE, 1) => access(Reg::X(rs2), store_addr, 2, false, true, 4),
+            (OPCODE_STORE, 2) => access(Reg::X(rs2), store_addr, 4, false, true, 4),
+            (OPCODE_STORE, 3) => access(Reg::X(rs2), store_addr, 8, false, true, 4),
+            // flw, fld, fsw, fsd
+            (OPCODE_LOAD_FP, 2) => access(Reg::F(rd), load_addr, 4, false, false, 4),
+            (OPCODE_LOAD_FP, 3) => access(Reg::F(rd), load_addr, 8, false, false, 4),
+            (OPCODE_STORE_FP, 2) => access(Reg::F(rs2), store_addr, 4, false, true, 4),
+            (OPCODE_STORE_FP, 3) => access(Reg::F(rs2), store_addr, 8, false, true, 4),
+            _ => None,
+        };
+    }
+    // The compressed registers x8-x15 in bits 9:7 and 4:2.
+    let rs1c = 8 + bits(insn, 9, 7);
+    let rdc = 8 + bits(insn, 4, 2);
+    let rd = bits(insn, 11, 7);
+    let rs2 = bits(insn, 6, 2);
+    let sp = x(2);
+    let word_off = (bits(insn, 12, 10) << 3) | (bits(insn, 6, 6) << 2) | (bits(insn, 5, 5) << 6);
+    let double_off = (bits(insn, 12, 10) << 3) | (bits(insn, 6, 5) << 6);
+    let lwsp_off = (bits(insn, 12, 12) << 5) | (bits(insn, 6, 4) << 2) | (bits(insn, 3, 2) << 6);
+    let ldsp_off = (bits(insn, 12, 12) << 5) | (bits(insn, 6, 5) << 3) | (bits(insn, 4, 2) << 6);
+    let swsp_off = (bits(insn, 12, 9) << 2) | (bits(insn, 8, 7) << 6);
+    let sdsp_off = (bits(insn, 12, 10) << 3) | (bits(insn, 9, 7) << 6);
+    match (insn & 0b11, bits(insn, 15, 13)) {
+        // c.fld, c.lw, c.ld, c.fsd, c.sw, c.sd
+        (0b00, 1) => access(Reg::F(rdc), x(rs1c).wrapping_add(double_off), 8, false, false, 2),
+        (0b00, 2) => access(Reg::X(rdc), x(rs1c).wrapping_add(word_off), 4, true, false, 2),
+        (0b00, 3) => access(Reg::X(rdc), x(rs1c).wrapping_add(double_off), 8, false, false, 2),
+        (0b00, 5) => access(Reg::F(rdc), x(rs1c).wrapping_add(double_off), 8, false, true, 2),
+        (0b00, 6) => access(Reg::X(rdc), x(rs1c).wrapping_add(word_off), 4, false, true, 2),
+        (0b00, 7) => access(Reg::X(rdc), x(rs1c).wrapping_add(double_off), 8, false, true, 2),
+        // c.fldsp, c.lwsp, c.ldsp, c.fsdsp, c.swsp, c.sdsp
+        (0b10, 1) => access(Reg::F(rd), sp.wrapping_add(ldsp_off), 8, false, false, 2),
+        (0b10, 2) if rd != 0 => access(Reg::X(rd), sp.wrapping_add(lwsp_off), 4, true, false, 2),
+        (0b10, 3) if rd != 0 => access(Reg::X(rd), sp.wrapping_add(ldsp_off), 8, false, false, 2),
+        (0b10, 5) => access(Reg::F(rs2), sp.wrapping_add(sdsp_off), 8, false, true, 2),
+        (0b10, 6) => access(Reg::X(rs2), sp.wrapping_add(swsp_off), 4, false, true, 2),
+        (0b10, 7) => access(Reg::X(rs2), sp.wrapping_add(sdsp_off), 8, false, true, 2),
+        _ => None,
+    }
+}
+
+/// Emulate the misaligned load or store at `sepc` and skip it.
+///
+/// Return [TrapType::Unknown] if it was emulated, or the page fault which
+/// must be handled before the instruction is emulated again.
+/// Panic if the instruction can't be emulated.
+pub(super) fn emulate_misaligned(tf: &mut TrapFrame) -> TrapType {
+    let access = match fetch(tf) {
+        Ok(insn) => decode(tf, insn).unwrap_or_else(|| {
+            panic!("unhandled misaligned access @ {:#x}, inst: {:#x}", tf.sepc, insn)
+        }),
+        Err(fault) => return fault,
+    };
+    // The floating-point state is only kept in the trap frame of a user task.
+    if matches!(access.reg, Reg::F(_)) && !tf.from_user() {
+        panic!("misaligned floating-point access in kernel @ {:#x}", tf.sepc);
+    }
+    let result = match (access.store, access.reg) {
+        (true, Reg::X(reg)) => write_bytes(tf, access.addr, access.len, tf.x[reg] as u64),
+        (true, Reg::F(reg)) => write_bytes(tf, access.addr, access.len, tf.fp.f[reg]),
+        (false, reg) => read_bytes(tf, access.addr, access.len).map(|value| {
+            let shift = 64 - access.len * 8;
+            match reg {
+                Reg::X(0) => {}
+                Reg::X(reg) if access.signed => {
+                    tf.x[reg] = (((value << shift) as i64) >> shift) as usize
+                }
+                Reg::X(reg) => tf.x[reg] = value as usize,
+                // The single-precision value is NaN-boxed.
+                Reg::F(reg) if access.len == 4 => tf.fp.f[reg] = value | (u64::MAX << 32),
+                Reg::F(reg) => tf.fp.f[reg] = value,
+            }
+        }),
+    };
+    if let Err(fault) = result {
+        return fault;
+    }
+    tf.sepc += access.insn_len;
+    if tf.from_user() {
+        tf.misaligned += 1;
+    }
+    TrapType::Unknown
+}
@@ -2,18 +2,20 @@
 mod macros;
 
 use super::{EscapeReason, TrapType};
-use crate::trapframe::{FxsaveArea, TrapFrame, TRAPFRAME_SIZE};
+use crate::trapframe::TrapFrame;
 use bitflags::bitflags;
 use core::{
     arch::{global_asm, naked_asm},
-    mem::{offset_of, size_of},
+    mem::offset_of,
 };
 use polyhal::{
     apic::{local_apic, vectors::*},
+    breakpoint::DebugHit,
     consts::{PIC_VECTOR_OFFSET, SYSCALL_VECTOR},
     gdt::{set_tss_kernel_sp, GdtStruct},
     irq,
     percpu::PerCPUReserved,
+    stack::stack_guard_hit,
 };
 use x86::irq::*;
 use x86_64::{
@@ -48,7 +50,7 @@ static KERNEL_SP: usize = 0;
 
 // 内核中断回调
 #[no_mangle]
-fn kernel_callback(context: &mut TrapFrame) {
+fn kernel_callback(context: &mut TrapFrame) -> TrapType {
     let trap_type = match context.vector as u8 {
         PAGE_FAULT_VECTOR => {
             let pflags = PageFaultFlags::from_bits_truncate(context.rflags as _);
@@ -61,7 +63,22 @@ fn kernel_callback(context: &mut TrapFrame) {
             }
         }
         BREAKPOINT_VECTOR => TrapType::Breakpoint,
+        DEBUG_VECTOR => match polyhal::breakpoint::take_hit() {
+            // Execute the instruction without hitting the breakpoint again.
+            Some(DebugHit::Breakpoint) => {
+                context.rflags |= RFlags::RESUME_FLAG.bits() as usize;
+                TrapType::Breakpoint
+            }
+            Some(hit) => hit.into(),
+            None => TrapType::Unknown,
+        },
+        // The first floating-point instruction after a lazy context switch.
+        #[cfg(feature = "fp_lazy")]
+        DEVICE_NOT_AVAILABLE_VECTOR if polyhal::kcontext::handle_fp_trap() => {
+            return TrapType::Unknown
+        }
         GENERAL_PROTECTION_FAULT_VECTOR => {
+            super::dump_backtrace(context);
             panic!(
                 "#GP @ {:#x}, fault_vaddr={:#x} error_code={:#x}:\n{:#x?}",
                 context.rip,
@@ -70,22 +87,50 @@ fn kernel_callback(context: &mut TrapFrame) {
                 context
             );
         }
+        DOUBLE_FAULT_VECTOR => {
+            if let Some(bottom) = stack_guard_hit(context.rsp) {
+                panic!(
+                    "Kernel stack overflow @ {:#x}, rsp={:#x} stack_bottom={:#x}:\n{:#x?}",
+                    context.rip, context.rsp, bottom, context
+                );
+            }
+            super::dump_backtrace(context);
+            panic!(
+                "#DF @ {:#x}, rsp={:#x}:\n{:#x?}",
+                context.rip, context.rsp, context
+            );
+        }
         APIC_TIMER_VECTOR => {
             unsafe { local_apic().end_of_interrupt() };
             TrapType::Timer
         }
+        APIC_IPI_VECTOR => TrapType::Ipi(irq::take_ipi()),
+        // The spurious vector isn't acknowledged.
+        APIC_SPURIOUS_VECTOR => {
+            irq::count_irq(irq::IrqSource::Spurious);
+            return TrapType::Unknown;
+        }
         // PIC IRQS
         0x20..=0x2f => TrapType::Irq(irq::IRQVector::new(
             context.vector - PIC_VECTOR_OFFSET as usize,
         )),
+        // MSI vectors
+        0x30..=0xef => TrapType::Irq(irq::IRQVector::new(context.vector)),
         _ => {
+            super::dump_backtrace(context);
             panic!(
                 "Unhandled exception {} (error_code = {:#x}) @ {:#x}:\n{:#x?}",
                 context.vector, context.error_code, context.rip, context
             );
         }
     };
+    super::count_interrupt(trap_type);
+    #[cfg(feature = "gdbstub")]
+    if crate::gdbstub::handle_trap(context, trap_type) {
+        return trap_type;
+    }
     unsafe { super::_interrupt_for_arch(context, trap_type, 0) };
+    trap_type
 }
 
 /// Kernel Trap Entry
@@ -344,11 +389,12 @@ unsafe extern "C" fn syscall_entry() {
     )
 }
 
-/// Return Some(()) if it was interrupt by syscall, otherwise None.
-pub fn run_user_task(context: &mut TrapFrame) -> EscapeReason {
+/// Run the user task until it traps, return the trap type.
+///
+/// The trap has been passed to the interrupt handler of the kernel.
+pub fn run_user_task_trap(context: &mut TrapFrame) -> TrapType {
     // TODO: set tss kernel sp just once, before task run.
-    let cx_general_top =
-        context as *mut TrapFrame as usize + TRAPFRAME_SIZE - size_of::<FxsaveArea>();
+    let cx_general_top = context as *mut TrapFrame as usize + offset_of!(TrapFrame, xstate);
     set_tss_kernel_sp(cx_general_top);
     // USER_CONTEXT.write_current(cx_general_top);
     unsafe {
@@ -359,18 +405,32 @@ pub fn run_user_task(context: &mut TrapFrame) -> EscapeReason {
             USER_CONTEXT = const offset_of!(PerCPUReserved, user_context)
         );
     }
-    context.fx_area.restore();
+    // Restore the state pending from the lazy context switch and clear `CR0.TS`,
+    // or the `XRSTOR` of the user state raises #NM.
+    #[cfg(feature = "fp_lazy")]
+    polyhal::kcontext::handle_fp_trap();
+    context.xstate.restore();
     user_restore(context);
-    context.fx_area.save();
+    context.xstate.save();
 
     match context.vector {
         SYSCALL_VECTOR => {
+            context.orig_rax = context.rax;
             unsafe { super::_interrupt_for_arch(context, TrapType::SysCall, 0) };
-            EscapeReason::SysCall
-        }
-        _ => {
-            kernel_callback(context);
-            EscapeReason::NoReason
+            TrapType::SysCall
         }
+        _ => kernel_callback(context),
+    }
+}
+
+/// Return EscapeReson related to interrupt type.
+pub fn run_user_task(context: &mut TrapFrame) -> EscapeReason {
+    run_user_task_trap(context).into()
+}
+
+/// Run the user task forever, the traps are only passed to the interrupt handler.
+pub fn run_user_task_forever(context: &mut TrapFrame) -> ! {
+    loop {
+        run_user_task_trap(context);
     }
 }
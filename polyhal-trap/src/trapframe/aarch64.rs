@@ -1,6 +1,8 @@
 use core::ops::{Index, IndexMut};
 
-use super::TrapFrameArgs;
+pub use polyhal::kcontext::FpState;
+
+use super::{SyscallAbi, TrapFrameArgs};
 
 /// Saved registers when a trap (interrupt or exception) occurs.#[allow(missing_docs)]
 #[repr(C)]
@@ -11,8 +13,17 @@ pub struct TrapFrame {
     pub elr: usize,
     pub spsr: usize,
     pub tpidr: usize,
+    pub fp: FpState,
+    /// The single-step is enabled, see [TrapFrame::set_single_step].
+    pub(crate) single_step: bool,
+    /// The first syscall argument at the syscall entry, restored by
+    /// [SyscallAbi::restart] as the register is overwritten by the return value.
+    pub orig_x0: usize,
 }
 
+/// Software step, `SPSR_EL1.SS`.
+pub(crate) const SPSR_SS: usize = 1 << 21;
+
 impl TrapFrame {
     // 创建上下文信息
     #[inline]
@@ -22,6 +33,21 @@ impl TrapFrame {
         }
     }
 
+    /// Create a trap frame to enter the user task at `entry`.
+    ///
+    /// The task runs in user mode with interrupts enabled, on the stack
+    /// `user_sp` and with the thread pointer `tls`.
+    pub fn new_user(entry: usize, user_sp: usize, tls: usize) -> Self {
+        Self {
+            // EL0t with all the exceptions unmasked.
+            spsr: 0,
+            elr: entry,
+            sp: user_sp,
+            tpidr: tls,
+            ..Default::default()
+        }
+    }
+
     #[inline]
     pub fn args(&self) -> [usize; 6] {
         [
@@ -40,8 +66,45 @@ impl TrapFrame {
         (self.spsr >> 2) & 0x3 == 0
     }
 
+    /// Enable or disable the single-step of the user task, `SPSR_EL1.SS`.
+    ///
+    /// [TrapType::SingleStep] is reported after each instruction until it's disabled.
+    ///
+    /// [TrapType::SingleStep]: crate::trap::TrapType::SingleStep
+    #[inline]
+    pub fn set_single_step(&mut self, enable: bool) {
+        self.single_step = enable;
+        match enable {
+            true => self.spsr |= SPSR_SS,
+            false => self.spsr &= !SPSR_SS,
+        }
+    }
+
+    /// Walk the stack of the trap context through the frame pointers.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> polyhal::backtrace::Backtrace {
+        polyhal::backtrace::Backtrace::new(self.elr, self.regs[29])
+    }
+}
+
+// The `svc` instruction has advanced `elr`.
+impl SyscallAbi<'_> {
+    /// Set the second return value of the syscall.
+    #[inline]
+    pub fn set_return2(&mut self, val: usize) {
+        self.0.regs[1] = val;
+    }
+
+    /// Continue after the syscall instruction.
     #[inline]
-    pub fn syscall_ok(&mut self) {}
+    pub fn skip(&mut self) {}
+
+    /// Execute the syscall instruction again.
+    #[inline]
+    pub fn restart(&mut self) {
+        self.0.regs[0] = self.0.orig_x0;
+        self.0.elr -= 4;
+    }
 }
 
 impl Index<TrapFrameArgs> for TrapFrame {
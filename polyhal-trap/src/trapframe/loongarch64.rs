@@ -1,10 +1,12 @@
 use core::ops::{Index, IndexMut};
 
-use super::TrapFrameArgs;
+pub use polyhal::kcontext::FpState;
+
+use super::{SyscallAbi, TrapFrameArgs};
 
 /// Saved registers when a trap (interrupt or exception) occurs.
 #[allow(missing_docs)]
-#[repr(C)]
+#[repr(C, align(16))]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct TrapFrame {
     /// General Registers
@@ -13,6 +15,17 @@ pub struct TrapFrame {
     pub prmd: usize,
     /// Exception Return Address
     pub era: usize,
+    /// The single-step is enabled, see [TrapFrame::set_single_step].
+    pub(crate) single_step: bool,
+    /// The first syscall argument at the syscall entry, restored by
+    /// [SyscallAbi::restart] as the register is overwritten by the return value.
+    pub orig_a0: usize,
+    /// The address of the syscall instruction, [SyscallAbi::restart] returns
+    /// to it even if the syscall was skipped.
+    pub orig_era: usize,
+    /// Floating-point registers, restored before returning to the user task
+    /// and saved after it traps.
+    pub fp: FpState,
 }
 
 impl TrapFrame {
@@ -27,13 +40,21 @@ impl TrapFrame {
             ..Default::default()
         }
     }
-}
 
-impl TrapFrame {
-    pub fn syscall_ok(&mut self) {
-        self.era += 4;
+    /// Create a trap frame to enter the user task at `entry`.
+    ///
+    /// The task runs in user mode with interrupts enabled, on the stack
+    /// `user_sp` and with the thread pointer `tls`.
+    pub fn new_user(entry: usize, user_sp: usize, tls: usize) -> Self {
+        let mut tf = Self::new();
+        tf.era = entry;
+        tf.regs[3] = user_sp;
+        tf.regs[2] = tls;
+        tf
     }
+}
 
+impl TrapFrame {
     #[inline]
     pub fn args(&self) -> [usize; 6] {
         [
@@ -45,6 +66,51 @@ impl TrapFrame {
             self.regs[9],
         ]
     }
+
+    /// Check if the trapframe was from user, `PRMD.PPLV` is 3.
+    #[inline]
+    pub fn from_user(&self) -> bool {
+        self.prmd & 0x3 == 0x3
+    }
+
+    /// Enable or disable the single-step of the user task.
+    ///
+    /// A fetch watchpoint is set before returning to the task,
+    /// [TrapType::SingleStep] is reported after each instruction until it's disabled.
+    ///
+    /// [TrapType::SingleStep]: crate::trap::TrapType::SingleStep
+    #[inline]
+    pub fn set_single_step(&mut self, enable: bool) {
+        self.single_step = enable;
+    }
+
+    /// Walk the stack of the trap context through the frame pointers.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> polyhal::backtrace::Backtrace {
+        polyhal::backtrace::Backtrace::new(self.era, self.regs[22])
+    }
+}
+
+// The `syscall` instruction doesn't advance `era`.
+impl SyscallAbi<'_> {
+    /// Set the second return value of the syscall.
+    #[inline]
+    pub fn set_return2(&mut self, val: usize) {
+        self.0.regs[5] = val;
+    }
+
+    /// Continue after the syscall instruction.
+    #[inline]
+    pub fn skip(&mut self) {
+        self.0.era += 4;
+    }
+
+    /// Execute the syscall instruction again.
+    #[inline]
+    pub fn restart(&mut self) {
+        self.0.regs[4] = self.0.orig_a0;
+        self.0.era = self.0.orig_era;
+    }
 }
 
 impl Index<TrapFrameArgs> for TrapFrame {
@@ -22,5 +22,65 @@ pub enum TrapFrameArgs {
     SYSCALL,
 }
 
+/// Syscall ABI view over a [TrapFrame].
+///
+/// The syscall number, arguments and return values are in the registers
+/// defined by the Linux syscall convention of each architecture.
+///
+/// Call [SyscallAbi::skip] or [SyscallAbi::restart] once for a syscall, the
+/// restart is still possible after the skip. [SyscallAbi::restart] needs the
+/// syscall number and arguments unchanged, except the register shared by the
+/// return value, which is saved at the syscall entry.
+pub struct SyscallAbi<'a>(&'a mut TrapFrame);
+
+impl SyscallAbi<'_> {
+    /// Get the syscall number.
+    #[inline]
+    pub fn number(&self) -> usize {
+        self.0[TrapFrameArgs::SYSCALL]
+    }
+
+    /// Get the `n`th syscall argument, `n` must be in `0..6`.
+    #[inline]
+    pub fn arg(&self, n: usize) -> usize {
+        self.0.args()[n]
+    }
+
+    /// Set the return value of the syscall.
+    #[inline]
+    pub fn set_return(&mut self, val: usize) {
+        self.0[TrapFrameArgs::RET] = val;
+    }
+}
+
+impl TrapFrame {
+    /// Get the syscall ABI view of the trap frame.
+    #[inline]
+    pub fn syscall_abi(&mut self) -> SyscallAbi<'_> {
+        SyscallAbi(self)
+    }
+
+    /// Continue after the syscall instruction.
+    #[deprecated(note = "use `SyscallAbi::skip` instead")]
+    #[inline]
+    pub fn syscall_ok(&mut self) {
+        self.syscall_abi().skip();
+    }
+
+    /// Create the trap frame of the child task forked at a syscall.
+    ///
+    /// The child returns 0 from the syscall, and continues after the
+    /// syscall instruction.
+    pub fn fork_child(&self) -> Self {
+        // The x86_64 and riscv64 trap frames aren't `Copy`.
+        #[allow(clippy::clone_on_copy)]
+        let mut child = self.clone();
+        let mut abi = child.syscall_abi();
+        abi.set_return(0);
+        abi.skip();
+        child
+    }
+}
+
 /// The size of the [TrapFrame]
 pub const TRAPFRAME_SIZE: usize = size_of::<TrapFrame>();
@@ -3,9 +3,11 @@ use core::{
     ops::{Index, IndexMut},
 };
 
-use riscv::register::sstatus::{self, Sstatus, SPP};
+pub use polyhal::kcontext::FpState;
+use polyhal::vector::{VectorState, VS};
+use riscv::register::sstatus::{self, Sstatus, FS, SPP};
 
-use super::TrapFrameArgs;
+use super::{SyscallAbi, TrapFrameArgs};
 
 #[repr(C)]
 #[derive(Clone)]
@@ -14,7 +16,19 @@ pub struct TrapFrame {
     pub x: [usize; 32], // 32 个通用寄存器
     pub sstatus: Sstatus,
     pub sepc: usize,
-    pub fsx: [usize; 2],
+    pub fp: FpState,
+    pub vector: VectorState,
+    /// The single-step is enabled, see [TrapFrame::set_single_step].
+    pub(crate) single_step: bool,
+    /// The number of the misaligned accesses emulated for the user task,
+    /// the kernel may sum them up per process.
+    pub misaligned: usize,
+    /// The first syscall argument at the syscall entry, restored by
+    /// [SyscallAbi::restart] as the register is overwritten by the return value.
+    pub orig_a0: usize,
+    /// The address of the syscall instruction, [SyscallAbi::restart] returns
+    /// to it even if the syscall was skipped.
+    pub orig_sepc: usize,
 }
 
 impl Debug for TrapFrame {
@@ -53,7 +67,12 @@ impl Debug for TrapFrame {
             .field("t6", &self.x[31])
             .field("sstatus", &self.sstatus)
             .field("sepc", &self.sepc)
-            .field("fsx", &self.fsx)
+            .field("fp", &self.fp)
+            .field("vector", &self.vector)
+            .field("single_step", &self.single_step)
+            .field("misaligned", &self.misaligned)
+            .field("orig_a0", &self.orig_a0)
+            .field("orig_sepc", &self.orig_sepc)
             .finish()
     }
 }
@@ -66,10 +85,36 @@ impl TrapFrame {
             x: [0usize; 32],
             sstatus: sstatus::read(),
             sepc: 0,
-            fsx: [0; 2],
+            fp: FpState::default(),
+            vector: VectorState::new(),
+            single_step: false,
+            misaligned: 0,
+            orig_a0: 0,
+            orig_sepc: 0,
         }
     }
 
+    /// Create a trap frame to enter the user task at `entry`.
+    ///
+    /// The task runs in user mode with interrupts enabled, on the stack
+    /// `user_sp` and with the thread pointer `tls`.
+    pub fn new_user(entry: usize, user_sp: usize, tls: usize) -> Self {
+        let mut tf = Self::new();
+        tf.sstatus.set_spp(SPP::User);
+        tf.sstatus.set_spie(true);
+        // The floating-point and vector states are loaded at the first return.
+        tf.sstatus.set_fs(FS::Initial);
+        let vs = match polyhal::vector::vlenb() {
+            0 => VS::Off,
+            _ => VS::Initial,
+        };
+        tf.sstatus = Sstatus::from_bits(vs.to_sstatus(tf.sstatus.bits()));
+        tf.sepc = entry;
+        tf.x[2] = user_sp;
+        tf.x[4] = tls;
+        tf
+    }
+
     #[inline]
     pub fn args(&self) -> [usize; 6] {
         self.x[10..16].try_into().expect("args slice force convert")
@@ -81,9 +126,82 @@ impl TrapFrame {
         self.sstatus.spp() == SPP::User
     }
 
+    /// Enable or disable the single-step of the user task.
+    ///
+    /// An instruction count trigger is installed before returning to the task,
+    /// [TrapType::SingleStep] is reported after each instruction until it's disabled.
+    ///
+    /// [TrapType::SingleStep]: crate::trap::TrapType::SingleStep
+    #[inline]
+    pub fn set_single_step(&mut self, enable: bool) {
+        self.single_step = enable;
+    }
+
+    /// Save the floating-point state if the task dirtied it, called after the task trapped.
+    #[inline]
+    pub(crate) fn save_fp(&mut self) {
+        if self.sstatus.fs() == FS::Dirty {
+            self.fp.save();
+            self.sstatus.set_fs(FS::Clean);
+        }
+    }
+
+    /// Restore the floating-point state, called before returning to the task.
+    #[inline]
+    pub(crate) fn restore_fp(&self) {
+        if self.sstatus.fs() != FS::Off {
+            // The FS of current sstatus may be Off if the last task didn't use the FPU.
+            unsafe { sstatus::set_fs(FS::Clean) };
+            self.fp.restore();
+        }
+    }
+
+    /// Save the vector state if the task dirtied it, called after the task trapped.
+    #[inline]
+    pub(crate) fn save_vector(&mut self) {
+        let sstatus = self.sstatus.bits();
+        if VS::from_sstatus(sstatus) == VS::Dirty {
+            self.vector.save();
+            self.sstatus = Sstatus::from_bits(VS::Clean.to_sstatus(sstatus));
+        }
+    }
+
+    /// Restore the vector state, called before returning to the task.
+    #[inline]
+    pub(crate) fn restore_vector(&self) {
+        if VS::from_sstatus(self.sstatus.bits()) != VS::Off {
+            // The VS of current sstatus may be Off if the last task didn't use the vector unit.
+            VS::Clean.write();
+            self.vector.restore();
+        }
+    }
+
+    /// Walk the stack of the trap context through the frame pointers.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> polyhal::backtrace::Backtrace {
+        polyhal::backtrace::Backtrace::new(self.sepc, self.x[8])
+    }
+}
+
+// The `ecall` instruction doesn't advance `sepc`.
+impl SyscallAbi<'_> {
+    /// Set the second return value of the syscall.
+    #[inline]
+    pub fn set_return2(&mut self, val: usize) {
+        self.0.x[11] = val;
+    }
+
+    /// Continue after the syscall instruction.
+    #[inline]
+    pub fn skip(&mut self) {
+        self.0.sepc += 4;
+    }
+
+    /// Execute the syscall instruction again.
     #[inline]
-    pub fn syscall_ok(&mut self) {
-        self.sepc += 4;
+    pub fn restart(&mut self) {
+        self.0.x[10] = self.0.orig_a0;
+        self.0.sepc = self.0.orig_sepc;
     }
 }
 
@@ -1,55 +1,12 @@
-use super::TrapFrameArgs;
+use super::{SyscallAbi, TrapFrameArgs};
 use core::{
     fmt::Debug,
     ops::{Index, IndexMut},
 };
 use polyhal::gdt::GdtStruct;
+pub use polyhal::xsave::{ExtendedState, FxsaveArea};
 use x86::bits64::rflags::RFlags;
 
-#[repr(C, align(16))]
-#[derive(Clone)]
-pub struct FxsaveArea {
-    pub fcw: u16,
-    pub fsw: u16,
-    pub ftw: u16,
-    pub fop: u16,
-    pub fip: u64,
-    pub fdp: u64,
-    pub mxcsr: u32,
-    pub mxcsr_mask: u32,
-    pub st: [u64; 16],
-    pub xmm: [u64; 32],
-    _padding: [u64; 12],
-}
-
-impl FxsaveArea {
-    #[inline]
-    pub(crate) fn save(&mut self) {
-        unsafe { core::arch::x86_64::_fxsave64(self as *mut _ as *mut u8) }
-    }
-
-    #[inline]
-    pub(crate) fn restore(&self) {
-        unsafe { core::arch::x86_64::_fxrstor64(self as *const _ as *const u8) }
-    }
-}
-
-impl Default for FxsaveArea {
-    fn default() -> Self {
-        let mut area: FxsaveArea = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
-        area.fcw = 0x37f;
-        area.ftw = 0xffff;
-        area.mxcsr = 0x1f80;
-        area
-    }
-}
-
-impl Debug for FxsaveArea {
-    fn fmt(&self, _f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        Ok(())
-    }
-}
-
 /// Saved registers when a trap (interrupt or exception) occurs.
 /// This is need be align 16, because tss trap ptr should be align 16? I think it is.
 #[allow(missing_docs)]
@@ -86,8 +43,12 @@ pub struct TrapFrame {
     pub rsp: usize,
     pub ss: usize,
 
-    // save fx area
-    pub fx_area: FxsaveArea,
+    // Extended state, saved by XSAVE or FXSAVE.
+    pub xstate: ExtendedState,
+
+    /// The syscall number at the syscall entry, restored by
+    /// [SyscallAbi::restart] as the register is overwritten by the return value.
+    pub orig_rax: usize,
 }
 
 impl TrapFrame {
@@ -101,6 +62,19 @@ impl TrapFrame {
             ..Default::default()
         }
     }
+
+    /// Create a trap frame to enter the user task at `entry`.
+    ///
+    /// The task runs in user mode with interrupts enabled, on the stack
+    /// `user_sp` and with the thread pointer `tls`.
+    pub fn new_user(entry: usize, user_sp: usize, tls: usize) -> Self {
+        Self {
+            rip: entry,
+            rsp: user_sp,
+            fs_base: tls,
+            ..Self::new()
+        }
+    }
 }
 
 impl TrapFrame {
@@ -109,16 +83,51 @@ impl TrapFrame {
         [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
     }
 
-    #[inline]
-    pub fn syscall_ok(&mut self) {
-        // self.sepc += 4;
-    }
-
     /// Check if the trapframe was from user.
     #[inline]
     pub fn from_user(&self) -> bool {
         self.cs == GdtStruct::UCODE64_SELECTOR.0 as _
     }
+
+    /// Enable or disable the single-step of the user task, `RFLAGS.TF`.
+    ///
+    /// [TrapType::SingleStep] is reported after each instruction until it's disabled.
+    ///
+    /// [TrapType::SingleStep]: crate::trap::TrapType::SingleStep
+    #[inline]
+    pub fn set_single_step(&mut self, enable: bool) {
+        let tf = RFlags::FLAGS_TF.bits() as usize;
+        match enable {
+            true => self.rflags |= tf,
+            false => self.rflags &= !tf,
+        }
+    }
+
+    /// Walk the stack of the trap context through the frame pointers.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> polyhal::backtrace::Backtrace {
+        polyhal::backtrace::Backtrace::new(self.rip, self.rbp)
+    }
+}
+
+// The `syscall` instruction has advanced `rip`.
+impl SyscallAbi<'_> {
+    /// Set the second return value of the syscall.
+    #[inline]
+    pub fn set_return2(&mut self, val: usize) {
+        self.0.rdx = val;
+    }
+
+    /// Continue after the syscall instruction.
+    #[inline]
+    pub fn skip(&mut self) {}
+
+    /// Execute the syscall instruction again.
+    #[inline]
+    pub fn restart(&mut self) {
+        self.0.rax = self.0.orig_rax;
+        self.0.rip -= 2;
+    }
 }
 
 impl Index<TrapFrameArgs> for TrapFrame {
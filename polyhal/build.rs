@@ -1,3 +1,5 @@
+use std::{env, fmt::Write, path::PathBuf};
+
 fn main() {
     // let ac = autocfg::new();
     // ac.set_no_std(true);
@@ -11,4 +13,66 @@ fn main() {
     //     "HAL_ENV_ARCH",
     //     std::env::var("CARGO_CFG_TARGET_ARCH").unwrap(),
     // );
+
+    println!("cargo::rerun-if-changed=build.rs");
+    check_frame_pointers();
+    gen_symbol_table();
+}
+
+/// Check whether `-C force-frame-pointers` is on, the backtrace relies on it.
+fn check_frame_pointers() {
+    println!("cargo::rustc-check-cfg=cfg(frame_pointers)");
+    let rustflags = env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+    let mut flags = rustflags.split('\x1f').peekable();
+    let mut enabled = false;
+    while let Some(flag) = flags.next() {
+        let flag = match flag {
+            "-C" | "--codegen" => flags.next().unwrap_or_default(),
+            _ => flag.trim_start_matches("-C"),
+        };
+        if let Some(value) = flag.strip_prefix("force-frame-pointers") {
+            enabled = matches!(value, "" | "=yes" | "=y" | "=on" | "=true");
+        }
+    }
+    if enabled {
+        println!("cargo::rustc-cfg=frame_pointers");
+    } else if env::var("CARGO_FEATURE_BACKTRACE").is_ok() {
+        println!(
+            "cargo::warning=backtrace needs `-C force-frame-pointers=yes`, only the first frame will be reported"
+        );
+    }
+}
+
+/// Generate the symbol table from the `nm -n -C` output given by `POLYHAL_SYMBOL_TABLE`.
+fn gen_symbol_table() {
+    println!("cargo::rerun-if-env-changed=POLYHAL_SYMBOL_TABLE");
+    let mut symbols = Vec::new();
+    if let Ok(path) = env::var("POLYHAL_SYMBOL_TABLE") {
+        println!("cargo::rerun-if-changed={}", path);
+        let content = std::fs::read_to_string(&path).expect("can't read the symbol table");
+        for line in content.lines() {
+            let mut fields = line.splitn(3, ' ');
+            let (Some(addr), Some(kind), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            // Only keep the symbols in the text section, skip the mapping symbols.
+            if !matches!(kind, "t" | "T" | "w" | "W") || name.starts_with(['$', '.']) {
+                continue;
+            }
+            if let Ok(addr) = usize::from_str_radix(addr, 16) {
+                symbols.push((addr, name.to_string()));
+            }
+        }
+        symbols.sort_by_key(|x| x.0);
+    }
+
+    let mut output = String::from("static SYMBOLS: &[(usize, &str)] = &[\n");
+    for (addr, name) in symbols {
+        writeln!(output, "    ({:#x}, {:?}),", addr, name).unwrap();
+    }
+    output.push_str("];\n");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    std::fs::write(out_dir.join("symbols.rs"), output).unwrap();
 }
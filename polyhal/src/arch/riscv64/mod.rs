@@ -1,4 +1,5 @@
 pub mod consts;
+pub mod vector;
 
 #[polyhal_macro::percpu]
 pub(crate) static CPU_ID: usize = 0;
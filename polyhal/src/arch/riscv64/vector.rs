@@ -0,0 +1,250 @@
+//! RISC-V Vector extension support.
+//!
+//! The V extension is detected from the `riscv,isa` or `riscv,isa-extensions`
+//! property of the cpu nodes, `sstatus.VS` is enabled if it is supported.
+//!
+//! The vector state is saved lazily, only if `sstatus.VS` is dirty.
+//! The register buffer of `32 * vlenb` bytes is allocated at the first save,
+//! the pages must be contiguous if it's larger than a page.
+
+use core::{arch::asm, ptr::copy_nonoverlapping};
+
+use lazyinit::LazyInit;
+
+use crate::{
+    common::{frame_alloc_contiguous, frame_dealloc_contiguous},
+    mem::get_fdt,
+    pagetable::PAGE_SIZE,
+    PhysAddr,
+};
+
+/// The offset of the `sstatus.VS` field.
+const SSTATUS_VS_SHIFT: usize = 9;
+/// The mask of the `sstatus.VS` field.
+const SSTATUS_VS: usize = 0b11 << SSTATUS_VS_SHIFT;
+
+/// The status of the vector unit, encoded the same as `sstatus.FS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VS {
+    Off = 0,
+    Initial = 1,
+    Clean = 2,
+    Dirty = 3,
+}
+
+impl VS {
+    /// Get the vector status from the value of `sstatus`.
+    #[inline]
+    pub const fn from_sstatus(sstatus: usize) -> Self {
+        match (sstatus & SSTATUS_VS) >> SSTATUS_VS_SHIFT {
+            0 => VS::Off,
+            1 => VS::Initial,
+            2 => VS::Clean,
+            _ => VS::Dirty,
+        }
+    }
+
+    /// Replace the vector status in the value of `sstatus`.
+    #[inline]
+    pub const fn to_sstatus(self, sstatus: usize) -> usize {
+        (sstatus & !SSTATUS_VS) | ((self as usize) << SSTATUS_VS_SHIFT)
+    }
+
+    /// Get the vector status of the current `sstatus`.
+    #[inline]
+    pub fn read() -> Self {
+        Self::from_sstatus(riscv::register::sstatus::read().bits())
+    }
+
+    /// Write the vector status to the current `sstatus`.
+    #[inline]
+    pub fn write(self) {
+        unsafe {
+            asm!(
+                "csrc sstatus, {mask}",
+                "csrs sstatus, {vs}",
+                mask = in(reg) SSTATUS_VS,
+                vs = in(reg) (self as usize) << SSTATUS_VS_SHIFT,
+            )
+        }
+    }
+}
+
+/// The length of the vector register in bytes, 0 if V is not supported.
+static VLENB: LazyInit<usize> = LazyInit::new();
+
+/// Check whether the V extension is declared in the device tree.
+fn detect_vector() -> bool {
+    let Ok(fdt) = get_fdt() else {
+        return false;
+    };
+    let supported = fdt.find_nodes("/cpus/cpu").any(|cpu| {
+        if let Some(exts) = cpu.find_property("riscv,isa-extensions") {
+            return exts.raw_value().split(|x| *x == 0).any(|x| x == b"v");
+        }
+        // Single letter extensions are before the first '_', like "rv64imafdcv_zicsr".
+        cpu.find_property("riscv,isa").is_some_and(|isa| {
+            let isa = isa.str();
+            isa.get(4..)
+                .and_then(|x| x.split('_').next())
+                .is_some_and(|x| x.contains(['v', 'V']))
+        })
+    });
+    supported
+}
+
+/// Initialize the vector unit on the current cpu.
+///
+/// Detect the V extension at the first call, enable `sstatus.VS` if it is supported.
+pub fn init() {
+    VLENB.call_once(|| match detect_vector() {
+        true => {
+            VS::Initial.write();
+            let vlenb: usize;
+            // vlenb is CSR 0xc22, readable once VS is not Off.
+            unsafe { asm!("csrr {}, 0xc22", out(reg) vlenb) };
+            vlenb
+        }
+        false => 0,
+    });
+    if vlenb() != 0 {
+        VS::Initial.write();
+    }
+}
+
+/// Get the length of the vector register in bytes, 0 if V is not supported.
+#[inline]
+pub fn vlenb() -> usize {
+    VLENB.get().copied().unwrap_or(0)
+}
+
+/// Get the number of the pages of the register buffer.
+#[inline]
+fn buffer_pages() -> usize {
+    (vlenb() * 32).div_ceil(PAGE_SIZE)
+}
+
+/// Vector registers `v0`-`v31` and `vstart/vtype/vl/vcsr` of a task.
+#[derive(Debug, Default)]
+pub struct VectorState {
+    pub vstart: usize,
+    pub vtype: usize,
+    pub vl: usize,
+    pub vcsr: usize,
+    /// Buffer of the vector registers, allocated at the first save.
+    buffer: Option<PhysAddr>,
+}
+
+impl VectorState {
+    /// Create a new vector state, the registers are zero.
+    pub const fn new() -> Self {
+        Self {
+            vstart: 0,
+            vtype: 0,
+            vl: 0,
+            vcsr: 0,
+            buffer: None,
+        }
+    }
+
+    /// Save the vector registers into the state.
+    pub fn save(&mut self) {
+        let buffer = *self
+            .buffer
+            .get_or_insert_with(|| frame_alloc_contiguous(buffer_pages()));
+        unsafe {
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "csrr       {vstart}, vstart",
+                "csrr       {vtype}, vtype",
+                "csrr       {vl}, vl",
+                "csrr       {vcsr}, vcsr",
+                "csrr       {step}, vlenb",
+                "slli       {step}, {step}, 3",
+                "vs8r.v     v0, ({buf})",
+                "add        {buf}, {buf}, {step}",
+                "vs8r.v     v8, ({buf})",
+                "add        {buf}, {buf}, {step}",
+                "vs8r.v     v16, ({buf})",
+                "add        {buf}, {buf}, {step}",
+                "vs8r.v     v24, ({buf})",
+                ".option pop",
+                buf = inout(reg) buffer.get_mut_ptr::<u8>() => _,
+                step = out(reg) _,
+                vstart = out(reg) self.vstart,
+                vtype = out(reg) self.vtype,
+                vl = out(reg) self.vl,
+                vcsr = out(reg) self.vcsr,
+            )
+        }
+    }
+
+    /// Restore the vector registers from the state.
+    ///
+    /// The registers are cleared if the state was never saved.
+    pub fn restore(&self) {
+        unsafe {
+            match self.buffer {
+                Some(buffer) => asm!(
+                    ".option push",
+                    ".option arch, +v",
+                    "csrr       {step}, vlenb",
+                    "slli       {step}, {step}, 3",
+                    "vl8r.v     v0, ({buf})",
+                    "add        {buf}, {buf}, {step}",
+                    "vl8r.v     v8, ({buf})",
+                    "add        {buf}, {buf}, {step}",
+                    "vl8r.v     v16, ({buf})",
+                    "add        {buf}, {buf}, {step}",
+                    "vl8r.v     v24, ({buf})",
+                    ".option pop",
+                    buf = inout(reg) buffer.get_mut_ptr::<u8>() => _,
+                    step = out(reg) _,
+                ),
+                None => asm!(
+                    ".option push",
+                    ".option arch, +v",
+                    "vsetvli    {tmp}, x0, e8, m8, ta, ma",
+                    "vmv.v.i    v0, 0",
+                    "vmv.v.i    v8, 0",
+                    "vmv.v.i    v16, 0",
+                    "vmv.v.i    v24, 0",
+                    ".option pop",
+                    tmp = out(reg) _,
+                ),
+            }
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "vsetvl     x0, {vl}, {vtype}",
+                "csrw       vstart, {vstart}",
+                "csrw       vcsr, {vcsr}",
+                ".option pop",
+                vl = in(reg) self.vl,
+                vtype = in(reg) self.vtype,
+                vstart = in(reg) self.vstart,
+                vcsr = in(reg) self.vcsr,
+            )
+        }
+    }
+}
+
+impl Clone for VectorState {
+    fn clone(&self) -> Self {
+        let buffer = self.buffer.map(|src| {
+            let dst = frame_alloc_contiguous(buffer_pages());
+            unsafe { copy_nonoverlapping(src.get_ptr::<u8>(), dst.get_mut_ptr(), vlenb() * 32) };
+            dst
+        });
+        Self { buffer, ..*self }
+    }
+}
+
+impl Drop for VectorState {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            frame_dealloc_contiguous(buffer, buffer_pages());
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use core::ptr::NonNull;
 
+use acpi::madt::{Madt, MadtEntry};
 use acpi::{AcpiHandler, AcpiTables};
+use spin::Once;
 
 use crate::{common::CPU_NUM, PhysAddr};
 
@@ -44,6 +46,67 @@ pub fn init() {
     }
 }
 
+/// The number of the ISA irqs.
+const ISA_IRQ_COUNT: usize = 16;
+
+/// The interrupt routing described by the MADT.
+#[derive(Default)]
+struct MadtInfo {
+    /// The I/O APIC which receives the GSI 0, the address and the first GSI.
+    io_apic: Option<(PhysAddr, u32)>,
+    /// The GSI and the MPS INTI flags of the ISA irqs overridden by the MADT.
+    isa_overrides: [Option<(u32, u16)>; ISA_IRQ_COUNT],
+}
+
+/// The MADT parsed at the first use, empty if there is no MADT.
+static MADT_INFO: Once<MadtInfo> = Once::new();
+
+/// Parse the MADT at the first call.
+fn madt_info() -> &'static MadtInfo {
+    MADT_INFO.call_once(|| {
+        let mut info = MadtInfo::default();
+        let madt = unsafe {
+            AcpiTables::search_for_rsdp_bios(AcpiImpl)
+                .ok()
+                .and_then(|tables| tables.find_table::<Madt>().ok())
+        };
+        let Some(madt) = madt else {
+            return info;
+        };
+        for entry in madt.get().entries() {
+            match entry {
+                MadtEntry::IoApic(io_apic) => {
+                    let gsi_base = io_apic.global_system_interrupt_base;
+                    if info.io_apic.is_none_or(|(_, base)| gsi_base < base) {
+                        info.io_apic = Some((pa!(io_apic.io_apic_address), gsi_base));
+                    }
+                }
+                MadtEntry::InterruptSourceOverride(entry) if entry.bus == 0 => {
+                    if let Some(slot) = info.isa_overrides.get_mut(entry.irq as usize) {
+                        *slot = Some((entry.global_system_interrupt, entry.flags));
+                    }
+                }
+                _ => {}
+            }
+        }
+        info
+    })
+}
+
+/// Get the I/O APIC which receives the GSI 0, the address and the first GSI.
+///
+/// Return [Option::None] if there is no MADT or I/O APIC.
+pub fn get_io_apic() -> Option<(PhysAddr, u32)> {
+    madt_info().io_apic
+}
+
+/// Get the GSI and the MPS INTI flags of the ISA irq if it's overridden by the MADT.
+///
+/// The ISA irqs which aren't overridden are identity mapped, edge-triggered and active high.
+pub fn get_isa_irq_override(irq: u8) -> Option<(u32, u16)> {
+    madt_info().isa_overrides.get(irq as usize).copied().flatten()
+}
+
 /// Get The Base Address Of The PCI
 ///
 /// Return [Option::None] if the pci is not exists or error
@@ -1,17 +1,22 @@
 use core::cmp;
 
 use self::vectors::*;
+use super::acpi::{get_io_apic, get_isa_irq_override};
 use super::consts::{PIC_VECTOR_OFFSET, VIRT_ADDR_START};
 use crate::utils::MutexNoIrq;
 use spin::Once;
-use x2apic::ioapic::{IoApic, RedirectionTableEntry};
+use x2apic::ioapic::{IoApic, IrqFlags, RedirectionTableEntry};
 use x2apic::lapic::{xapic_base, LocalApic, LocalApicBuilder};
 use x86_64::instructions::port::Port;
 
 pub mod vectors {
+    /// The vectors allocated to the MSIs, between the PIC IRQs and the APIC vectors.
+    pub const MSI_VECTOR_START: u8 = 0x30;
+    pub const MSI_VECTOR_END: u8 = 0xef;
     pub const APIC_TIMER_VECTOR: u8 = 0xf0;
     pub const APIC_SPURIOUS_VECTOR: u8 = 0xf1;
     pub const APIC_ERROR_VECTOR: u8 = 0xf2;
+    pub const APIC_IPI_VECTOR: u8 = 0xf3;
 }
 
 /// The maximum number of IRQs.
@@ -20,8 +25,14 @@ pub const MAX_IRQ_COUNT: usize = 256;
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = APIC_TIMER_VECTOR as usize;
 
+/// The I/O APIC of the PC, used if it isn't found in the MADT.
 const IO_APIC_BASE: u64 = 0xFEC0_0000;
 
+/// The I/O APIC inputs which are used, the input `n` is delivered to the
+/// vector `PIC_VECTOR_OFFSET + n` below the MSI vectors. The others are
+/// left masked.
+const IO_APIC_INPUTS: u8 = 0x10;
+
 static mut LOCAL_APIC: Option<LocalApic> = None;
 static mut IS_X2APIC: bool = false;
 static IO_APIC: Once<MutexNoIrq<IoApic>> = Once::new();
@@ -36,14 +47,38 @@ pub fn io_apic<'a>() -> &'a MutexNoIrq<IoApic> {
     IO_APIC.get().expect("Can't get io_apic")
 }
 
+/// Get the local APIC id of the CPU, the id of a CPU is its initial APIC id,
+/// see [hart_id](super::hart_id).
+#[inline]
+pub fn apic_id(cpu: usize) -> u32 {
+    cpu as u32
+}
+
+/// Get the destination of the ICR for the CPU.
 pub fn raw_apic_id(id_u8: u8) -> u32 {
+    let id = apic_id(id_u8 as usize);
     if unsafe { IS_X2APIC } {
-        id_u8 as u32
+        id
     } else {
-        (id_u8 as u32) << 24
+        id << 24
     }
 }
 
+/// Check if the vector is requested in the IRR of the local APIC of the current CPU.
+pub fn local_apic_pending(vector: u8) -> bool {
+    let index = vector as usize / 32;
+    let irr = unsafe {
+        match IS_X2APIC {
+            true => x86::msr::rdmsr(0x820 + index as u32) as u32,
+            false => {
+                let irr = (xapic_base() as usize | VIRT_ADDR_START) + 0x200 + index * 0x10;
+                (irr as *const u32).read_volatile()
+            }
+        }
+    };
+    irr & (1 << (vector % 32)) != 0
+}
+
 /// Check if the current cpu supports the x2apic
 fn cpu_has_x2apic() -> bool {
     match raw_cpuid::CpuId::new().get_feature_info() {
@@ -103,11 +138,49 @@ pub fn init() {
         LOCAL_APIC = Some(lapic);
     }
 
-    let mut io_apic = unsafe { IoApic::new(IO_APIC_BASE) };
+    // Initialize the IO_APIC
+    IO_APIC.call_once(|| MutexNoIrq::new(init_io_apic()));
+}
+
+/// Convert the MPS INTI flags of the MADT, the conforming ISA irqs are edge-triggered and active high.
+fn mps_inti_flags(flags: u16) -> IrqFlags {
+    let mut irq_flags = IrqFlags::empty();
+    if flags & 0b11 == 0b11 {
+        irq_flags |= IrqFlags::LOW_ACTIVE;
+    }
+    if (flags >> 2) & 0b11 == 0b11 {
+        irq_flags |= IrqFlags::LEVEL_TRIGGERED;
+    }
+    irq_flags
+}
+
+/// Get the input of the I/O APIC which receives the ISA irq.
+pub fn isa_irq_input(irq: u8) -> usize {
+    let gsi_base = get_io_apic().map_or(0, |(_, gsi_base)| gsi_base);
+    match get_isa_irq_override(irq) {
+        Some((gsi, _)) => gsi.saturating_sub(gsi_base) as usize,
+        None => irq as usize,
+    }
+}
+
+/// Get the number of the I/O APIC inputs programmed at init, it's less
+/// than [IO_APIC_INPUTS] if the I/O APIC has fewer redirection entries.
+pub fn io_apic_inputs(io_apic: &mut IoApic) -> u8 {
+    let entries = unsafe { io_apic.max_table_entry() }.saturating_add(1);
+    cmp::min(entries, IO_APIC_INPUTS)
+}
+
+/// Find the I/O APIC in the MADT, it's at [IO_APIC_BASE] if there is no MADT.
+fn init_io_apic() -> IoApic {
+    let (base, gsi_base) = get_io_apic().map_or((IO_APIC_BASE, 0), |(base, gsi_base)| {
+        (base.raw() as u64, gsi_base)
+    });
+    let mut io_apic = unsafe { IoApic::new(base) };
+    let inputs = io_apic_inputs(&mut io_apic);
     // Remap the PIC irqs, Default disabled.
-    for irq in 0..cmp::min(unsafe { io_apic.max_table_entry() }, 0x10) {
+    for irq in 0..inputs {
         let mut entry = RedirectionTableEntry::default();
-        entry.set_vector(0x20 + irq);
+        entry.set_vector(PIC_VECTOR_OFFSET + irq);
         entry.set_dest(0); // CPU(s)
 
         // Set table entry and set it disabled.
@@ -117,6 +190,19 @@ pub fn init() {
         }
     }
 
-    // Initialize the IO_APIC
-    IO_APIC.call_once(|| MutexNoIrq::new(io_apic));
+    // Apply the trigger mode of the ISA irqs overridden by the MADT.
+    for isa_irq in 0..0x10 {
+        let Some((gsi, flags)) = get_isa_irq_override(isa_irq) else {
+            continue;
+        };
+        let Some(input) = gsi.checked_sub(gsi_base).filter(|input| *input < inputs as u32) else {
+            continue;
+        };
+        unsafe {
+            let mut entry = io_apic.table_entry(input as u8);
+            entry.set_flags(mps_inti_flags(flags) | IrqFlags::MASKED);
+            io_apic.set_table_entry(input as u8, entry);
+        }
+    }
+    io_apic
 }
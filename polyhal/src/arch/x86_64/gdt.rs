@@ -13,6 +13,21 @@ pub(super) static GDT: LazyInit<GdtStruct> = LazyInit::new();
 #[polyhal_macro::percpu]
 pub(super) static TSS: LazyInit<TaskStateSegment> = LazyInit::new();
 
+/// The size of the each interrupt stack in the IST.
+const IST_STACK_SIZE: usize = 0x2000;
+
+/// IST index of the double fault (#DF) stack.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// IST index of the non-maskable interrupt (#NMI) stack.
+pub const NMI_IST_INDEX: u16 = 1;
+/// IST index of the machine check (#MC) stack.
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+/// Interrupt stacks used by the exceptions which can't trust the current
+/// stack, such as the double fault caused by a kernel stack overflow.
+#[polyhal_macro::percpu]
+static IST_STACKS: [[u8; IST_STACK_SIZE]; 3] = [[0; IST_STACK_SIZE]; 3];
+
 /// A wrapper of the Global Descriptor Table (GDT) with maximum 16 entries.
 #[repr(align(16))]
 pub struct GdtStruct {
@@ -98,6 +113,13 @@ impl fmt::Debug for GdtStruct {
 pub fn init() {
     unsafe {
         TSS.init_once(TaskStateSegment::new());
+        let ist_stacks = IST_STACKS.get_mut_ptr() as usize;
+        let mut ist = TSS.interrupt_stack_table;
+        ist.iter_mut().take(3).enumerate().for_each(|(i, x)| {
+            let top = (ist_stacks + (i + 1) * IST_STACK_SIZE) & !0xf;
+            *x = VirtAddr::new(top as _);
+        });
+        TSS.ref_mut().get_mut_unchecked().interrupt_stack_table = ist;
         GDT.init_once(GdtStruct::new(TSS.ref_mut()));
         GDT.load();
         GDT.load_tss();
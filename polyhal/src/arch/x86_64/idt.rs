@@ -1,8 +1,11 @@
 use core::mem::transmute;
 
 use lazyinit::LazyInit;
+use x86::irq::{DOUBLE_FAULT_VECTOR, MACHINE_CHECK_VECTOR, NONMASKABLE_INTERRUPT_VECTOR};
 use x86_64::structures::idt::{Entry, HandlerFunc, InterruptDescriptorTable, InterruptStackFrame};
 
+use super::gdt::{DOUBLE_FAULT_IST_INDEX, MACHINE_CHECK_IST_INDEX, NMI_IST_INDEX};
+
 const NUM_INT: usize = 256;
 
 pub(super) static IDT: LazyInit<IdtStruct> = LazyInit::new();
@@ -28,11 +31,19 @@ impl IdtStruct {
             )
         };
         for i in 0..NUM_INT {
-            entries[i].set_handler_fn(unsafe {
+            let opts = entries[i].set_handler_fn(unsafe {
                 transmute::<extern "C" fn(), extern "x86-interrupt" fn(InterruptStackFrame)>(
                     ENTRIES[i],
                 )
             });
+            // Switch to the interrupt stacks, the current stack may be broken.
+            let ist_index = match i as u8 {
+                DOUBLE_FAULT_VECTOR => DOUBLE_FAULT_IST_INDEX,
+                NONMASKABLE_INTERRUPT_VECTOR => NMI_IST_INDEX,
+                MACHINE_CHECK_VECTOR => MACHINE_CHECK_IST_INDEX,
+                _ => continue,
+            };
+            unsafe { opts.set_stack_index(ist_index) };
         }
         idt
     }
@@ -3,6 +3,7 @@ pub mod apic;
 pub mod consts;
 pub mod gdt;
 pub mod idt;
+pub mod xsave;
 
 pub fn hart_id() -> usize {
     match raw_cpuid::CpuId::new().get_feature_info() {
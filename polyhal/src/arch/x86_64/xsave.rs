@@ -0,0 +1,361 @@
+//! x86_64 extended processor state.
+//!
+//! The extended state is saved by `XSAVEOPT`/`XSAVE` and restored by `XRSTOR`
+//! if the processor supports `XSAVE`, otherwise by `FXSAVE`/`FXRSTOR`.
+//! The size of the save area is given by the CPUID leaf 0xD after the
+//! enabled features were written into `XCR0`.
+
+use core::{
+    arch::asm,
+    fmt::Debug,
+    ptr::{copy_nonoverlapping, write_bytes},
+};
+
+use lazyinit::LazyInit;
+use raw_cpuid::{cpuid, CpuId};
+use x86_64::registers::{
+    control::{Cr4, Cr4Flags},
+    xcontrol::{XCr0, XCr0Flags},
+};
+
+use crate::{
+    common::{frame_alloc, frame_dealloc},
+    pagetable::PAGE_SIZE,
+    PhysAddr,
+};
+
+/// The legacy region of the save area, the layout of `FXSAVE`.
+#[repr(C, align(16))]
+#[derive(Clone)]
+pub struct FxsaveArea {
+    pub fcw: u16,
+    pub fsw: u16,
+    pub ftw: u16,
+    pub fop: u16,
+    pub fip: u64,
+    pub fdp: u64,
+    pub mxcsr: u32,
+    pub mxcsr_mask: u32,
+    pub st: [u64; 16],
+    pub xmm: [u64; 32],
+    _padding: [u64; 12],
+}
+
+impl FxsaveArea {
+    /// The initial state of the legacy region.
+    const INIT: Self = Self {
+        fcw: 0x37f,
+        fsw: 0,
+        ftw: 0xffff,
+        fop: 0,
+        fip: 0,
+        fdp: 0,
+        mxcsr: 0x1f80,
+        mxcsr_mask: 0,
+        st: [0; 16],
+        xmm: [0; 32],
+        _padding: [0; 12],
+    };
+
+    #[inline]
+    pub fn save(&mut self) {
+        unsafe { core::arch::x86_64::_fxsave64(self as *mut _ as *mut u8) }
+    }
+
+    #[inline]
+    pub fn restore(&self) {
+        unsafe { core::arch::x86_64::_fxrstor64(self as *const _ as *const u8) }
+    }
+}
+
+impl Default for FxsaveArea {
+    fn default() -> Self {
+        Self::INIT
+    }
+}
+
+impl Debug for FxsaveArea {
+    fn fmt(&self, _f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        Ok(())
+    }
+}
+
+/// The initial save area, the `XSAVE` header after the legacy region is zero,
+/// so `XRSTOR` puts every enabled component into its initial state.
+#[repr(C, align(64))]
+struct InitArea {
+    legacy: FxsaveArea,
+    header: [u64; 8],
+}
+
+static INIT_AREA: InitArea = InitArea {
+    legacy: FxsaveArea::INIT,
+    header: [0; 8],
+};
+
+/// The extended state features supported by the kernel.
+const XFEATURE_SUPPORTED: u64 = XCr0Flags::X87.bits()
+    | XCr0Flags::SSE.bits()
+    | XCr0Flags::AVX.bits()
+    | XCR0_AVX512;
+
+/// AVX-512 state components, they must be enabled together.
+const XCR0_AVX512: u64 =
+    XCr0Flags::OPMASK.bits() | XCr0Flags::ZMM_HI256.bits() | XCr0Flags::HI16_ZMM.bits();
+
+/// Extended state information of the processor.
+#[derive(Debug, Clone, Copy)]
+struct XStateInfo {
+    /// Features enabled in `XCR0`, 0 if `XSAVE` is not supported.
+    mask: u64,
+    /// Size of the save area in bytes.
+    size: usize,
+    /// Whether `XSAVEOPT` is supported.
+    xsaveopt: bool,
+}
+
+static XSTATE: LazyInit<XStateInfo> = LazyInit::new();
+
+/// Enable the extended state features on the current cpu.
+///
+/// The features are detected at the first call, X87, SSE, AVX and AVX-512
+/// are enabled in `XCR0` if they are supported.
+pub fn init() {
+    let has_xsave = CpuId::new()
+        .get_feature_info()
+        .is_some_and(|x| x.has_xsave());
+    if !has_xsave {
+        XSTATE.call_once(|| XStateInfo {
+            mask: 0,
+            size: size_of::<FxsaveArea>(),
+            xsaveopt: false,
+        });
+        return;
+    }
+    unsafe { Cr4::update(|x| x.insert(Cr4Flags::OSXSAVE)) };
+    XSTATE.call_once(|| {
+        let leaf = cpuid!(0xd, 0);
+        let mut mask = XFEATURE_SUPPORTED & (leaf.eax as u64 | ((leaf.edx as u64) << 32));
+        // AVX needs SSE, AVX-512 needs AVX and all of its components.
+        if mask & XCr0Flags::AVX.bits() == 0 || mask & XCR0_AVX512 != XCR0_AVX512 {
+            mask &= !XCR0_AVX512;
+        }
+        let mask = XCr0Flags::from_bits_truncate(mask);
+        unsafe { XCr0::write(mask) };
+        // EBX is the size of the area required by the features enabled in XCR0.
+        let size = cpuid!(0xd, 0).ebx as usize;
+        assert!(size <= PAGE_SIZE, "XSAVE area size {:#x} is too large", size);
+        XStateInfo {
+            mask: mask.bits(),
+            size,
+            xsaveopt: cpuid!(0xd, 1).eax & 1 != 0,
+        }
+    });
+    // Secondary cpus enable the same features as the boot cpu.
+    unsafe { XCr0::write(XCr0Flags::from_bits_truncate(xfeature_mask())) };
+}
+
+/// Check whether the extended state is saved by `XSAVE`.
+#[inline]
+pub fn xsave_enabled() -> bool {
+    xfeature_mask() != 0
+}
+
+/// Get the extended state features enabled in `XCR0`, 0 if `XSAVE` is not used.
+#[inline]
+pub fn xfeature_mask() -> u64 {
+    XSTATE.get().map_or(0, |x| x.mask)
+}
+
+/// Get the size of the save area in bytes.
+#[inline]
+pub fn xstate_size() -> usize {
+    XSTATE.get().map_or(size_of::<FxsaveArea>(), |x| x.size)
+}
+
+/// Extended processor state of a task.
+///
+/// The save area is allocated at the first save, the initial state
+/// is restored if it was never saved.
+pub struct ExtendedState {
+    /// Virtual address of the save area, 0 if not allocated.
+    area: usize,
+}
+
+impl ExtendedState {
+    /// Create a new extended state in the initial state.
+    pub const fn new() -> Self {
+        Self { area: 0 }
+    }
+
+    /// Get the legacy region of the save area, `None` if it was never saved.
+    pub fn legacy(&self) -> Option<&FxsaveArea> {
+        match self.area {
+            0 => None,
+            area => Some(unsafe { &*(area as *const FxsaveArea) }),
+        }
+    }
+
+    /// Get the mutable legacy region of the save area.
+    ///
+    /// The area is allocated in the initial state if it was never saved,
+    /// the x87 and SSE states are marked in use, so `XRSTOR` loads the region.
+    pub fn legacy_mut(&mut self) -> &mut FxsaveArea {
+        let area = self.alloc_area();
+        if xsave_enabled() {
+            // XSTATE_BV is the first field of the header after the legacy region.
+            let xstate_bv = (area + size_of::<FxsaveArea>()) as *mut u64;
+            unsafe { *xstate_bv |= XCr0Flags::X87.bits() | XCr0Flags::SSE.bits() };
+        }
+        unsafe { &mut *(area as *mut FxsaveArea) }
+    }
+
+    /// Replace the legacy region, the other components keep their state.
+    ///
+    /// The reserved bits of `MXCSR` are cleared, as they raise #GP when restoring.
+    pub fn set_legacy(&mut self, legacy: &FxsaveArea) {
+        let mxcsr_mask = self.mxcsr_mask();
+        let area = self.legacy_mut();
+        *area = legacy.clone();
+        area.mxcsr &= mxcsr_mask;
+        area.mxcsr_mask = mxcsr_mask;
+    }
+
+    /// Copy the save area to `dst` in the standard format of `XSAVE`,
+    /// [xstate_size] bytes are written.
+    ///
+    /// # Safety
+    ///
+    /// `dst` must be valid for writes of [xstate_size] bytes.
+    pub unsafe fn copy_to(&self, dst: *mut u8) {
+        match self.area {
+            0 => {
+                write_bytes(dst, 0, xstate_size());
+                (dst as *mut FxsaveArea).write_unaligned(FxsaveArea::INIT);
+            }
+            area => copy_nonoverlapping(area as *const u8, dst, xstate_size()),
+        }
+    }
+
+    /// Replace the save area with [xstate_size] bytes at `src` in the standard
+    /// format of `XSAVE`, which may be written by the user task.
+    ///
+    /// The features not enabled in `XCR0`, the reserved bits of `MXCSR` and the
+    /// reserved fields of the `XSAVE` header are cleared, as they raise #GP
+    /// when restoring.
+    ///
+    /// # Safety
+    ///
+    /// `src` must be valid for reads of [xstate_size] bytes.
+    pub unsafe fn copy_from(&mut self, src: *const u8) {
+        let mxcsr_mask = self.mxcsr_mask();
+        let area = self.alloc_area();
+        copy_nonoverlapping(src, area as *mut u8, xstate_size());
+        let legacy = &mut *(area as *mut FxsaveArea);
+        legacy.mxcsr &= mxcsr_mask;
+        legacy.mxcsr_mask = mxcsr_mask;
+        if xsave_enabled() {
+            // XSTATE_BV is followed by XCOMP_BV and the reserved bytes of the header.
+            let header = (area + size_of::<FxsaveArea>()) as *mut u64;
+            *header &= xfeature_mask();
+            write_bytes(header.add(1), 0, 7);
+        }
+    }
+
+    /// The valid bits of `MXCSR` saved by the cpu, the default mask is 0xffbf.
+    fn mxcsr_mask(&self) -> u32 {
+        match self.legacy().map_or(0, |x| x.mxcsr_mask) {
+            0 => 0xffbf,
+            mask => mask,
+        }
+    }
+
+    /// Allocate the save area in the initial state if it wasn't allocated.
+    fn alloc_area(&mut self) -> usize {
+        if self.area == 0 {
+            let area = frame_alloc().get_mut_ptr::<u8>();
+            unsafe {
+                write_bytes(area, 0, PAGE_SIZE);
+                (area as *mut FxsaveArea).write(FxsaveArea::INIT);
+            }
+            self.area = area as usize;
+        }
+        self.area
+    }
+
+    /// Save the extended state of the current cpu.
+    pub fn save(&mut self) {
+        self.alloc_area();
+        let Some(info) = XSTATE.get().filter(|x| x.mask != 0) else {
+            unsafe { (*(self.area as *mut FxsaveArea)).save() };
+            return;
+        };
+        unsafe {
+            match info.xsaveopt {
+                true => asm!(
+                    "xsaveopt64 [{}]",
+                    in(reg) self.area,
+                    in("eax") info.mask as u32,
+                    in("edx") (info.mask >> 32) as u32,
+                ),
+                false => asm!(
+                    "xsave64 [{}]",
+                    in(reg) self.area,
+                    in("eax") info.mask as u32,
+                    in("edx") (info.mask >> 32) as u32,
+                ),
+            }
+        }
+    }
+
+    /// Restore the extended state to the current cpu.
+    pub fn restore(&self) {
+        let area = match self.area {
+            0 => &INIT_AREA as *const InitArea as usize,
+            area => area,
+        };
+        let Some(info) = XSTATE.get().filter(|x| x.mask != 0) else {
+            unsafe { (*(area as *const FxsaveArea)).restore() };
+            return;
+        };
+        unsafe {
+            asm!(
+                "xrstor64 [{}]",
+                in(reg) area,
+                in("eax") info.mask as u32,
+                in("edx") (info.mask >> 32) as u32,
+            )
+        }
+    }
+}
+
+impl Default for ExtendedState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for ExtendedState {
+    fn clone(&self) -> Self {
+        if self.area == 0 {
+            return Self::new();
+        }
+        let area = frame_alloc().get_mut_ptr::<u8>();
+        unsafe { copy_nonoverlapping(self.area as *const u8, area, xstate_size()) };
+        Self { area: area as _ }
+    }
+}
+
+impl Drop for ExtendedState {
+    fn drop(&mut self) {
+        if self.area != 0 {
+            frame_dealloc(PhysAddr::new(self.area & !crate::consts::VIRT_ADDR_START));
+        }
+    }
+}
+
+impl Debug for ExtendedState {
+    fn fmt(&self, _f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! Stack backtrace module.
+//!
+//! Walk the kernel stack through the frame pointers. The frame pointers
+//! must be enabled with `-C force-frame-pointers=yes`, otherwise only the
+//! first frame is reported and the build script gives a warning. The
+//! `.cargo/config.toml` of the workspace enables it for the bare-metal
+//! targets, pass it in `RUSTFLAGS` if they are overridden.
+//!
+//! The walk stops at the frame pointer out of the stack, so a corrupted
+//! frame can't fault in the walker.
+//!
+//! The return addresses can be symbolized if the symbol table was embedded
+//! at build time. Set `POLYHAL_SYMBOL_TABLE` to the output of `nm -n -C`
+//! of the previous kernel build and build again, the text section will not
+//! move because the table is placed into the rodata section.
+//!
+//! ```rust
+//! use polyhal::backtrace::Backtrace;
+//!
+//! println!("{}", Backtrace::current());
+//! ```
+
+use core::fmt::{self, Display};
+use core::ops::Range;
+
+/// The maximum number of the frames will be walked.
+const MAX_DEPTH: usize = 64;
+
+/// The size of the stack above the first frame pointer, if the stack isn't given.
+const DEFAULT_STACK_SPAN: usize = 0x10_0000;
+
+/// The size of the frame record, the return address and the previous frame pointer.
+const FRAME_RECORD_SIZE: usize = 2 * size_of::<usize>();
+
+// Symbol table generated by the build script, sorted by the address.
+include!(concat!(env!("OUT_DIR"), "/symbols.rs"));
+
+/// Frame pointer based stack walker.
+///
+/// Yield the return addresses, the first one is the pc where the walk starts.
+#[derive(Debug, Clone)]
+pub struct Backtrace {
+    pc: usize,
+    fp: usize,
+    depth: usize,
+    /// The frame records must be in the stack.
+    stack: Range<usize>,
+}
+
+impl Backtrace {
+    /// Create a walker start from the given pc and frame pointer.
+    ///
+    /// It can be used to walk the stack of the trap context. The stack is
+    /// assumed to be at most 1 MiB above the frame pointer, use
+    /// [Backtrace::with_stack] if the stack is known.
+    pub const fn new(pc: usize, fp: usize) -> Self {
+        let stack = fp.saturating_sub(FRAME_RECORD_SIZE)..fp.saturating_add(DEFAULT_STACK_SPAN);
+        Self::with_stack(pc, fp, stack)
+    }
+
+    /// Create a walker start from the given pc and frame pointer, in the given stack.
+    pub const fn with_stack(pc: usize, fp: usize, stack: Range<usize>) -> Self {
+        Self {
+            pc,
+            fp,
+            depth: 0,
+            stack,
+        }
+    }
+
+    /// Create a walker start from the caller of this function.
+    ///
+    /// Nothing will be yielded if the frame pointers are not forced.
+    #[inline(never)]
+    pub fn current() -> Self {
+        if !cfg!(frame_pointers) {
+            return Self::new(0, 0);
+        }
+        let fp: usize;
+        unsafe {
+            cfg_if::cfg_if! {
+                if #[cfg(target_arch = "x86_64")] {
+                    core::arch::asm!("mov {}, rbp", out(reg) fp)
+                } else if #[cfg(target_arch = "riscv64")] {
+                    core::arch::asm!("mv {}, s0", out(reg) fp)
+                } else if #[cfg(target_arch = "aarch64")] {
+                    core::arch::asm!("mov {}, x29", out(reg) fp)
+                } else if #[cfg(target_arch = "loongarch64")] {
+                    core::arch::asm!("move {}, $fp", out(reg) fp)
+                }
+            }
+        }
+        // Skip the frame of this function.
+        let (pc, fp) = unsafe { read_frame(fp) };
+        Self::new(pc, fp)
+    }
+}
+
+/// Get the address range of the frame record of the frame pointer.
+#[inline(always)]
+fn frame_record(fp: usize) -> Option<Range<usize>> {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))] {
+            Some(fp..fp.checked_add(FRAME_RECORD_SIZE)?)
+        } else {
+            Some(fp.checked_sub(FRAME_RECORD_SIZE)?..fp)
+        }
+    }
+}
+
+/// Read the return address and the previous frame pointer in the frame.
+///
+/// # Safety
+///
+/// `fp` must point to a valid stack frame.
+#[inline(always)]
+unsafe fn read_frame(fp: usize) -> (usize, usize) {
+    let fp = fp as *const usize;
+    cfg_if::cfg_if! {
+        // The frame record is above the frame pointer.
+        if #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))] {
+            (fp.add(1).read(), fp.read())
+        // The frame record is below the frame pointer.
+        } else {
+            (fp.sub(1).read(), fp.sub(2).read())
+        }
+    }
+}
+
+impl Iterator for Backtrace {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pc == 0 || self.depth >= MAX_DEPTH {
+            return None;
+        }
+        let pc = self.pc;
+        self.depth += 1;
+        // The frame pointer is unreliable without forcing the frame pointers.
+        let valid = self.fp != 0
+            && self.fp % size_of::<usize>() == 0
+            && frame_record(self.fp).is_some_and(|record| {
+                self.stack.start <= record.start && record.end <= self.stack.end
+            });
+        if !cfg!(frame_pointers) || !valid {
+            self.pc = 0;
+            return Some(pc);
+        }
+        let (ra, fp) = unsafe { read_frame(self.fp) };
+        // The stack grows down, the caller's frame must be above.
+        self.pc = match fp > self.fp || fp == 0 {
+            true => ra,
+            false => 0,
+        };
+        self.fp = fp;
+        Some(pc)
+    }
+}
+
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+        for (i, pc) in self.clone().enumerate() {
+            match symbolize(pc) {
+                Some((name, offset)) => {
+                    writeln!(f, "  #{:<2} {:#018x} {}+{:#x}", i, pc, name, offset)?
+                }
+                None => writeln!(f, "  #{:<2} {:#018x}", i, pc)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Find the symbol which contains the address.
+///
+/// Return the symbol name and the offset of the address in the symbol.
+/// Return `None` if the symbol table was not embedded or not found.
+pub fn symbolize(addr: usize) -> Option<(&'static str, usize)> {
+    // Keep the code same whether the symbol table is empty or not.
+    let symbols = core::hint::black_box(SYMBOLS);
+    let index = symbols.partition_point(|x| x.0 <= addr).checked_sub(1)?;
+    let (start, name) = symbols[index];
+    Some((name, addr - start))
+}
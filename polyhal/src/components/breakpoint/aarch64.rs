@@ -0,0 +1,162 @@
+use core::arch::asm;
+
+use aarch64_cpu::registers::{Writeable, OSLAR_EL1};
+
+use super::{check_watch_range, BreakpointError, WatchKind};
+
+/// Monitor debug events, `MDSCR_EL1.MDE`.
+const MDSCR_MDE: usize = 1 << 15;
+/// Local debug events at the kernel, `MDSCR_EL1.KDE`.
+const MDSCR_KDE: usize = 1 << 13;
+/// Software step, `MDSCR_EL1.SS`.
+const MDSCR_SS: usize = 1 << 0;
+
+/// Enable the breakpoint or watchpoint, `DBGBCR.E`/`DBGWCR.E`.
+const CTRL_ENABLE: usize = 1;
+/// Match at EL0 only, `DBGBCR.PMC`/`DBGWCR.PAC`.
+const CTRL_EL0: usize = 0b10 << 1;
+/// Match all the bytes of an A64 instruction, `DBGBCR.BAS`.
+const BCR_BAS_A64: usize = 0b1111 << 5;
+
+/// Write the debug register `$reg<n>_el1`.
+macro_rules! write_dbg_reg {
+    ($reg:literal, $n:expr, $val:expr) => {
+        write_dbg_reg!(@arms $reg, $n, $val, 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)
+    };
+    (@arms $reg:literal, $n:expr, $val:expr, $($i:literal)*) => {
+        match $n {
+            $($i => asm!(concat!("msr ", $reg, stringify!($i), "_el1, {}"), in(reg) $val),)*
+            _ => unreachable!(),
+        }
+    };
+}
+
+#[inline]
+fn read_mdscr() -> usize {
+    let mdscr;
+    unsafe { asm!("mrs {}, mdscr_el1", out(reg) mdscr) };
+    mdscr
+}
+
+#[inline]
+fn write_mdscr(mdscr: usize) {
+    unsafe { asm!("msr mdscr_el1, {}", "isb", in(reg) mdscr) };
+}
+
+#[inline]
+fn read_dfr0() -> usize {
+    let dfr0;
+    unsafe { asm!("mrs {}, id_aa64dfr0_el1", out(reg) dfr0) };
+    dfr0
+}
+
+/// Get the number of the hardware breakpoints, `ID_AA64DFR0_EL1.BRPs` + 1.
+#[inline]
+pub fn breakpoint_slots() -> usize {
+    ((read_dfr0() >> 12) & 0xf) + 1
+}
+
+/// Get the number of the hardware watchpoints, `ID_AA64DFR0_EL1.WRPs` + 1.
+#[inline]
+pub fn watchpoint_slots() -> usize {
+    ((read_dfr0() >> 20) & 0xf) + 1
+}
+
+/// Set the hardware breakpoint `slot` at the instruction `addr`.
+///
+/// The breakpoints only match in user mode.
+pub fn set_breakpoint(slot: usize, addr: usize) -> Result<(), BreakpointError> {
+    if slot >= breakpoint_slots() {
+        return Err(BreakpointError::InvalidSlot);
+    }
+    if addr % 4 != 0 {
+        return Err(BreakpointError::InvalidRange);
+    }
+    unsafe {
+        write_dbg_reg!("dbgbvr", slot, addr);
+        write_dbg_reg!("dbgbcr", slot, BCR_BAS_A64 | CTRL_EL0 | CTRL_ENABLE);
+        asm!("isb");
+    }
+    Ok(())
+}
+
+/// Set the hardware watchpoint `slot` on `len` bytes at `addr`.
+///
+/// The watchpoints only match in user mode.
+pub fn set_watchpoint(
+    slot: usize,
+    addr: usize,
+    len: usize,
+    kind: WatchKind,
+) -> Result<(), BreakpointError> {
+    if slot >= watchpoint_slots() {
+        return Err(BreakpointError::InvalidSlot);
+    }
+    check_watch_range(addr, len)?;
+    let lsc = match kind {
+        WatchKind::Read => 0b01,
+        WatchKind::Write => 0b10,
+        WatchKind::ReadWrite => 0b11,
+    };
+    // The byte address select of the doubleword at the aligned address.
+    let bas = ((1 << len) - 1) << (addr % 8);
+    unsafe {
+        write_dbg_reg!("dbgwvr", slot, addr & !7);
+        write_dbg_reg!("dbgwcr", slot, (bas << 5) | (lsc << 3) | CTRL_EL0 | CTRL_ENABLE);
+        asm!("isb");
+    }
+    Ok(())
+}
+
+/// Clear the hardware breakpoint `slot`.
+#[inline]
+pub fn clear_breakpoint(slot: usize) {
+    if slot < breakpoint_slots() {
+        unsafe { write_dbg_reg!("dbgbcr", slot, 0usize) };
+    }
+}
+
+/// Clear the hardware watchpoint `slot`.
+#[inline]
+pub fn clear_watchpoint(slot: usize) {
+    if slot < watchpoint_slots() {
+        unsafe { write_dbg_reg!("dbgwcr", slot, 0usize) };
+    }
+}
+
+/// Enable or disable the software step of the current cpu, `MDSCR_EL1.SS`.
+///
+/// The step takes effect when returning to a lower level with `SPSR_EL1.SS` set.
+#[inline]
+pub fn set_single_step(enable: bool) {
+    let mdscr = read_mdscr();
+    match enable {
+        true => write_mdscr(mdscr | MDSCR_SS),
+        false => write_mdscr(mdscr & !MDSCR_SS),
+    }
+}
+
+/// Enable or disable the software step of the kernel, `MDSCR_EL1.KDE` and `MDSCR_EL1.SS`.
+///
+/// The step takes effect when returning to the kernel with `SPSR_EL1.SS` set
+/// and `SPSR_EL1.D` clear.
+#[inline]
+pub fn set_kernel_step(enable: bool) {
+    let mdscr = read_mdscr();
+    match enable {
+        true => write_mdscr(mdscr | MDSCR_KDE | MDSCR_SS),
+        false => write_mdscr(mdscr & !(MDSCR_KDE | MDSCR_SS)),
+    }
+}
+
+/// Unlock the OS lock and enable the debug events of the current cpu.
+pub(crate) fn init() {
+    OSLAR_EL1.set(0);
+    for slot in 0..breakpoint_slots() {
+        clear_breakpoint(slot);
+    }
+    for slot in 0..watchpoint_slots() {
+        clear_watchpoint(slot);
+    }
+    write_mdscr((read_mdscr() | MDSCR_MDE) & !(MDSCR_KDE | MDSCR_SS));
+}
@@ -0,0 +1,224 @@
+use core::arch::asm;
+
+use super::{check_watch_range, BreakpointError, DebugHit, WatchKind};
+
+/// Memory watchpoint config and status.
+const CSR_MWPC: usize = 0x300;
+const CSR_MWPS: usize = 0x301;
+/// Fetch watchpoint config and status.
+const CSR_FWPC: usize = 0x380;
+const CSR_FWPS: usize = 0x381;
+/// `MWPnCFG1`, the registers of the slot `n` are at `+ 8 * n`.
+const CSR_MWP_CFG: usize = 0x310;
+/// `FWPnCFG1`, the registers of the slot `n` are at `+ 8 * n`.
+const CSR_FWP_CFG: usize = 0x390;
+
+/// Offsets of the address, mask and control registers in the slot.
+const CFG_ADDR: usize = 0;
+const CFG_MASK: usize = 1;
+const CFG_CTRL: usize = 2;
+
+/// Match at PLV3 only.
+const CTRL_PLV3: usize = 1 << 4;
+const CTRL_LOAD: usize = 1 << 8;
+const CTRL_STORE: usize = 1 << 9;
+const CTRL_LEN_SHIFT: usize = 10;
+
+/// Skip the next watchpoint exception, in `MWPS` and `FWPS`.
+const WPS_SKIP: usize = 1 << 16;
+
+/// The maximum number of the slots managed on a cpu.
+const MAX_SLOTS: usize = 8;
+
+/// The single-step was completed before it was disabled.
+#[polyhal_macro::percpu]
+static STEP_HIT: bool = false;
+
+macro_rules! read_csr {
+    ($csr:expr) => {{
+        let value: usize;
+        asm!("csrrd {}, {}", out(reg) value, const $csr);
+        value
+    }};
+}
+
+macro_rules! write_csr {
+    ($csr:expr, $val:expr) => {
+        asm!("csrwr {}, {}", inout(reg) $val => _, const $csr)
+    };
+}
+
+/// Read the register `$reg` of the watchpoint `$slot` in the bank at `$cfg`.
+macro_rules! read_slot_csr {
+    ($cfg:expr, $slot:expr, $reg:expr) => {
+        read_slot_csr!(@arms $cfg, $slot, $reg, 0 1 2 3 4 5 6 7)
+    };
+    (@arms $cfg:expr, $slot:expr, $reg:expr, $($i:literal)*) => {
+        match $slot {
+            $($i => read_csr!($cfg + $i * 8 + $reg),)*
+            _ => unreachable!(),
+        }
+    };
+}
+
+/// Write the register `$reg` of the watchpoint `$slot` in the bank at `$cfg`.
+macro_rules! write_slot_csr {
+    ($cfg:expr, $slot:expr, $reg:expr, $val:expr) => {
+        write_slot_csr!(@arms $cfg, $slot, $reg, $val, 0 1 2 3 4 5 6 7)
+    };
+    (@arms $cfg:expr, $slot:expr, $reg:expr, $val:expr, $($i:literal)*) => {
+        match $slot {
+            $($i => write_csr!($cfg + $i * 8 + $reg, $val),)*
+            _ => unreachable!(),
+        }
+    };
+}
+
+/// Get the number of the fetch watchpoints, including the one of the single-step.
+#[inline]
+fn fetch_slots() -> usize {
+    (unsafe { read_csr!(CSR_FWPC) } & 0x3f).min(MAX_SLOTS)
+}
+
+/// Get the number of the hardware breakpoints.
+///
+/// The last fetch watchpoint is reserved for the single-step.
+#[inline]
+pub fn breakpoint_slots() -> usize {
+    fetch_slots().saturating_sub(1)
+}
+
+/// Get the number of the hardware watchpoints.
+#[inline]
+pub fn watchpoint_slots() -> usize {
+    (unsafe { read_csr!(CSR_MWPC) } & 0x3f).min(MAX_SLOTS)
+}
+
+/// Set the hardware breakpoint `slot` at the instruction `addr`.
+///
+/// The breakpoints only match in user mode.
+pub fn set_breakpoint(slot: usize, addr: usize) -> Result<(), BreakpointError> {
+    if slot >= breakpoint_slots() {
+        return Err(BreakpointError::InvalidSlot);
+    }
+    unsafe {
+        write_slot_csr!(CSR_FWP_CFG, slot, CFG_ADDR, addr);
+        write_slot_csr!(CSR_FWP_CFG, slot, CFG_MASK, 0usize);
+        write_slot_csr!(CSR_FWP_CFG, slot, CFG_CTRL, CTRL_PLV3);
+    }
+    Ok(())
+}
+
+/// Set the hardware watchpoint `slot` on `len` bytes at `addr`.
+///
+/// The watchpoints only match in user mode.
+pub fn set_watchpoint(
+    slot: usize,
+    addr: usize,
+    len: usize,
+    kind: WatchKind,
+) -> Result<(), BreakpointError> {
+    if slot >= watchpoint_slots() {
+        return Err(BreakpointError::InvalidSlot);
+    }
+    check_watch_range(addr, len)?;
+    let access = match kind {
+        WatchKind::Read => CTRL_LOAD,
+        WatchKind::Write => CTRL_STORE,
+        WatchKind::ReadWrite => CTRL_LOAD | CTRL_STORE,
+    };
+    // The length is encoded as 8, 4, 2 and 1 bytes from 0 to 3.
+    let len_bits = 3 - len.trailing_zeros() as usize;
+    unsafe {
+        write_slot_csr!(CSR_MWP_CFG, slot, CFG_ADDR, addr);
+        write_slot_csr!(CSR_MWP_CFG, slot, CFG_MASK, 0usize);
+        write_slot_csr!(
+            CSR_MWP_CFG,
+            slot,
+            CFG_CTRL,
+            (len_bits << CTRL_LEN_SHIFT) | access | CTRL_PLV3
+        );
+    }
+    Ok(())
+}
+
+/// Clear the hardware breakpoint `slot`.
+#[inline]
+pub fn clear_breakpoint(slot: usize) {
+    if slot < breakpoint_slots() {
+        unsafe { write_slot_csr!(CSR_FWP_CFG, slot, CFG_CTRL, 0usize) };
+    }
+}
+
+/// Clear the hardware watchpoint `slot`.
+#[inline]
+pub fn clear_watchpoint(slot: usize) {
+    if slot < watchpoint_slots() {
+        unsafe { write_slot_csr!(CSR_MWP_CFG, slot, CFG_CTRL, 0usize) };
+    }
+}
+
+/// Watch the fetch of any user instruction on the current cpu, the first
+/// fetch is skipped so the exception is raised after an instruction.
+pub fn enable_single_step() {
+    let Some(slot) = fetch_slots().checked_sub(1) else {
+        log::warn!("single-step watchpoint is unavailable");
+        return;
+    };
+    unsafe {
+        write_slot_csr!(CSR_FWP_CFG, slot, CFG_ADDR, 0usize);
+        write_slot_csr!(CSR_FWP_CFG, slot, CFG_MASK, usize::MAX);
+        write_slot_csr!(CSR_FWP_CFG, slot, CFG_CTRL, CTRL_PLV3);
+        write_csr!(CSR_FWPS, WPS_SKIP);
+    }
+}
+
+/// Remove the single-step watchpoint from the current cpu.
+///
+/// Whether the step was completed is reported by [take_hit].
+pub fn disable_single_step() {
+    let Some(slot) = fetch_slots().checked_sub(1) else {
+        return;
+    };
+    unsafe {
+        let status = read_csr!(CSR_FWPS);
+        write_slot_csr!(CSR_FWP_CFG, slot, CFG_CTRL, 0usize);
+        if status & (1 << slot) != 0 {
+            write_csr!(CSR_FWPS, 1usize << slot);
+            STEP_HIT.write(true);
+        }
+    }
+}
+
+/// Decode the watchpoint exception from the status registers and clear them.
+///
+/// The watchpoint is skipped once, so the trapped instruction can be resumed.
+pub fn take_hit() -> Option<DebugHit> {
+    if core::mem::take(STEP_HIT.ref_mut()) {
+        return Some(DebugHit::SingleStep);
+    }
+    unsafe {
+        let fetch = read_csr!(CSR_FWPS) & ((1 << breakpoint_slots()) - 1);
+        if fetch != 0 {
+            write_csr!(CSR_FWPS, fetch | WPS_SKIP);
+            return Some(DebugHit::Breakpoint);
+        }
+        let memory = read_csr!(CSR_MWPS) & ((1 << watchpoint_slots()) - 1);
+        if memory != 0 {
+            let slot = memory.trailing_zeros() as usize;
+            write_csr!(CSR_MWPS, memory | WPS_SKIP);
+            return Some(DebugHit::Watchpoint(read_slot_csr!(CSR_MWP_CFG, slot, CFG_ADDR)));
+        }
+    }
+    None
+}
+
+/// Disable all the watchpoints of the current cpu.
+pub(crate) fn init() {
+    for slot in 0..fetch_slots() {
+        unsafe { write_slot_csr!(CSR_FWP_CFG, slot, CFG_CTRL, 0usize) };
+    }
+    for slot in 0..watchpoint_slots() {
+        clear_watchpoint(slot);
+    }
+}
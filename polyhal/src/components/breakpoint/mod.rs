@@ -0,0 +1,75 @@
+//! Hardware breakpoint module.
+//!
+//! Set the hardware breakpoints and data watchpoints of the current cpu.
+//! The number of slots depends on the cpu, a slot is set by its index.
+//!
+//! | Architecture | Breakpoints          | Watchpoints          |
+//! | ------------ | -------------------- | -------------------- |
+//! | x86_64       | `DR0`-`DR3`          | shared with breakpoints |
+//! | aarch64      | `DBGBVR`/`DBGBCR`    | `DBGWVR`/`DBGWCR`    |
+//! | riscv64      | Sdtrig triggers through the SBI debug trigger extension | shared with breakpoints |
+//! | loongarch64  | `FWPC`/`FWPnCFG`     | `MWPC`/`MWPnCFG`     |
+//!
+//! The slots shared with breakpoints are distinct slots of the same registers,
+//! a breakpoint and a watchpoint can't use the same index at the same time.
+//! The slots match in user mode only, except on x86_64 where the debug
+//! registers match in all privilege levels.
+//!
+//! ```rust
+//! use polyhal::breakpoint::{set_breakpoint, set_watchpoint, WatchKind};
+//!
+//! set_breakpoint(0, entry)?;
+//! set_watchpoint(1, &counter as *const _ as usize, 8, WatchKind::Write)?;
+//! ```
+//!
+//! The hits are reported by the trap handler as `Breakpoint`, `Watchpoint(addr)`
+//! and `SingleStep`, single-step is enabled on the trap frame of a user task.
+
+use crate::ctor::CtorType;
+
+super::define_arch_mods!();
+
+/// Access type which triggers a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Trigger on loads.
+    Read,
+    /// Trigger on stores.
+    Write,
+    /// Trigger on loads and stores.
+    ReadWrite,
+}
+
+/// Error of setting a hardware breakpoint or watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointError {
+    /// The slot doesn't exist on the current cpu.
+    InvalidSlot,
+    /// The address and length can't be matched by a single slot.
+    InvalidRange,
+    /// The access type isn't supported by the cpu.
+    Unsupported,
+}
+
+/// Debug event decoded from the debug registers at a debug exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugHit {
+    /// An instruction breakpoint was hit.
+    Breakpoint,
+    /// A watchpoint was hit, contains the watched address.
+    Watchpoint(usize),
+    /// A single step was completed.
+    SingleStep,
+}
+
+/// Check the address and length of a watchpoint, the range must be
+/// 1, 2, 4 or 8 bytes and naturally aligned.
+#[inline]
+pub(crate) fn check_watch_range(addr: usize, len: usize) -> Result<(), BreakpointError> {
+    match len {
+        1 | 2 | 4 | 8 if addr % len == 0 => Ok(()),
+        _ => Err(BreakpointError::InvalidRange),
+    }
+}
+
+ph_ctor!(ARCH_INIT_BREAKPOINT, CtorType::Cpu, init);
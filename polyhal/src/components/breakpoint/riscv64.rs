@@ -0,0 +1,268 @@
+use core::arch::asm;
+
+use super::{check_watch_range, BreakpointError, DebugHit, WatchKind};
+use crate::consts::VIRT_ADDR_START;
+
+/// The SBI debug trigger extension, "DBTR".
+const EID_DBTR: usize = 0x4442_5452;
+const FID_NUM_TRIGGERS: usize = 0;
+const FID_SET_SHMEM: usize = 1;
+const FID_READ_TRIGGERS: usize = 2;
+const FID_INSTALL_TRIGGERS: usize = 3;
+const FID_UPDATE_TRIGGERS: usize = 4;
+const FID_UNINSTALL_TRIGGERS: usize = 5;
+
+/// The maximum number of the slots managed on a cpu.
+const MAX_SLOTS: usize = 8;
+
+/// Type of the `mcontrol6` trigger in `tdata1`.
+const TDATA1_MCONTROL6: usize = 6 << 60;
+/// Type of the `icount` trigger in `tdata1`.
+const TDATA1_ICOUNT: usize = 3 << 60;
+
+const MCONTROL6_HIT1: usize = 1 << 25;
+const MCONTROL6_HIT0: usize = 1 << 22;
+/// Match the NAPOT range encoded in `tdata2`.
+const MCONTROL6_MATCH_NAPOT: usize = 1 << 7;
+const MCONTROL6_MATCH_MASK: usize = 0xf << 7;
+const MCONTROL6_U: usize = 1 << 3;
+const MCONTROL6_EXECUTE: usize = 1 << 2;
+const MCONTROL6_STORE: usize = 1 << 1;
+const MCONTROL6_LOAD: usize = 1 << 0;
+
+const ICOUNT_HIT: usize = 1 << 24;
+const ICOUNT_COUNT_SHIFT: usize = 10;
+const ICOUNT_COUNT_MASK: usize = 0x3fff << ICOUNT_COUNT_SHIFT;
+const ICOUNT_U: usize = 1 << 6;
+
+/// Shared memory entry of the extension, `idx` is written at the install.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct TriggerEntry {
+    /// `tstate` at the read, `idx` at the install and update.
+    idx: usize,
+    tdata1: usize,
+    tdata2: usize,
+    tdata3: usize,
+}
+
+/// The triggers installed on the cpu.
+struct Triggers {
+    shmem: TriggerEntry,
+    /// The trigger index of each slot.
+    slots: [Option<usize>; MAX_SLOTS],
+    /// The trigger index of the single-step trigger.
+    step: Option<usize>,
+    /// The single-step trigger was hit before it was removed.
+    step_hit: bool,
+}
+
+#[polyhal_macro::percpu]
+static TRIGGERS: Triggers = Triggers {
+    shmem: TriggerEntry {
+        idx: 0,
+        tdata1: 0,
+        tdata2: 0,
+        tdata3: 0,
+    },
+    slots: [None; MAX_SLOTS],
+    step: None,
+    step_hit: false,
+};
+
+#[inline]
+fn sbi_call(fid: usize, arg0: usize, arg1: usize, arg2: usize) -> Result<usize, isize> {
+    let (error, value): (isize, usize);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") arg0 => error,
+            inlateout("a1") arg1 => value,
+            in("a2") arg2,
+            in("a6") fid,
+            in("a7") EID_DBTR,
+        );
+    }
+    match error {
+        0 => Ok(value),
+        _ => Err(error),
+    }
+}
+
+/// Call the extension with a single entry in the shared memory of the current cpu.
+fn call_with_entry(fid: usize, arg0: usize, arg1: usize, entry: &mut TriggerEntry) -> bool {
+    let shmem = unsafe { &raw mut (*TRIGGERS.get_mut_ptr()).shmem };
+    let paddr = shmem as usize & !VIRT_ADDR_START;
+    unsafe {
+        shmem.write_volatile(*entry);
+        let ok = sbi_call(FID_SET_SHMEM, paddr, 0, 0).is_ok() && sbi_call(fid, arg0, arg1, 0).is_ok();
+        *entry = shmem.read_volatile();
+        ok
+    }
+}
+
+fn install(tdata1: usize, tdata2: usize) -> Result<usize, BreakpointError> {
+    let mut entry = TriggerEntry {
+        idx: 0,
+        tdata1,
+        tdata2,
+        tdata3: 0,
+    };
+    match call_with_entry(FID_INSTALL_TRIGGERS, 1, 0, &mut entry) {
+        true => Ok(entry.idx),
+        false => Err(BreakpointError::Unsupported),
+    }
+}
+
+fn read(idx: usize) -> Option<TriggerEntry> {
+    let mut entry = TriggerEntry {
+        idx: 0,
+        tdata1: 0,
+        tdata2: 0,
+        tdata3: 0,
+    };
+    call_with_entry(FID_READ_TRIGGERS, idx, 1, &mut entry).then_some(entry)
+}
+
+#[inline]
+fn uninstall(idx: usize) {
+    sbi_call(FID_UNINSTALL_TRIGGERS, idx, 1, 0).ok();
+}
+
+/// Get the number of the address match triggers, limited to the managed slots.
+fn trigger_slots() -> usize {
+    sbi_call(FID_NUM_TRIGGERS, TDATA1_MCONTROL6, 0, 0).map_or(0, |n| n.min(MAX_SLOTS))
+}
+
+fn set_slot(slot: usize, tdata1: usize, tdata2: usize) -> Result<(), BreakpointError> {
+    if slot >= trigger_slots() {
+        return Err(BreakpointError::InvalidSlot);
+    }
+    clear_breakpoint(slot);
+    let idx = install(tdata1, tdata2)?;
+    TRIGGERS.ref_mut().slots[slot] = Some(idx);
+    Ok(())
+}
+
+/// Get the number of the hardware breakpoints.
+#[inline]
+pub fn breakpoint_slots() -> usize {
+    trigger_slots()
+}
+
+/// Get the number of the hardware watchpoints.
+#[inline]
+pub fn watchpoint_slots() -> usize {
+    trigger_slots()
+}
+
+/// Set the hardware breakpoint `slot` at the instruction `addr`.
+///
+/// The breakpoints only match in user mode.
+pub fn set_breakpoint(slot: usize, addr: usize) -> Result<(), BreakpointError> {
+    set_slot(
+        slot,
+        TDATA1_MCONTROL6 | MCONTROL6_U | MCONTROL6_EXECUTE,
+        addr,
+    )
+}
+
+/// Set the hardware watchpoint `slot` on `len` bytes at `addr`.
+///
+/// The watchpoints only match in user mode.
+pub fn set_watchpoint(
+    slot: usize,
+    addr: usize,
+    len: usize,
+    kind: WatchKind,
+) -> Result<(), BreakpointError> {
+    check_watch_range(addr, len)?;
+    let access = match kind {
+        WatchKind::Read => MCONTROL6_LOAD,
+        WatchKind::Write => MCONTROL6_STORE,
+        WatchKind::ReadWrite => MCONTROL6_LOAD | MCONTROL6_STORE,
+    };
+    // The trailing ones of the NAPOT address give the size of the range.
+    let (matching, tdata2) = match len {
+        1 => (0, addr),
+        _ => (MCONTROL6_MATCH_NAPOT, addr | (len / 2 - 1)),
+    };
+    set_slot(
+        slot,
+        TDATA1_MCONTROL6 | MCONTROL6_U | matching | access,
+        tdata2,
+    )
+}
+
+/// Clear the hardware breakpoint `slot`.
+#[inline]
+pub fn clear_breakpoint(slot: usize) {
+    if let Some(idx) = TRIGGERS.ref_mut().slots.get_mut(slot).and_then(Option::take) {
+        uninstall(idx);
+    }
+}
+
+/// Clear the hardware watchpoint `slot`.
+#[inline]
+pub fn clear_watchpoint(slot: usize) {
+    clear_breakpoint(slot);
+}
+
+/// Install the single-step trigger on the current cpu, a breakpoint
+/// exception is raised after an instruction is executed in user mode.
+pub fn enable_single_step() {
+    if TRIGGERS.as_ref().step.is_some() {
+        return;
+    }
+    let tdata1 = TDATA1_ICOUNT | (1 << ICOUNT_COUNT_SHIFT) | ICOUNT_U;
+    match install(tdata1, 0) {
+        Ok(idx) => TRIGGERS.ref_mut().step = Some(idx),
+        Err(_) => log::warn!("single-step trigger is unavailable"),
+    }
+}
+
+/// Remove the single-step trigger from the current cpu.
+///
+/// Whether the step was completed is reported by [take_hit].
+pub fn disable_single_step() {
+    if let Some(idx) = TRIGGERS.ref_mut().step.take() {
+        // The count is decreased to zero when the trigger fires.
+        let hit = read(idx).is_some_and(|entry| {
+            entry.tdata1 & ICOUNT_HIT != 0 || entry.tdata1 & ICOUNT_COUNT_MASK == 0
+        });
+        uninstall(idx);
+        TRIGGERS.ref_mut().step_hit = hit;
+    }
+}
+
+/// Decode the breakpoint exception from the triggers and clear their hit bits.
+///
+/// Return [None] if the exception was raised by the `ebreak` instruction.
+pub fn take_hit() -> Option<DebugHit> {
+    if core::mem::take(&mut TRIGGERS.ref_mut().step_hit) {
+        return Some(DebugHit::SingleStep);
+    }
+    let slots = TRIGGERS.as_ref().slots;
+    slots.into_iter().flatten().find_map(|idx| {
+        let mut entry = read(idx)?;
+        if entry.tdata1 & (MCONTROL6_HIT0 | MCONTROL6_HIT1) == 0 {
+            return None;
+        }
+        let tdata1 = entry.tdata1;
+        entry.idx = idx;
+        entry.tdata1 &= !(MCONTROL6_HIT0 | MCONTROL6_HIT1);
+        call_with_entry(FID_UPDATE_TRIGGERS, 1, 0, &mut entry);
+        let hit = if tdata1 & MCONTROL6_EXECUTE != 0 {
+            DebugHit::Breakpoint
+        } else if tdata1 & MCONTROL6_MATCH_MASK == MCONTROL6_MATCH_NAPOT {
+            // Clear the trailing ones of the NAPOT address.
+            DebugHit::Watchpoint(entry.tdata2 & (entry.tdata2 + 1))
+        } else {
+            DebugHit::Watchpoint(entry.tdata2)
+        };
+        Some(hit)
+    })
+}
+
+/// The triggers are installed by the SBI on demand, nothing to initialize.
+pub(crate) fn init() {}
@@ -0,0 +1,112 @@
+use x86::debugregs::{self, BreakCondition, BreakSize, Breakpoint, Dr6, Dr7};
+
+use super::{check_watch_range, BreakpointError, DebugHit, WatchKind};
+
+/// The address registers `DR0`-`DR3`, shared by breakpoints and watchpoints.
+const DEBUG_REGS: [Breakpoint; 4] = [
+    Breakpoint::Dr0,
+    Breakpoint::Dr1,
+    Breakpoint::Dr2,
+    Breakpoint::Dr3,
+];
+
+#[inline]
+fn debug_reg(slot: usize) -> Result<Breakpoint, BreakpointError> {
+    DEBUG_REGS
+        .get(slot)
+        .copied()
+        .ok_or(BreakpointError::InvalidSlot)
+}
+
+/// Get the number of the hardware breakpoints.
+#[inline]
+pub fn breakpoint_slots() -> usize {
+    DEBUG_REGS.len()
+}
+
+/// Get the number of the hardware watchpoints.
+#[inline]
+pub fn watchpoint_slots() -> usize {
+    DEBUG_REGS.len()
+}
+
+/// Set the hardware breakpoint `slot` at the instruction `addr`.
+pub fn set_breakpoint(slot: usize, addr: usize) -> Result<(), BreakpointError> {
+    let reg = debug_reg(slot)?;
+    unsafe {
+        reg.configure(addr, BreakCondition::Instructions, BreakSize::Bytes1);
+        reg.enable_global();
+    }
+    Ok(())
+}
+
+/// Set the hardware watchpoint `slot` on `len` bytes at `addr`.
+///
+/// The loads can't be watched without the stores, [WatchKind::Read] is unsupported.
+pub fn set_watchpoint(
+    slot: usize,
+    addr: usize,
+    len: usize,
+    kind: WatchKind,
+) -> Result<(), BreakpointError> {
+    let reg = debug_reg(slot)?;
+    check_watch_range(addr, len)?;
+    let condition = match kind {
+        WatchKind::Read => return Err(BreakpointError::Unsupported),
+        WatchKind::Write => BreakCondition::DataWrites,
+        WatchKind::ReadWrite => BreakCondition::DataReadsWrites,
+    };
+    let size = match len {
+        1 => BreakSize::Bytes1,
+        2 => BreakSize::Bytes2,
+        4 => BreakSize::Bytes4,
+        _ => BreakSize::Bytes8,
+    };
+    unsafe {
+        reg.configure(addr, condition, size);
+        reg.enable_global();
+    }
+    Ok(())
+}
+
+/// Clear the hardware breakpoint `slot`.
+#[inline]
+pub fn clear_breakpoint(slot: usize) {
+    if let Ok(reg) = debug_reg(slot) {
+        unsafe { reg.disable_global() };
+    }
+}
+
+/// Clear the hardware watchpoint `slot`.
+#[inline]
+pub fn clear_watchpoint(slot: usize) {
+    clear_breakpoint(slot);
+}
+
+/// Decode the debug exception from `DR6` and clear it.
+///
+/// Return [None] if the exception wasn't raised by the debug registers.
+pub fn take_hit() -> Option<DebugHit> {
+    let (dr6, dr7) = unsafe { (debugregs::dr6(), debugregs::dr7()) };
+    // Bit 16 is set when the register is clear.
+    unsafe { debugregs::dr6_write(Dr6::RTM) };
+    if dr6.contains(Dr6::BS) {
+        return Some(DebugHit::SingleStep);
+    }
+    DEBUG_REGS
+        .iter()
+        .enumerate()
+        .find(|(i, _)| dr6.bits() & (1 << i) != 0 && dr7.0 & (1 << (i * 2 + 1)) != 0)
+        .map(|(i, reg)| match (dr7.0 >> (16 + i * 4)) & 0b11 {
+            0 => DebugHit::Breakpoint,
+            _ => DebugHit::Watchpoint(unsafe { reg.dr() }),
+        })
+}
+
+/// Disable all the hardware breakpoints of the current cpu.
+pub(crate) fn init() {
+    unsafe {
+        debugregs::dr7_write(Dr7::default());
+        debugregs::dr6_write(Dr6::RTM);
+    }
+}
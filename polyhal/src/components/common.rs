@@ -8,6 +8,13 @@ pub trait PageAlloc: Sync {
     fn alloc(&self) -> PhysAddr;
     /// Release a physical page
     fn dealloc(&self, paddr: PhysAddr);
+    /// Allocate `count` contiguous physical pages
+    ///
+    /// More than a page is needed by the vector registers of the riscv64
+    /// with `VLEN` above 1024.
+    fn alloc_contiguous(&self, count: usize) -> PhysAddr;
+    /// Release the pages allocated by [PageAlloc::alloc_contiguous]
+    fn dealloc_contiguous(&self, paddr: PhysAddr, count: usize);
 }
 
 static PAGE_ALLOC: LazyInit<&dyn PageAlloc> = LazyInit::new();
@@ -37,3 +44,29 @@ pub(crate) fn frame_alloc() -> PhysAddr {
 pub(crate) fn frame_dealloc(paddr: PhysAddr) {
     PAGE_ALLOC.dealloc(paddr)
 }
+
+/// alloc contiguous persistent memory pages
+#[allow(dead_code)]
+#[inline]
+pub(crate) fn frame_alloc_contiguous(count: usize) -> PhysAddr {
+    PAGE_ALLOC.alloc_contiguous(count)
+}
+
+/// release the contiguous frames
+#[allow(dead_code)]
+#[inline]
+pub(crate) fn frame_dealloc_contiguous(paddr: PhysAddr, count: usize) {
+    PAGE_ALLOC.dealloc_contiguous(paddr, count)
+}
+
+/// alloc a persistent memory page, take it from the boot memory area
+/// if the page allocator is not initialized yet.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn frame_alloc_early() -> PhysAddr {
+    if PAGE_ALLOC.is_inited() {
+        return frame_alloc();
+    }
+    let size = crate::pagetable::PAGE_SIZE;
+    let ptr = unsafe { crate::mem::alloc(size * 2) } as usize;
+    pa!(ptr.next_multiple_of(size))
+}
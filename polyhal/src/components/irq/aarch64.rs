@@ -1,71 +1,326 @@
+mod gicv3;
+mod its;
+
+use core::ops::Range;
+
 use aarch64_cpu::registers::{Readable, DAIF};
-use arm_gicv2::{translate_irq, InterruptType};
+use arm_gicv2::{translate_irq, InterruptType, TriggerMode};
 use arm_gicv2::{GicCpuInterface, GicDistributor};
+use fdt_parser::Node;
+use lazyinit::LazyInit;
 
-use crate::components::irq::{IRQVector, IRQ};
+use crate::arch::hart_id;
+use crate::components::irq::msi::VectorAllocator;
+use crate::components::irq::of::IrqCells;
+use crate::components::irq::{IRQVector, IrqConfig, IrqTrigger, MsiDescriptor, IRQ};
 use crate::ctor::CtorType;
+use crate::mem::get_fdt;
 use crate::utils::MutexNoIrq;
 use crate::PhysAddr;
+use gicv3::GicV3;
+use its::{Its, LPI_BASE};
 
 /// The maximum number of IRQs.
-#[allow(dead_code)]
 pub const MAX_IRQ_COUNT: usize = 1024;
 
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = translate_irq(14, InterruptType::PPI).unwrap();
 
+/// The IPI number, the SGI 1.
+pub const IPI_IRQ_NUM: usize = translate_irq(1, InterruptType::SGI).unwrap();
+
+/// The spurious interrupt, there was no pending interrupt when it was acknowledged.
+pub const SPURIOUS_IRQ_NUM: usize = 1023;
+
 /// The UART IRQ number.
 #[allow(dead_code)]
 pub const UART_IRQ_NUM: usize = translate_irq(1, InterruptType::SPI).unwrap();
 
+/// The GICv2 of the QEMU virt machine, used if the device tree has no GIC.
 const GICD_BASE: PhysAddr = PhysAddr::new(0x0800_0000);
 const GICC_BASE: PhysAddr = PhysAddr::new(0x0801_0000);
 
-static GICD: MutexNoIrq<GicDistributor> =
-    MutexNoIrq::new(GicDistributor::new(GICD_BASE.get_mut_ptr()));
+const GICV2_COMPATIBLE: &[&str] = &[
+    "arm,gic-400",
+    "arm,cortex-a15-gic",
+    "arm,cortex-a9-gic",
+    "arm,cortex-a7-gic",
+];
+
+/// The offsets of the registers of the GICv2 distributor and the GICv2m frame.
+const GICD_ISENABLER: usize = 0x100;
+const GICD_ISPENDR: usize = 0x200;
+const GICD_IPRIORITYR: usize = 0x400;
+const GICD_ITARGETSR: usize = 0x800;
+const GICD_SGIR: usize = 0xf00;
+const V2M_MSI_TYPER: usize = 0x008;
+const V2M_MSI_SETSPI_NS: usize = 0x040;
+
+#[allow(clippy::large_enum_variant)]
+enum Gic {
+    V2 {
+        /// The base of the distributor, to write `GICD_SGIR`.
+        base: usize,
+        gicd: MutexNoIrq<GicDistributor>,
+        // per-CPU, no lock
+        gicc: GicCpuInterface,
+    },
+    V3(GicV3),
+}
+
+static GIC: LazyInit<Gic> = LazyInit::new();
+
+/// A GICv2m frame, which converts the writes to `MSI_SETSPI_NS` to the SPIs.
+struct V2m {
+    address: PhysAddr,
+    spis: Range<usize>,
+    allocated: VectorAllocator<{ MAX_IRQ_COUNT / 64 }>,
+}
+
+/// The GICv2m frame, it's preferred to the ITS as it needs no DeviceID.
+static V2M: LazyInit<Option<V2m>> = LazyInit::new();
 
-// per-CPU, no lock
-static GICC: GicCpuInterface = GicCpuInterface::new(GICC_BASE.get_mut_ptr());
+/// Find the GICv2m frame in the device tree.
+fn probe_v2m() -> Option<V2m> {
+    let fdt = get_fdt().ok()?;
+    let node = fdt.find_compatible(&["arm,gic-v2m-frame"]).next()?;
+    let base = PhysAddr::new(node.reg()?.next()?.address as usize);
+    let typer = unsafe { base.get_mut_ptr::<u8>().add(V2M_MSI_TYPER).cast::<u32>().read_volatile() };
+    let spi_base = node
+        .find_property("arm,msi-base-spi")
+        .map_or((typer >> 16) & 0x3ff, |base| base.u32()) as usize;
+    let spi_count = node
+        .find_property("arm,msi-num-spis")
+        .map_or(typer & 0x3ff, |count| count.u32()) as usize;
+    log::info!(
+        "GICv2m @ {:#x}, SPI {}..{}",
+        base.raw(),
+        spi_base,
+        spi_base + spi_count
+    );
+    Some(V2m {
+        address: PhysAddr::new(base.raw() + V2M_MSI_SETSPI_NS),
+        spis: spi_base..(spi_base + spi_count).min(MAX_IRQ_COUNT),
+        allocated: VectorAllocator::new(),
+    })
+}
+
+/// Find the GIC in the device tree and initialize the distributor.
+///
+/// The GICC and GICD entries of the MADT aren't probed, the aarch64 boot path
+/// only receives a device tree, the GICv2 of QEMU virt is used without it.
+fn probe() -> Gic {
+    let fdt = get_fdt().ok();
+    if let Some(node) = fdt
+        .as_ref()
+        .and_then(|fdt| fdt.find_compatible(&["arm,gic-v3"]).next())
+    {
+        let regions = node
+            .find_property("#redistributor-regions")
+            .map_or(1, |regions| regions.u32() as usize);
+        let stride = node
+            .find_property("redistributor-stride")
+            .map(|stride| stride.u32() as usize)
+            .filter(|stride| *stride != 0);
+        if let Some(mut regs) = node.reg() {
+            if let Some(gicd) = regs.next() {
+                let rdists = regs.take(regions).map(|reg| {
+                    (
+                        PhysAddr::new(reg.address as usize),
+                        reg.size.unwrap_or(0x2_0000),
+                    )
+                });
+                // The ITS is a child of the GIC node.
+                let its = fdt
+                    .as_ref()
+                    .and_then(|fdt| {
+                        let its = fdt.find_compatible(&["arm,gic-v3-its"]).next()?;
+                        let cpus = fdt.find_nodes("/cpus/cpu").count();
+                        Some((its.reg()?.next()?.address as usize, cpus))
+                    })
+                    .map(|(base, cpus)| Its::new(PhysAddr::new(base), cpus));
+                return Gic::V3(GicV3::new(
+                    PhysAddr::new(gicd.address as usize),
+                    rdists,
+                    stride,
+                    its,
+                ));
+            }
+        }
+    }
+    let (gicd, gicc) = fdt
+        .as_ref()
+        .and_then(|fdt| {
+            let node = fdt.find_compatible(GICV2_COMPATIBLE).next()?;
+            let mut regs = node.reg()?;
+            let gicd = regs.next()?.address as usize;
+            let gicc = regs.next()?.address as usize;
+            Some((PhysAddr::new(gicd), PhysAddr::new(gicc)))
+        })
+        .unwrap_or((GICD_BASE, GICC_BASE));
+    log::info!("Initialize GICv2 @ {:#x}...", gicd.raw());
+    let mut distributor = GicDistributor::new(gicd.get_mut_ptr());
+    distributor.init();
+    Gic::V2 {
+        base: gicd.get_mut_ptr::<u8>() as usize,
+        gicd: MutexNoIrq::new(distributor),
+        gicc: GicCpuInterface::new(gicc.get_mut_ptr()),
+    }
+}
 
-/// Initializes GICD, GICC on the primary CPU.
+/// Initializes the distributor on the primary CPU, and the CPU interface of the current CPU.
 pub(crate) fn init() {
-    log::info!("Initialize GICv2...");
-    GICD.lock().init();
-    GICC.init();
+    GIC.call_once(probe);
+    V2M.call_once(probe_v2m);
+    match &*GIC {
+        Gic::V2 { gicc, .. } => gicc.init(),
+        Gic::V3(gic) => gic.init_cpu(),
+    }
+    // The SGIs are banked, enable the IPI on each CPU.
+    IRQ::irq_enable(IPI_IRQ_NUM);
 }
 
+/// Send the IPI to the CPU as a SGI.
+pub(super) fn send_ipi_to(cpu: usize) {
+    match &*GIC {
+        Gic::V2 { base, .. } => {
+            // The CPU target list in bits 23:16, only 8 CPUs can be targeted.
+            if cpu >= 8 {
+                log::error!("the GICv2 can't send the SGI to cpu {}", cpu);
+                return;
+            }
+            let sgir = ((1 << (cpu + 16)) | IPI_IRQ_NUM) as u32;
+            unsafe { ((base + GICD_SGIR) as *mut u32).write_volatile(sgir) };
+        }
+        Gic::V3(gic) => gic.send_sgi(cpu, IPI_IRQ_NUM),
+    }
+}
+
+/// The SGI is acknowledged through the [IRQVector].
+#[inline]
+pub(super) fn ack_ipi() {}
+
 /// Implmente the irq vector methods
 impl IRQVector {
     /// Get the irq number in this vector
+    ///
+    /// The source CPU of a GICv2 SGI is masked, an LPI is kept as it is.
     pub const fn irq_num(&self) -> usize {
-        self.0 & 0x3ff
+        match self.0 {
+            LPI_BASE.. => self.0,
+            _ => self.0 & 0x3ff,
+        }
     }
 
     /// Acknowledge the irq
     pub fn ack(&self) {
-        GICC.eoi(self.0 as u32);
+        match &*GIC {
+            Gic::V2 { gicc, .. } => gicc.eoi(self.0 as u32),
+            Gic::V3(gic) => gic.eoi(self.0 as u32),
+        }
     }
 }
 
 /// Get the irq Vector that was
 #[inline]
 pub fn get_irq() -> IRQVector {
-    IRQVector(GICC.iar() as _)
+    IRQVector(match &*GIC {
+        Gic::V2 { gicc, .. } => gicc.iar(),
+        Gic::V3(gic) => gic.iar(),
+    } as _)
 }
 
 /// Implement IRQ operations for the IRQ interface.
 impl IRQ {
     /// Enable irq for the given IRQ number.
+    ///
+    /// An SPI is routed to the current CPU.
     #[inline]
     pub fn irq_enable(irq_num: usize) {
-        GICD.lock().set_enable(irq_num, true);
-        // GICD.lock().configure_interrupt(irq_num, arm_gic::TriggerMode::Level);
+        match &*GIC {
+            Gic::V2 { base, gicd, .. } => {
+                let mut gicd = gicd.lock();
+                // The GICv2 targets at most 8 CPUs.
+                if (32..MAX_IRQ_COUNT).contains(&irq_num) && hart_id() < 8 {
+                    let target = (base + GICD_ITARGETSR + irq_num) as *mut u8;
+                    unsafe { target.write_volatile(1 << hart_id()) };
+                }
+                gicd.set_enable(irq_num, true)
+            }
+            Gic::V3(gic) => gic.set_enable(irq_num, true),
+        }
     }
 
     /// Disable irq for the given IRQ number.
     #[inline]
     pub fn irq_disable(irq_num: usize) {
-        GICD.lock().set_enable(irq_num, false);
+        match &*GIC {
+            Gic::V2 { gicd, .. } => gicd.lock().set_enable(irq_num, false),
+            Gic::V3(gic) => gic.set_enable(irq_num, false),
+        }
+    }
+
+    /// Check if the irq is enabled, the SGIs and PPIs of the current CPU.
+    pub fn irq_enabled(irq_num: usize) -> bool {
+        match &*GIC {
+            Gic::V2 { base, .. } => {
+                irq_num < MAX_IRQ_COUNT && {
+                    let reg = (base + GICD_ISENABLER + (irq_num / 32) * 4) as *const u32;
+                    unsafe { reg.read_volatile() & (1 << (irq_num % 32)) != 0 }
+                }
+            }
+            Gic::V3(gic) => gic.is_enabled(irq_num),
+        }
+    }
+
+    /// Check if the irq is pending, the SGIs and PPIs of the current CPU.
+    pub fn is_pending(irq_num: usize) -> bool {
+        match &*GIC {
+            Gic::V2 { base, .. } => {
+                irq_num < MAX_IRQ_COUNT && {
+                    let reg = (base + GICD_ISPENDR + (irq_num / 32) * 4) as *const u32;
+                    unsafe { reg.read_volatile() & (1 << (irq_num % 32)) != 0 }
+                }
+            }
+            Gic::V3(gic) => gic.is_pending(irq_num),
+        }
+    }
+
+    /// Configure the trigger, the priority and the targets of the irq, and enable it.
+    ///
+    /// An SPI of the GICv2 targets at most 8 CPUs, the GICv3 routes it to the lowest target.
+    /// The polarity isn't configurable.
+    pub fn configure(irq_num: usize, config: IrqConfig) {
+        // The lower value is the higher priority, at least 16 levels are implemented.
+        let priority = !config.priority & 0xf0;
+        match &*GIC {
+            Gic::V2 { base, gicd, .. } => {
+                let mut gicd = gicd.lock();
+                gicd.configure_interrupt(
+                    irq_num,
+                    match config.trigger {
+                        IrqTrigger::Edge => TriggerMode::Edge,
+                        IrqTrigger::Level => TriggerMode::Level,
+                    },
+                );
+                if irq_num < MAX_IRQ_COUNT {
+                    let priority_reg = (base + GICD_IPRIORITYR + irq_num) as *mut u8;
+                    unsafe { priority_reg.write_volatile(priority) };
+                }
+                let targets = config.targets() & 0xff;
+                if (32..MAX_IRQ_COUNT).contains(&irq_num) && targets != 0 {
+                    let target = (base + GICD_ITARGETSR + irq_num) as *mut u8;
+                    unsafe { target.write_volatile(targets as u8) };
+                }
+                gicd.set_enable(irq_num, true)
+            }
+            Gic::V3(gic) => gic.configure(
+                irq_num,
+                config.trigger == IrqTrigger::Edge,
+                priority,
+                config.first_target(),
+            ),
+        }
     }
 
     /// Enable interrupt.
@@ -89,4 +344,78 @@ impl IRQ {
     }
 }
 
+/// Allocate the SPIs of the GICv2m frame, or the LPIs of the ITS.
+///
+/// The SPIs are routed to the `affinity` CPU when they are enabled, the LPIs
+/// when they are bound by [bind_msi_device].
+pub(super) fn alloc_msi_vectors(count: usize, affinity: usize) -> Option<MsiDescriptor> {
+    let (address, vectors, data) = match (V2M.get().and_then(Option::as_ref), &*GIC) {
+        (Some(v2m), _) => {
+            let spis = v2m.allocated.alloc(v2m.spis.clone(), count)?;
+            (v2m.address, spis.clone(), spis.start)
+        }
+        (None, Gic::V3(gic)) => {
+            let its = gic.its()?;
+            let lpis = its.alloc(count)?;
+            (its.translater(), lpis.clone(), lpis.start - LPI_BASE)
+        }
+        _ => return None,
+    };
+    Some(MsiDescriptor {
+        address,
+        data: data as u32,
+        vectors,
+        affinity,
+    })
+}
+
+/// Free the vectors allocated by [alloc_msi_vectors].
+pub(super) fn free_msi_vectors(desc: &MsiDescriptor) {
+    match (V2M.get().and_then(Option::as_ref), &*GIC) {
+        (Some(v2m), _) => v2m.allocated.free(desc.vectors.clone()),
+        (None, Gic::V3(gic)) => {
+            if let Some(its) = gic.its() {
+                its.free(desc.vectors.clone());
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Bind the MSI vectors to the device, which is required by the GICv3 ITS.
+///
+/// The ITS translates the MSIs by the DeviceID of the writer, which is the
+/// requester id of a PCI device mapped by the `msi-map` of the host bridge.
+/// Return `false` if the vectors can't be bound, it always succeeds with the GICv2m.
+pub fn bind_msi_device(desc: &MsiDescriptor, device_id: u32) -> bool {
+    match (V2M.get().and_then(Option::as_ref), &*GIC) {
+        (Some(_), _) => true,
+        (None, Gic::V3(gic)) => gic
+            .its()
+            .is_some_and(|its| its.bind(device_id, desc.vectors.clone(), desc.affinity)),
+        _ => false,
+    }
+}
+
+/// Translate the interrupt specifier of the GIC, `<type number flags>`.
+///
+/// The type is 0 for a SPI and 1 for a PPI, the PPIs have the CPU mask in
+/// the bits 15:8 of the flags.
+pub(super) fn translate_of_irq(controller: &Node, cells: &IrqCells) -> Option<(usize, u32)> {
+    let gic = controller
+        .compatibles()
+        .any(|c| c == "arm,gic-v3" || GICV2_COMPATIBLE.contains(&c));
+    let [kind, number, flags] = cells.as_slice() else {
+        return None;
+    };
+    let kind = match kind {
+        0 => InterruptType::SPI,
+        1 => InterruptType::PPI,
+        _ => return None,
+    };
+    gic.then(|| translate_irq(*number as usize, kind))
+        .flatten()
+        .map(|irq| (irq, flags & 0xf))
+}
+
 ph_ctor!(AARCH64_INIT_GIC, CtorType::Cpu, init);
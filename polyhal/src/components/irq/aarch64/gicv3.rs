@@ -0,0 +1,376 @@
+//! Generic Interrupt Controller version 3.
+//!
+//! The affinity routing is enabled, the SGIs and PPIs are configured in the
+//! redistributor of each CPU, and the CPU interface is accessed through the
+//! `ICC_*` system registers. All the interrupts are in the non-secure group 1.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use aarch64_cpu::registers::{Readable, MPIDR_EL1};
+use arrayvec::ArrayVec;
+
+use super::its::{Its, LPI_BASE};
+use crate::arch::hart_id;
+use crate::components::irq::MAX_IPI_CPUS;
+use crate::utils::MutexNoIrq;
+use crate::PhysAddr;
+
+const GICD_CTLR: usize = 0x0000;
+const GICD_TYPER: usize = 0x0004;
+const GICD_IGROUPR: usize = 0x0080;
+const GICD_ISENABLER: usize = 0x0100;
+const GICD_ICENABLER: usize = 0x0180;
+const GICD_ISPENDR: usize = 0x0200;
+const GICD_ICPENDR: usize = 0x0280;
+const GICD_IPRIORITYR: usize = 0x0400;
+const GICD_ICFGR: usize = 0x0c00;
+const GICD_IROUTER: usize = 0x6000;
+
+const GICD_CTLR_RWP: u32 = 1 << 31;
+const GICD_CTLR_ARE_NS: u32 = 1 << 4;
+const GICD_CTLR_ENABLE_G1A: u32 = 1 << 1;
+const GICD_CTLR_ENABLE_G1: u32 = 1 << 0;
+
+const GICR_TYPER: usize = 0x0008;
+const GICR_WAKER: usize = 0x0014;
+const GICR_TYPER_VLPIS: u64 = 1 << 1;
+const GICR_TYPER_LAST: u64 = 1 << 4;
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+
+/// The SGI and PPI registers are in the second frame of the redistributor.
+const GICR_SGI_BASE: usize = 0x1_0000;
+
+/// The priority of all the interrupts, lower is higher.
+pub(super) const DEFAULT_PRIORITY: u8 = 0xa0;
+
+/// The interrupts 1020-1023 are special.
+const MAX_IRQS: usize = 1020;
+
+/// The maximum number of the redistributor regions.
+const MAX_RDIST_REGIONS: usize = 8;
+
+/// The redistributor of the current CPU.
+#[polyhal_macro::percpu]
+static GICR_BASE: usize = 0;
+
+/// The route of a CPU which hasn't initialized its redistributor.
+const NO_ROUTE: u64 = u64::MAX;
+
+/// The affinity of each CPU in the layout of `GICD_IROUTER`, saved by [GicV3::init_cpu].
+static CPU_ROUTES: [AtomicU64; MAX_IPI_CPUS] = [const { AtomicU64::new(NO_ROUTE) }; MAX_IPI_CPUS];
+
+/// The affinity of the CPU in the layout of `GICD_IROUTER`, `None` if it isn't initialized.
+#[inline]
+fn cpu_route(cpu: usize) -> Option<u64> {
+    let route = CPU_ROUTES.get(cpu)?.load(Ordering::Acquire);
+    (route != NO_ROUTE).then_some(route)
+}
+
+/// The affinity of the current CPU in the layout of `GICR_TYPER[63:32]`.
+#[inline]
+fn affinity() -> u32 {
+    let mpidr = MPIDR_EL1.get();
+    ((((mpidr >> 32) & 0xff) << 24) | (mpidr & 0xff_ffff)) as u32
+}
+
+/// The affinity of the current CPU in the layout of `GICD_IROUTER`.
+#[inline]
+fn route() -> u64 {
+    MPIDR_EL1.get() & 0xff_00ff_ffff
+}
+
+#[inline]
+pub(super) fn read32(addr: usize) -> u32 {
+    unsafe { (addr as *const u32).read_volatile() }
+}
+
+#[inline]
+pub(super) fn write32(addr: usize, value: u32) {
+    unsafe { (addr as *mut u32).write_volatile(value) }
+}
+
+pub(super) struct GicV3 {
+    gicd: usize,
+    /// The redistributor regions, the base and the size.
+    rdists: ArrayVec<(usize, usize), MAX_RDIST_REGIONS>,
+    /// The `redistributor-stride`, found from `GICR_TYPER` if it's `None`.
+    stride: Option<usize>,
+    max_irqs: usize,
+    /// The ITS translating the MSIs to LPIs.
+    its: Option<Its>,
+    /// Serialize the configuration of the distributor.
+    lock: MutexNoIrq<()>,
+}
+
+impl GicV3 {
+    /// Initialize the distributor, all the SPIs are disabled and routed to the current CPU.
+    ///
+    /// The LPIs are enabled if the ITS is given.
+    pub(super) fn new(
+        gicd: PhysAddr,
+        rdists: impl Iterator<Item = (PhysAddr, usize)>,
+        stride: Option<usize>,
+        its: Option<Its>,
+    ) -> Self {
+        let mut gic = Self {
+            gicd: gicd.get_mut_ptr::<u8>() as usize,
+            rdists: rdists
+                .take(MAX_RDIST_REGIONS)
+                .map(|(base, size)| (base.get_mut_ptr::<u8>() as usize, size))
+                .collect(),
+            stride,
+            max_irqs: 0,
+            its,
+            lock: MutexNoIrq::new(()),
+        };
+        gic.max_irqs = (((gic.gicd_read(GICD_TYPER) as usize & 0x1f) + 1) * 32).min(MAX_IRQS);
+
+        gic.gicd_write(GICD_CTLR, 0);
+        gic.wait_rwp();
+        for i in (32..gic.max_irqs).step_by(32) {
+            gic.gicd_write(GICD_IGROUPR + i / 8, u32::MAX);
+            gic.gicd_write(GICD_ICENABLER + i / 8, u32::MAX);
+            gic.gicd_write(GICD_ICPENDR + i / 8, u32::MAX);
+        }
+        for i in 32..gic.max_irqs {
+            unsafe { ((gic.gicd + GICD_IPRIORITYR + i) as *mut u8).write_volatile(DEFAULT_PRIORITY) };
+            gic.set_route(i, route());
+        }
+        gic.gicd_write(
+            GICD_CTLR,
+            GICD_CTLR_ARE_NS | GICD_CTLR_ENABLE_G1A | GICD_CTLR_ENABLE_G1,
+        );
+        gic.wait_rwp();
+        log::info!(
+            "GICv3 @ {:#x}, {} irqs, {} redistributor regions",
+            gicd.raw(),
+            gic.max_irqs,
+            gic.rdists.len()
+        );
+        gic
+    }
+
+    #[inline]
+    fn gicd_read(&self, offset: usize) -> u32 {
+        read32(self.gicd + offset)
+    }
+
+    #[inline]
+    fn gicd_write(&self, offset: usize, value: u32) {
+        write32(self.gicd + offset, value)
+    }
+
+    /// Wait until the write to `GICD_CTLR` takes effect.
+    #[inline]
+    fn wait_rwp(&self) {
+        while self.gicd_read(GICD_CTLR) & GICD_CTLR_RWP != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    #[inline]
+    fn set_route(&self, irq: usize, route: u64) {
+        unsafe { ((self.gicd + GICD_IROUTER + irq * 8) as *mut u64).write_volatile(route) };
+    }
+
+    /// Find the redistributor of the current CPU.
+    fn find_rdist(&self) -> Option<usize> {
+        let affinity = affinity();
+        for &(base, size) in self.rdists.iter() {
+            let mut rd = base;
+            while rd < base + size {
+                let typer = unsafe { ((rd + GICR_TYPER) as *const u64).read_volatile() };
+                if (typer >> 32) as u32 == affinity {
+                    return Some(rd);
+                }
+                if typer & GICR_TYPER_LAST != 0 {
+                    break;
+                }
+                rd += self.stride.unwrap_or(match typer & GICR_TYPER_VLPIS {
+                    0 => 0x2_0000,
+                    _ => 0x4_0000,
+                });
+            }
+        }
+        None
+    }
+
+    /// Wake up the redistributor and enable the CPU interface of the current CPU.
+    pub(super) fn init_cpu(&self) {
+        match CPU_ROUTES.get(hart_id()) {
+            Some(cpu_route) => cpu_route.store(route(), Ordering::Release),
+            None => log::warn!("GICv3 can't route the interrupts to cpu {}", hart_id()),
+        }
+        let Some(rd) = self.find_rdist() else {
+            log::error!("GICv3 redistributor not found for {:#x}", affinity());
+            return;
+        };
+        GICR_BASE.write(rd);
+
+        write32(
+            rd + GICR_WAKER,
+            read32(rd + GICR_WAKER) & !GICR_WAKER_PROCESSOR_SLEEP,
+        );
+        while read32(rd + GICR_WAKER) & GICR_WAKER_CHILDREN_ASLEEP != 0 {
+            core::hint::spin_loop();
+        }
+
+        let sgi = rd + GICR_SGI_BASE;
+        write32(sgi + GICD_IGROUPR, u32::MAX);
+        write32(sgi + GICD_ICENABLER, u32::MAX);
+        write32(sgi + GICD_ICPENDR, u32::MAX);
+        for i in 0..32 {
+            unsafe { ((sgi + GICD_IPRIORITYR + i) as *mut u8).write_volatile(DEFAULT_PRIORITY) };
+        }
+
+        unsafe {
+            // Enable the system register interface.
+            let sre: usize;
+            asm!("mrs {}, icc_sre_el1", out(reg) sre);
+            asm!("msr icc_sre_el1, {}", "isb", in(reg) sre | 1);
+            // Unmask all the priorities, no preemption groups.
+            asm!("msr icc_pmr_el1, {}", in(reg) 0xffusize);
+            asm!("msr icc_bpr1_el1, {}", in(reg) 0usize);
+            asm!("msr icc_igrpen1_el1, {}", "isb", in(reg) 1usize);
+        }
+
+        if let Some(its) = &self.its {
+            its.init_cpu(rd, hart_id());
+        }
+    }
+
+    /// The ITS of the GIC.
+    #[inline]
+    pub(super) fn its(&self) -> Option<&Its> {
+        self.its.as_ref()
+    }
+
+    /// The base of the registers of the interrupt, the SGIs and PPIs are in
+    /// the redistributor of the current CPU.
+    #[inline]
+    fn irq_base(&self, irq: usize) -> Option<usize> {
+        match irq {
+            0..32 => match GICR_BASE.read() {
+                0 => None,
+                rd => Some(rd + GICR_SGI_BASE),
+            },
+            _ if irq < self.max_irqs => Some(self.gicd),
+            _ => None,
+        }
+    }
+
+    /// Enable or disable the interrupt.
+    ///
+    /// The SGIs and PPIs are enabled on the current CPU, an SPI is routed to the current CPU.
+    /// An LPI is enabled for all the CPUs, it's routed by the ITS.
+    pub(super) fn set_enable(&self, irq: usize, enable: bool) {
+        if irq >= LPI_BASE {
+            if let Some(its) = &self.its {
+                its.set_enable(irq, enable, hart_id());
+            }
+            return;
+        }
+        let Some(base) = self.irq_base(irq) else {
+            return;
+        };
+        let offset = (irq / 32) * 4;
+        let _guard = self.lock.lock();
+        match enable {
+            true => {
+                if irq >= 32 {
+                    self.set_route(irq, route());
+                }
+                write32(base + GICD_ISENABLER + offset, 1 << (irq % 32));
+            }
+            false => write32(base + GICD_ICENABLER + offset, 1 << (irq % 32)),
+        }
+    }
+
+    /// Check if the interrupt is enabled, the SGIs and PPIs of the current CPU.
+    pub(super) fn is_enabled(&self, irq: usize) -> bool {
+        if irq >= LPI_BASE {
+            return self.its.as_ref().is_some_and(|its| its.is_enabled(irq));
+        }
+        self.irq_base(irq).is_some_and(|base| {
+            read32(base + GICD_ISENABLER + (irq / 32) * 4) & (1 << (irq % 32)) != 0
+        })
+    }
+
+    /// Check if the interrupt is pending, the SGIs and PPIs of the current CPU.
+    ///
+    /// The pending state of the LPIs is cached by the redistributors, they are never reported.
+    pub(super) fn is_pending(&self, irq: usize) -> bool {
+        self.irq_base(irq).is_some_and(|base| {
+            read32(base + GICD_ISPENDR + (irq / 32) * 4) & (1 << (irq % 32)) != 0
+        })
+    }
+
+    /// Configure the interrupt and enable it, an SPI is routed to the `cpu`,
+    /// or to the current CPU if the `cpu` isn't initialized.
+    ///
+    /// The SGIs are always edge-triggered, only the priority of an LPI is configurable.
+    pub(super) fn configure(&self, irq: usize, edge: bool, priority: u8, cpu: usize) {
+        if irq >= LPI_BASE {
+            if let Some(its) = &self.its {
+                its.set_priority(irq, priority);
+                its.set_enable(irq, true, hart_id());
+            }
+            return;
+        }
+        let Some(base) = self.irq_base(irq) else {
+            return;
+        };
+        let _guard = self.lock.lock();
+        unsafe { ((base + GICD_IPRIORITYR + irq) as *mut u8).write_volatile(priority) };
+        if irq >= 16 {
+            // The higher bit of the two bits of each interrupt selects the edge.
+            let icfgr = base + GICD_ICFGR + (irq / 16) * 4;
+            let bit = 1 << ((irq % 16) * 2 + 1);
+            match edge {
+                true => write32(icfgr, read32(icfgr) | bit),
+                false => write32(icfgr, read32(icfgr) & !bit),
+            }
+        }
+        if irq >= 32 {
+            let target = cpu_route(cpu).unwrap_or_else(|| {
+                log::error!("GICv3 can't route irq {} to cpu {}", irq, cpu);
+                route()
+            });
+            self.set_route(irq, target);
+        }
+        write32(base + GICD_ISENABLER + (irq / 32) * 4, 1 << (irq % 32));
+    }
+
+    /// Send the SGI to the `cpu`, it's dropped if the `cpu` isn't initialized.
+    pub(super) fn send_sgi(&self, cpu: usize, sgi: usize) {
+        let Some(route) = cpu_route(cpu) else {
+            log::error!("GICv3 can't send the SGI to cpu {}", cpu);
+            return;
+        };
+        let aff0 = route & 0xff;
+        // The target list holds 16 CPUs, the range selector picks the group of 16.
+        let value = (1 << (aff0 % 16))
+            | (((route >> 8) & 0xff) << 16)
+            | ((sgi as u64 & 0xf) << 24)
+            | (((route >> 16) & 0xff) << 32)
+            | ((aff0 / 16) << 44)
+            | (((route >> 32) & 0xff) << 48);
+        unsafe { asm!("msr icc_sgi1r_el1, {}", "isb", in(reg) value) };
+    }
+
+    /// Acknowledge the highest priority pending interrupt, 1023 if there is none.
+    #[inline]
+    pub(super) fn iar(&self) -> u32 {
+        let iar: usize;
+        unsafe { asm!("mrs {}, icc_iar1_el1", out(reg) iar) };
+        iar as u32
+    }
+
+    /// Complete the interrupt, which is also deactivated.
+    #[inline]
+    pub(super) fn eoi(&self, iar: u32) {
+        unsafe { asm!("msr icc_eoir1_el1, {}", "isb", in(reg) iar as usize) };
+    }
+}
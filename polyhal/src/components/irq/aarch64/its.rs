@@ -0,0 +1,380 @@
+//! Interrupt Translation Service of the GICv3.
+//!
+//! The ITS translates the MSI written to `GITS_TRANSLATER` to an LPI, by the
+//! DeviceID of the writer and the EventID written. The LPIs are configured in
+//! the memory tables shared by all the redistributors, each CPU has its own
+//! pending table and a collection whose id is the CPU id.
+//!
+//! The EventID of an LPI is its offset from [LPI_BASE], so the interrupt
+//! translation table of each device covers all the LPIs.
+
+use core::arch::asm;
+use core::ops::Range;
+
+use arrayvec::ArrayVec;
+
+use super::gicv3::{read32, write32, DEFAULT_PRIORITY};
+use crate::common::frame_alloc;
+use crate::components::irq::msi::VectorAllocator;
+use crate::consts::VIRT_ADDR_START;
+use crate::pagetable::PAGE_SIZE;
+use crate::utils::MutexNoIrq;
+use crate::PhysAddr;
+
+const GITS_CTLR: usize = 0x0000;
+const GITS_TYPER: usize = 0x0008;
+const GITS_CBASER: usize = 0x0080;
+const GITS_CWRITER: usize = 0x0088;
+const GITS_CREADR: usize = 0x0090;
+const GITS_BASER: usize = 0x0100;
+const GITS_TRANSLATER: usize = 0x1_0040;
+
+const GITS_CTLR_ENABLED: u32 = 1 << 0;
+const GITS_TYPER_PTA: u64 = 1 << 19;
+
+const GITS_BASER_VALID: u64 = 1 << 63;
+const GITS_BASER_TYPE_DEVICE: u64 = 1;
+const GITS_BASER_TYPE_COLLECTION: u64 = 4;
+/// The maximum number of the pages of a table.
+const GITS_BASER_MAX_PAGES: usize = 256;
+
+const GICR_CTLR: usize = 0x0000;
+const GICR_TYPER: usize = 0x0008;
+const GICR_PROPBASER: usize = 0x0070;
+const GICR_PENDBASER: usize = 0x0078;
+const GICR_CTLR_ENABLE_LPIS: u32 = 1 << 0;
+
+/// The inner cacheability of the tables in `GITS_BASER` and `GITS_CBASER`.
+const CACHE_RAWAWB_HIGH: u64 = 7 << 59;
+/// The inner cacheability of the tables in `GICR_PROPBASER` and `GICR_PENDBASER`.
+const CACHE_RAWAWB_LOW: u64 = 7 << 7;
+const INNER_SHAREABLE: u64 = 1 << 10;
+
+const CMD_MAPD: u64 = 0x08;
+const CMD_MAPC: u64 = 0x09;
+const CMD_MAPTI: u64 = 0x0a;
+const CMD_INV: u64 = 0x0c;
+const CMD_INVALL: u64 = 0x0d;
+const CMD_DISCARD: u64 = 0x0f;
+const CMD_SYNC: u64 = 0x05;
+const CMD_VALID: u64 = 1 << 63;
+
+/// The first LPI.
+pub(super) const LPI_BASE: usize = 8192;
+
+/// The number of the LPIs which can be allocated.
+const LPI_COUNT: usize = 256;
+
+/// The interrupt ids are 14 bits, which covers the LPIs from [LPI_BASE].
+const LPI_ID_BITS: u64 = 14;
+
+/// The LPI configuration table covers the LPIs of [LPI_ID_BITS].
+const PROP_TABLE_SIZE: usize = (1 << LPI_ID_BITS) - LPI_BASE;
+
+/// The pending table must be aligned to 64KB.
+const PEND_TABLE_ALIGN: usize = 0x1_0000;
+
+/// The command queue, one page holds 128 commands.
+const CMD_QUEUE_SIZE: usize = PAGE_SIZE;
+
+/// The configuration byte of an LPI, the priority is in bits 7:2.
+const LPI_ENABLE: u8 = 1 << 0;
+const LPI_GROUP1: u8 = 1 << 1;
+
+/// The maximum number of the mapped devices.
+const MAX_DEVICES: usize = 64;
+
+/// The maximum number of the CPUs receiving the LPIs.
+const MAX_CPUS: usize = 64;
+
+/// The maximum width of the DeviceIDs, the device table is flat.
+const MAX_DEVICE_BITS: usize = 16;
+
+#[inline]
+fn read64(addr: usize) -> u64 {
+    unsafe { (addr as *const u64).read_volatile() }
+}
+
+#[inline]
+fn write64(addr: usize, value: u64) {
+    unsafe { (addr as *mut u64).write_volatile(value) }
+}
+
+/// Allocate the zeroed memory from the boot memory area.
+///
+/// It must be called on the primary CPU when booting.
+fn alloc_zeroed(size: usize, align: usize) -> PhysAddr {
+    let ptr = unsafe { crate::mem::alloc(size + align) } as usize;
+    let addr = PhysAddr::new(ptr.next_multiple_of(align));
+    unsafe { addr.get_mut_ptr::<u8>().write_bytes(0, size) };
+    addr
+}
+
+struct CmdQueue {
+    base: usize,
+    /// The offset of the next command.
+    writer: usize,
+}
+
+pub(super) struct Its {
+    base: usize,
+    translater: PhysAddr,
+    /// The width of the DeviceIDs.
+    device_bits: usize,
+    /// The size of an entry of the interrupt translation table.
+    itt_entry_size: usize,
+    /// The redistributors are targeted by the physical addresses.
+    pta: bool,
+    /// The LPI configuration table.
+    prop: PhysAddr,
+    /// The pending tables of the CPUs, one 64KB frame each.
+    pend: PhysAddr,
+    cpus: usize,
+    /// The `RDbase` field of the commands of each CPU.
+    targets: MutexNoIrq<[Option<u64>; MAX_CPUS]>,
+    cmdq: MutexNoIrq<CmdQueue>,
+    /// The mapped devices and their interrupt translation tables.
+    devices: MutexNoIrq<ArrayVec<(u32, PhysAddr), MAX_DEVICES>>,
+    /// The device each LPI is mapped to.
+    events: MutexNoIrq<[Option<u32>; LPI_COUNT]>,
+    allocated: VectorAllocator<{ LPI_COUNT / 64 }>,
+}
+
+impl Its {
+    /// Set up the tables and the command queue of the ITS for `cpus` CPUs.
+    pub(super) fn new(base: PhysAddr, cpus: usize) -> Self {
+        let cpus = cpus.clamp(1, MAX_CPUS);
+        let typer = read64(base.get_mut_ptr::<u8>() as usize + GITS_TYPER);
+        let mut its = Self {
+            base: base.get_mut_ptr::<u8>() as usize,
+            translater: PhysAddr::new(base.raw() + GITS_TRANSLATER),
+            device_bits: (((typer >> 13) & 0x1f) as usize + 1).min(MAX_DEVICE_BITS),
+            itt_entry_size: ((typer >> 4) & 0xf) as usize + 1,
+            pta: typer & GITS_TYPER_PTA != 0,
+            prop: alloc_zeroed(PROP_TABLE_SIZE, PAGE_SIZE),
+            pend: alloc_zeroed(cpus * PEND_TABLE_ALIGN, PEND_TABLE_ALIGN),
+            cpus,
+            targets: MutexNoIrq::new([None; MAX_CPUS]),
+            cmdq: MutexNoIrq::new(CmdQueue {
+                base: 0,
+                writer: 0,
+            }),
+            devices: MutexNoIrq::new(ArrayVec::new()),
+            events: MutexNoIrq::new([None; LPI_COUNT]),
+            allocated: VectorAllocator::new(),
+        };
+        // The LPIs are disabled until they are enabled by `set_enable`.
+        let prop = its.prop.get_mut_ptr::<u8>();
+        for i in 0..PROP_TABLE_SIZE {
+            unsafe { prop.add(i).write_volatile(DEFAULT_PRIORITY | LPI_GROUP1) };
+        }
+
+        write32(its.base + GITS_CTLR, 0);
+        for i in 0..8 {
+            its.init_table(GITS_BASER + i * 8);
+        }
+        let cmdq = alloc_zeroed(CMD_QUEUE_SIZE, PAGE_SIZE);
+        its.cmdq.lock().base = cmdq.get_mut_ptr::<u8>() as usize;
+        write64(
+            its.base + GITS_CBASER,
+            GITS_BASER_VALID
+                | CACHE_RAWAWB_HIGH
+                | INNER_SHAREABLE
+                | cmdq.raw() as u64
+                | (CMD_QUEUE_SIZE / PAGE_SIZE - 1) as u64,
+        );
+        write64(its.base + GITS_CWRITER, 0);
+        write32(
+            its.base + GITS_CTLR,
+            read32(its.base + GITS_CTLR) | GITS_CTLR_ENABLED,
+        );
+        log::info!(
+            "GICv3 ITS @ {:#x}, {} bits DeviceID, {} LPIs",
+            base.raw(),
+            its.device_bits,
+            LPI_COUNT
+        );
+        its
+    }
+
+    /// Allocate the flat table of the `GITS_BASER` if it's the device or collection table.
+    fn init_table(&mut self, reg: usize) {
+        let baser = read64(self.base + reg);
+        let ty = (baser >> 56) & 0x7;
+        let entry_size = ((baser >> 48) & 0x1f) as usize + 1;
+        let entries = match ty {
+            GITS_BASER_TYPE_DEVICE => 1 << self.device_bits,
+            GITS_BASER_TYPE_COLLECTION => MAX_CPUS,
+            _ => return,
+        };
+        let size = (entries * entry_size)
+            .next_multiple_of(PAGE_SIZE)
+            .min(GITS_BASER_MAX_PAGES * PAGE_SIZE);
+        if ty == GITS_BASER_TYPE_DEVICE {
+            // The DeviceIDs beyond the table can't be mapped.
+            self.device_bits = self.device_bits.min((size / entry_size).ilog2() as usize);
+        }
+        let table = alloc_zeroed(size, PAGE_SIZE);
+        write64(
+            self.base + reg,
+            GITS_BASER_VALID
+                | CACHE_RAWAWB_HIGH
+                | (ty << 56)
+                | ((entry_size as u64 - 1) << 48)
+                | table.raw() as u64
+                | INNER_SHAREABLE
+                | (size / PAGE_SIZE - 1) as u64,
+        );
+    }
+
+    /// Enable the LPIs of the redistributor `rd` and map the collection of the CPU.
+    pub(super) fn init_cpu(&self, rd: usize, cpu: usize) {
+        if cpu >= self.cpus {
+            log::warn!("GICv3 ITS has no pending table for CPU {}", cpu);
+            return;
+        }
+        write64(
+            rd + GICR_PROPBASER,
+            self.prop.raw() as u64 | CACHE_RAWAWB_LOW | INNER_SHAREABLE | (LPI_ID_BITS - 1),
+        );
+        write64(
+            rd + GICR_PENDBASER,
+            (self.pend.raw() + cpu * PEND_TABLE_ALIGN) as u64 | CACHE_RAWAWB_LOW | INNER_SHAREABLE,
+        );
+        write32(
+            rd + GICR_CTLR,
+            read32(rd + GICR_CTLR) | GICR_CTLR_ENABLE_LPIS,
+        );
+
+        let target = match self.pta {
+            true => (rd - VIRT_ADDR_START) as u64,
+            false => ((read64(rd + GICR_TYPER) >> 8) & 0xffff) << 16,
+        };
+        self.targets.lock()[cpu] = Some(target);
+        self.command([CMD_MAPC, 0, CMD_VALID | target | cpu as u64, 0]);
+        self.command([CMD_SYNC, 0, target, 0]);
+    }
+
+    /// Queue the command and wait until the ITS consumes it.
+    fn command(&self, cmd: [u64; 4]) {
+        let mut cmdq = self.cmdq.lock();
+        let writer = cmdq.writer;
+        for (i, dw) in cmd.iter().enumerate() {
+            write64(cmdq.base + writer + i * 8, *dw);
+        }
+        cmdq.writer = (writer + cmd.len() * 8) % CMD_QUEUE_SIZE;
+        unsafe { asm!("dsb ishst") };
+        write64(self.base + GITS_CWRITER, cmdq.writer as u64);
+        while read64(self.base + GITS_CREADR) as usize != cmdq.writer {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// The address written by the devices.
+    #[inline]
+    pub(super) fn translater(&self) -> PhysAddr {
+        self.translater
+    }
+
+    /// Allocate `count` contiguous LPIs.
+    pub(super) fn alloc(&self, count: usize) -> Option<Range<usize>> {
+        let events = self.allocated.alloc(0..LPI_COUNT, count)?;
+        Some(events.start + LPI_BASE..events.end + LPI_BASE)
+    }
+
+    /// Discard the mappings of the LPIs and free them.
+    pub(super) fn free(&self, lpis: Range<usize>) {
+        let events = lpis.start.max(LPI_BASE) - LPI_BASE..lpis.end.saturating_sub(LPI_BASE);
+        for event in events.clone().filter(|event| *event < LPI_COUNT) {
+            if let Some(device) = self.events.lock()[event].take() {
+                self.command([CMD_DISCARD | ((device as u64) << 32), event as u64, 0, 0]);
+            }
+        }
+        self.allocated.free(events);
+    }
+
+    /// Map the LPIs to the device, they are delivered to the collection of the CPU.
+    ///
+    /// Return `false` if the device or the CPU can't be mapped.
+    pub(super) fn bind(&self, device: u32, lpis: Range<usize>, cpu: usize) -> bool {
+        if device as usize >= 1 << self.device_bits || lpis.start < LPI_BASE {
+            return false;
+        }
+        let Some(target) = self.targets.lock().get(cpu).copied().flatten() else {
+            return false;
+        };
+        {
+            let mut devices = self.devices.lock();
+            if !devices.iter().any(|(id, _)| *id == device) {
+                if devices.is_full() || LPI_COUNT * self.itt_entry_size > PAGE_SIZE {
+                    return false;
+                }
+                let itt = frame_alloc();
+                unsafe { itt.get_mut_ptr::<u8>().write_bytes(0, PAGE_SIZE) };
+                self.command([
+                    CMD_MAPD | ((device as u64) << 32),
+                    LPI_COUNT.ilog2() as u64 - 1,
+                    CMD_VALID | itt.raw() as u64,
+                    0,
+                ]);
+                devices.push((device, itt));
+            }
+        }
+        for lpi in lpis.filter(|lpi| *lpi < LPI_BASE + LPI_COUNT) {
+            let event = lpi - LPI_BASE;
+            self.events.lock()[event] = Some(device);
+            self.command([
+                CMD_MAPTI | ((device as u64) << 32),
+                event as u64 | ((lpi as u64) << 32),
+                cpu as u64,
+                0,
+            ]);
+            self.command([CMD_INV | ((device as u64) << 32), event as u64, 0, 0]);
+        }
+        self.command([CMD_SYNC, 0, target, 0]);
+        true
+    }
+
+    /// Update the configuration byte of the LPI, and invalidate the caches
+    /// of the collection of the CPU.
+    fn update_config(&self, lpi: usize, cpu: usize, f: impl FnOnce(u8) -> u8) {
+        if !(LPI_BASE..LPI_BASE + LPI_COUNT).contains(&lpi) {
+            return;
+        }
+        let config = unsafe { self.prop.get_mut_ptr::<u8>().add(lpi - LPI_BASE) };
+        unsafe {
+            config.write_volatile(f(config.read_volatile()) | LPI_GROUP1);
+            asm!("dsb ishst");
+        }
+        if let Some(target) = self.targets.lock().get(cpu).copied().flatten() {
+            self.command([CMD_INVALL, 0, cpu as u64, 0]);
+            self.command([CMD_SYNC, 0, target, 0]);
+        }
+    }
+
+    /// Enable or disable the LPI, the caches of the collection of the CPU are invalidated.
+    pub(super) fn set_enable(&self, lpi: usize, enable: bool, cpu: usize) {
+        self.update_config(lpi, cpu, |config| match enable {
+            true => config | LPI_ENABLE,
+            false => config & !LPI_ENABLE,
+        });
+    }
+
+    /// Check if the LPI is enabled.
+    pub(super) fn is_enabled(&self, lpi: usize) -> bool {
+        if !(LPI_BASE..LPI_BASE + LPI_COUNT).contains(&lpi) {
+            return false;
+        }
+        let config = unsafe { self.prop.get_mut_ptr::<u8>().add(lpi - LPI_BASE).read_volatile() };
+        config & LPI_ENABLE != 0
+    }
+
+    /// Set the priority of the LPI, which takes effect when it's enabled.
+    pub(super) fn set_priority(&self, lpi: usize, priority: u8) {
+        if !(LPI_BASE..LPI_BASE + LPI_COUNT).contains(&lpi) {
+            return;
+        }
+        let config = unsafe { self.prop.get_mut_ptr::<u8>().add(lpi - LPI_BASE) };
+        unsafe { config.write_volatile((priority & 0xfc) | (config.read_volatile() & LPI_ENABLE)) };
+    }
+}
@@ -1,22 +1,171 @@
+mod eiointc;
+mod pch_pic;
+
+use fdt_parser::Node;
+use loongArch64::iocsr::{iocsr_read_w, iocsr_write_w};
+use loongArch64::ipi::send_ipi_single;
 use loongArch64::register::crmd;
+use loongArch64::register::ecfg::{self, LineBasedInterrupt};
+use spin::Once;
 
-use crate::components::irq::IRQ;
+use crate::arch::hart_id;
+use crate::components::irq::msi::VectorAllocator;
+use crate::components::irq::of::IrqCells;
+use crate::components::irq::{IRQVector, IrqConfig, IrqPolarity, IrqTrigger, MsiDescriptor, IRQ};
+use crate::ctor::CtorType;
+use crate::mem::get_fdt;
+use crate::utils::MutexNoIrq;
+use crate::PhysAddr;
+use eiointc::EIOINTC_VECTORS;
+use pch_pic::{PchMsi, PchPic};
 
 /// Timer IRQ of loongarch64
 pub const TIMER_IRQ: usize = 11;
 
+/// The line of the external interrupts, `HWI0`.
+///
+/// The IRQ numbers of the [IRQ] interface are the vectors of the EIOINTC.
+pub const EXT_IRQ: usize = 2;
+
+/// The line of the IPIs.
+pub const IPI_IRQ: usize = 12;
+
+const IOCSR_IPI_STATUS: usize = 0x1000;
+const IOCSR_IPI_EN: usize = 0x1004;
+const IOCSR_IPI_CLEAR: usize = 0x100c;
+
+const PCH_PIC_COMPATIBLE: &str = "loongson,pch-pic-1.0";
+
+/// The LS7A of the QEMU virt machine, used if the device tree has no PCH.
+const PCH_PIC_BASE: PhysAddr = PhysAddr::new(0x1000_0000);
+const PCH_MSI_ADDR: PhysAddr = PhysAddr::new(0x2ff0_0000);
+const PCH_MSI_VEC_BASE: usize = 32;
+
+struct Pch {
+    pic: PchPic,
+    msi: PchMsi,
+}
+
+static PCH: Once<Pch> = Once::new();
+
+/// Serialize the configuration of the controllers.
+static LOCK: MutexNoIrq<()> = MutexNoIrq::new(());
+
+/// The allocated vectors of the PCH-MSI.
+static MSI_ALLOCATED: VectorAllocator<{ EIOINTC_VECTORS / 64 }> = VectorAllocator::new();
+
+/// Find the PCH in the device tree and initialize the controllers.
+fn probe() -> Pch {
+    eiointc::init();
+    let fdt = get_fdt().ok();
+    let pic_node = fdt
+        .as_ref()
+        .and_then(|fdt| fdt.find_compatible(&[PCH_PIC_COMPATIBLE]).next());
+    let msi_node = fdt
+        .as_ref()
+        .and_then(|fdt| fdt.find_compatible(&["loongson,pch-msi-1.0"]).next());
+    let reg = |node: &Option<Node>| {
+        let reg = node.as_ref()?.reg()?.next()?;
+        Some(PhysAddr::new(reg.address as usize))
+    };
+    let u32_prop = |node: &Option<Node>, name: &str| {
+        Some(node.as_ref()?.find_property(name)?.u32() as usize)
+    };
+
+    let pic = PchPic::new(
+        reg(&pic_node).unwrap_or(PCH_PIC_BASE),
+        u32_prop(&pic_node, "loongson,pic-base-vec").unwrap_or(0),
+    );
+    let msi_base = u32_prop(&msi_node, "loongson,msi-base-vec")
+        .unwrap_or(PCH_MSI_VEC_BASE)
+        .min(EIOINTC_VECTORS);
+    let msi_count = u32_prop(&msi_node, "loongson,msi-num-vecs")
+        .unwrap_or(EIOINTC_VECTORS - PCH_MSI_VEC_BASE);
+    let msi = PchMsi {
+        address: reg(&msi_node).unwrap_or(PCH_MSI_ADDR),
+        vectors: msi_base..(msi_base + msi_count).min(EIOINTC_VECTORS),
+    };
+    Pch { pic, msi }
+}
+
+/// Initialize the controllers on the primary CPU, and enable `HWI0` and the IPIs on the current CPU.
+pub(crate) fn init() {
+    PCH.call_once(probe);
+    iocsr_write_w(IOCSR_IPI_EN, u32::MAX);
+    ecfg::set_lie(ecfg::read().lie() | LineBasedInterrupt::HWI0 | LineBasedInterrupt::IPI);
+}
+
+/// Send the IPI to the CPU through the IOCSR.
+#[inline]
+pub(super) fn send_ipi_to(cpu: usize) {
+    send_ipi_single(cpu, 1);
+}
+
+/// Clear the IPI status of the current CPU.
+#[inline]
+pub(super) fn ack_ipi() {
+    iocsr_write_w(IOCSR_IPI_CLEAR, iocsr_read_w(IOCSR_IPI_STATUS));
+}
+
 /// Implement IRQ operations for the IRQ interface.
 impl IRQ {
     /// Enable irq for the given IRQ number.
+    ///
+    /// The irq is routed to the current CPU.
     #[inline]
-    pub fn irq_enable(_irq_num: usize) {
-        log::warn!("irq not implemented in loongarch64 platform yet");
+    pub fn irq_enable(irq_num: usize) {
+        let _guard = LOCK.lock();
+        eiointc::set_enable(irq_num, Some(hart_id()), true);
+        if let Some(pch) = PCH.get() {
+            if let Some(input) = pch.pic.input(irq_num) {
+                pch.pic.set_enable(input, true);
+            }
+        }
     }
 
     /// Disable irq for the given IRQ number.
     #[inline]
-    pub fn irq_disable(_irq_num: usize) {
-        log::warn!("irq not implemented in loongarch64 platform yet");
+    pub fn irq_disable(irq_num: usize) {
+        let _guard = LOCK.lock();
+        if let Some(pch) = PCH.get() {
+            if let Some(input) = pch.pic.input(irq_num) {
+                pch.pic.set_enable(input, false);
+            }
+        }
+        eiointc::set_enable(irq_num, None, false);
+    }
+
+    /// Check if the irq is enabled, and unmasked in the PCH-PIC if it's an input of it.
+    pub fn irq_enabled(irq_num: usize) -> bool {
+        let input = PCH.get().and_then(|pch| Some((pch, pch.pic.input(irq_num)?)));
+        eiointc::is_enabled(irq_num) && input.is_none_or(|(pch, input)| pch.pic.is_enabled(input))
+    }
+
+    /// Check if the irq is pending on the current CPU.
+    #[inline]
+    pub fn is_pending(irq_num: usize) -> bool {
+        eiointc::is_pending(irq_num)
+    }
+
+    /// Configure the irq and enable it on the target CPUs.
+    ///
+    /// The EIOINTC routes the irq to the targets in the node of the lowest one,
+    /// the trigger and the polarity are configured for the inputs of the PCH-PIC.
+    /// The priority isn't configurable.
+    pub fn configure(irq_num: usize, config: IrqConfig) {
+        let _guard = LOCK.lock();
+        eiointc::set_route(irq_num, config.targets());
+        eiointc::set_enable(irq_num, None, true);
+        if let Some(pch) = PCH.get() {
+            if let Some(input) = pch.pic.input(irq_num) {
+                pch.pic.configure(
+                    input,
+                    config.trigger == IrqTrigger::Edge,
+                    config.polarity == IrqPolarity::Low,
+                );
+                pch.pic.set_enable(input, true);
+            }
+        }
     }
 
     /// Enable interrupt
@@ -37,3 +186,65 @@ impl IRQ {
         crmd::read().ie()
     }
 }
+
+/// Implmente the irq vector methods
+impl IRQVector {
+    /// Get the irq number in this vector
+    #[inline]
+    pub const fn irq_num(&self) -> usize {
+        self.0
+    }
+
+    /// Acknowledge the irq
+    pub fn ack(&self) {
+        if let Some(pch) = PCH.get() {
+            if let Some(input) = pch.pic.input(self.0) {
+                pch.pic.ack(input);
+            }
+        }
+    }
+}
+
+/// Claim the pending external irq of the current CPU.
+///
+/// Return `None` if there is no pending irq.
+#[inline]
+pub fn get_irq() -> Option<IRQVector> {
+    eiointc::claim().map(IRQVector)
+}
+
+/// Allocate the vectors of the PCH-MSI, routed to the `affinity` CPU when they are enabled.
+pub(super) fn alloc_msi_vectors(count: usize, affinity: usize) -> Option<MsiDescriptor> {
+    let msi = &PCH.get()?.msi;
+    let vectors = MSI_ALLOCATED.alloc(msi.vectors.clone(), count)?;
+    Some(MsiDescriptor {
+        address: msi.address,
+        data: vectors.start as u32,
+        vectors,
+        affinity,
+    })
+}
+
+/// Free the vectors allocated by [alloc_msi_vectors].
+pub(super) fn free_msi_vectors(desc: &MsiDescriptor) {
+    MSI_ALLOCATED.free(desc.vectors.clone());
+}
+
+/// Translate the interrupt specifier of the PCH-PIC, `<input flags>`, or the EIOINTC, `<vector>`.
+pub(super) fn translate_of_irq(controller: &Node, cells: &IrqCells) -> Option<(usize, u32)> {
+    let pch_pic = controller.compatibles().any(|c| c == PCH_PIC_COMPATIBLE);
+    let eiointc = controller.compatibles().any(|c| c.ends_with("-eiointc"));
+    match cells.as_slice() {
+        [input, flags] if pch_pic => {
+            let vector = PCH.get()?.pic.vector(*input as usize)?;
+            Some((vector, *flags))
+        }
+        [vector] if eiointc => {
+            let vector = *vector as usize;
+            (vector < EIOINTC_VECTORS).then_some((vector, 0))
+        }
+        _ => None,
+    }
+}
+
+ph_ctor!(LOONGARCH64_INIT_IRQ, CtorType::Cpu, init);
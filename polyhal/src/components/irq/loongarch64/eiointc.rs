@@ -0,0 +1,117 @@
+//! Extended I/O interrupt controller.
+//!
+//! The 256 vectors are configured through the IOCSRs, all of them are
+//! delivered to the `HWI0` line of the CPUs. The pending vectors of the
+//! current CPU are in its own `ISR` registers.
+
+use loongArch64::iocsr::{iocsr_read_d, iocsr_read_w, iocsr_write_b, iocsr_write_d, iocsr_write_w};
+
+const IOCSR_MISC_FUNC: usize = 0x420;
+const IOCSR_MISC_FUNC_EXT_IOI_EN: u64 = 1 << 48;
+
+const EIOINTC_NODEMAP: usize = 0x14a0;
+const EIOINTC_IPMAP: usize = 0x14c0;
+const EIOINTC_ENABLE: usize = 0x1600;
+const EIOINTC_BOUNCE: usize = 0x1680;
+const EIOINTC_ISR: usize = 0x1800;
+const EIOINTC_ROUTE: usize = 0x1c00;
+
+/// The number of the vectors.
+pub(super) const EIOINTC_VECTORS: usize = 256;
+
+/// Enable the controller and map all the vectors to `HWI0` of the CPU 0.
+///
+/// The vectors are disabled until [set_enable] is called.
+pub(super) fn init() {
+    iocsr_write_d(
+        IOCSR_MISC_FUNC,
+        iocsr_read_d(IOCSR_MISC_FUNC) | IOCSR_MISC_FUNC_EXT_IOI_EN,
+    );
+    // All the vectors are on the node 0.
+    for i in 0..EIOINTC_VECTORS / 128 {
+        iocsr_write_w(EIOINTC_NODEMAP + i * 4, 0x0001_0001);
+    }
+    // Each byte maps 32 vectors to the lowest set bit, `HWI0`.
+    for i in 0..EIOINTC_VECTORS / 32 / 4 {
+        iocsr_write_w(EIOINTC_IPMAP + i * 4, 0x0101_0101);
+    }
+    for i in 0..EIOINTC_VECTORS / 32 {
+        iocsr_write_w(EIOINTC_ENABLE + i * 4, 0);
+        iocsr_write_w(EIOINTC_BOUNCE + i * 4, 0);
+    }
+    for i in 0..EIOINTC_VECTORS {
+        iocsr_write_b(EIOINTC_ROUTE + i, route(0));
+    }
+}
+
+/// The route of a CPU, the CPU bitmap in the low nibble and the node in the high nibble.
+#[inline]
+fn route(cpu: usize) -> u8 {
+    ((1 << (cpu % 4)) | ((cpu / 4) << 4)) as u8
+}
+
+/// Route the vector to the CPUs in `cpus` of the node of the lowest one.
+///
+/// The vector bounces among the CPUs if there are more than one.
+pub(super) fn set_route(vector: usize, cpus: usize) {
+    if vector >= EIOINTC_VECTORS || cpus == 0 {
+        return;
+    }
+    let node = cpus.trailing_zeros() as usize / 4;
+    let map = (cpus >> (node * 4)) & 0xf;
+    iocsr_write_b(EIOINTC_ROUTE + vector, (map | (node << 4)) as u8);
+
+    let reg = EIOINTC_BOUNCE + (vector / 32) * 4;
+    let value = iocsr_read_w(reg);
+    match map.count_ones() > 1 {
+        true => iocsr_write_w(reg, value | (1 << (vector % 32))),
+        false => iocsr_write_w(reg, value & !(1 << (vector % 32))),
+    }
+}
+
+/// Enable the vector and route it to the CPU, or disable it.
+///
+/// The route is kept if `cpu` is `None`.
+pub(super) fn set_enable(vector: usize, cpu: Option<usize>, enable: bool) {
+    if vector >= EIOINTC_VECTORS {
+        return;
+    }
+    let reg = EIOINTC_ENABLE + (vector / 32) * 4;
+    let value = iocsr_read_w(reg);
+    match enable {
+        true => {
+            if let Some(cpu) = cpu {
+                set_route(vector, 1 << cpu);
+            }
+            iocsr_write_w(reg, value | (1 << (vector % 32)));
+        }
+        false => iocsr_write_w(reg, value & !(1 << (vector % 32))),
+    }
+}
+
+/// Check if the vector is enabled.
+pub(super) fn is_enabled(vector: usize) -> bool {
+    vector < EIOINTC_VECTORS
+        && iocsr_read_w(EIOINTC_ENABLE + (vector / 32) * 4) & (1 << (vector % 32)) != 0
+}
+
+/// Check if the vector is pending on the current CPU.
+pub(super) fn is_pending(vector: usize) -> bool {
+    vector < EIOINTC_VECTORS
+        && iocsr_read_d(EIOINTC_ISR + (vector / 64) * 8) & (1 << (vector % 64)) != 0
+}
+
+/// Claim the lowest pending vector of the current CPU.
+///
+/// The pending bit is cleared before the interrupt is handled.
+pub(super) fn claim() -> Option<usize> {
+    (0..EIOINTC_VECTORS / 64).find_map(|i| {
+        let pending = iocsr_read_d(EIOINTC_ISR + i * 8);
+        if pending == 0 {
+            return None;
+        }
+        let bit = pending.trailing_zeros() as usize;
+        iocsr_write_d(EIOINTC_ISR + i * 8, 1 << bit);
+        Some(i * 64 + bit)
+    })
+}
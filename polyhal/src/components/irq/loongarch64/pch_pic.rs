@@ -0,0 +1,127 @@
+//! LS7A platform controller hub interrupt controllers.
+//!
+//! The inputs of the PCH-PIC are sent to the EIOINTC as the HT messages,
+//! the input `n` is the vector `base + n`. The PCH-MSI is the doorbell of
+//! the MSIs, the data written to it is the vector.
+
+use crate::PhysAddr;
+
+const PCH_PIC_INT_ID: usize = 0x000;
+const PCH_PIC_INT_MASK: usize = 0x020;
+const PCH_PIC_HTMSI_EN: usize = 0x040;
+const PCH_PIC_INT_EDGE: usize = 0x060;
+const PCH_PIC_INT_CLEAR: usize = 0x080;
+const PCH_PIC_ROUTE_ENTRY: usize = 0x100;
+const PCH_PIC_HTMSI_VEC: usize = 0x200;
+const PCH_PIC_INT_POL: usize = 0x3e0;
+
+/// The maximum number of the inputs.
+const PCH_PIC_MAX_INPUTS: usize = 64;
+
+pub(super) struct PchPic {
+    base: usize,
+    /// The vector of the input 0.
+    vec_base: usize,
+    inputs: usize,
+}
+
+impl PchPic {
+    /// Initialize the PCH-PIC, all the inputs are masked and level-triggered.
+    pub(super) fn new(base: PhysAddr, vec_base: usize) -> Self {
+        let mut pic = Self {
+            base: base.get_mut_ptr::<u8>() as usize,
+            vec_base,
+            inputs: 0,
+        };
+        pic.inputs = ((((pic.read(PCH_PIC_INT_ID) >> 48) & 0xff) + 1) as usize)
+            .min(PCH_PIC_MAX_INPUTS);
+        for i in 0..pic.inputs {
+            pic.write_byte(PCH_PIC_HTMSI_VEC + i, (vec_base + i) as u8);
+            pic.write_byte(PCH_PIC_ROUTE_ENTRY + i, 1);
+        }
+        pic.write(PCH_PIC_INT_MASK, u64::MAX);
+        pic.write(PCH_PIC_INT_EDGE, 0);
+        pic.write(PCH_PIC_INT_POL, 0);
+        pic.write(PCH_PIC_INT_CLEAR, u64::MAX);
+        pic.write(PCH_PIC_HTMSI_EN, u64::MAX);
+        log::info!(
+            "PCH-PIC @ {:#x}, {} inputs from vector {}",
+            base.raw(),
+            pic.inputs,
+            vec_base
+        );
+        pic
+    }
+
+    #[inline]
+    fn read(&self, offset: usize) -> u64 {
+        unsafe { ((self.base + offset) as *const u64).read_volatile() }
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, value: u64) {
+        unsafe { ((self.base + offset) as *mut u64).write_volatile(value) }
+    }
+
+    #[inline]
+    fn write_byte(&self, offset: usize, value: u8) {
+        unsafe { ((self.base + offset) as *mut u8).write_volatile(value) }
+    }
+
+    /// Get the input of the vector, if it's from the PCH-PIC.
+    #[inline]
+    pub(super) fn input(&self, vector: usize) -> Option<usize> {
+        vector
+            .checked_sub(self.vec_base)
+            .filter(|input| *input < self.inputs)
+    }
+
+    /// Get the vector of the input.
+    #[inline]
+    pub(super) fn vector(&self, input: usize) -> Option<usize> {
+        (input < self.inputs).then_some(self.vec_base + input)
+    }
+
+    /// Unmask or mask the input.
+    pub(super) fn set_enable(&self, input: usize, enable: bool) {
+        let mask = self.read(PCH_PIC_INT_MASK);
+        match enable {
+            true => self.write(PCH_PIC_INT_MASK, mask & !(1 << input)),
+            false => self.write(PCH_PIC_INT_MASK, mask | (1 << input)),
+        }
+    }
+
+    /// Check if the input is unmasked.
+    #[inline]
+    pub(super) fn is_enabled(&self, input: usize) -> bool {
+        self.read(PCH_PIC_INT_MASK) & (1 << input) == 0
+    }
+
+    /// Set the trigger mode and the polarity of the input.
+    pub(super) fn configure(&self, input: usize, edge: bool, low: bool) {
+        let set = |offset: usize, value: bool| {
+            let bits = self.read(offset);
+            match value {
+                true => self.write(offset, bits | (1 << input)),
+                false => self.write(offset, bits & !(1 << input)),
+            }
+        };
+        set(PCH_PIC_INT_EDGE, edge);
+        set(PCH_PIC_INT_POL, low);
+    }
+
+    /// Clear the edge-triggered input, the level-triggered input follows the line.
+    #[inline]
+    pub(super) fn ack(&self, input: usize) {
+        if self.read(PCH_PIC_INT_EDGE) & (1 << input) != 0 {
+            self.write(PCH_PIC_INT_CLEAR, 1 << input);
+        }
+    }
+}
+
+/// The MSI doorbell of the PCH.
+pub(super) struct PchMsi {
+    pub(super) address: PhysAddr,
+    /// The vectors of the MSIs.
+    pub(super) vectors: core::ops::Range<usize>,
+}
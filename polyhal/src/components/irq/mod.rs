@@ -7,6 +7,9 @@
 //! [IRQ::irq_enable]
 //! // Enable irq 3
 //! IRQ::irq_enable(3);
+//! [IRQ::configure]
+//! // Enable irq 3 as an edge-triggered irq of the CPU 1
+//! IRQ::configure(3, IrqConfig { trigger: IrqTrigger::Edge, ..IrqConfig::new(1 << 1) });
 //! [IRQ::irq_disable]
 //! // Disable irq 3
 //! IRQ::irq_disable(3);
@@ -14,6 +17,12 @@
 //! // Check if irq is enabled
 //! // Return true if the irq is enabled.
 //! IRQ::irq_enabled(3);
+//! [IRQ::is_pending]
+//! // Check if irq 3 is pending
+//! IRQ::is_pending(3);
+//! [irq_stats]
+//! // Print the interrupts received by each CPU
+//! irq_stats().for_each(|stat| println!("{:?}", stat));
 //! [IRQ::int_enable]
 //! // Enable interrupt
 //! IRQ::int_enable();
@@ -23,8 +32,33 @@
 //! [IRQ::int_enabled]
 //! // Check if interrupt is enabled
 //! let enabled = IRQ::int_enabled();
+//! [send_ipi]
+//! // Send the IPI of kind 0 to all the other CPUs
+//! send_ipi(IpiTarget::AllButSelf, 0);
+//! [of_irq_config]
+//! // Set up the first interrupt of the device tree node on the current CPU
+//! if let Some((irq, config)) = of_irq_config(&node, 0, 0) {
+//!     IRQ::configure(irq.irq_num(), config);
+//! }
+//! [alloc_msi]
+//! // Allocate 4 MSI vectors delivered to the CPU 0
+//! let desc = alloc_msi(4, 0);
 //! ```
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::hart_id;
+use crate::components::multicore::{handle_calls, CALL_IPI_KIND};
+use crate::ctor::CtorType;
+
+mod msi;
+mod of;
+mod stats;
+
+pub use msi::{alloc_msi, free_msi, MsiDescriptor};
+pub use of::{of_irq_config, of_irq_get};
+pub use stats::{count_irq, irq_stats, IrqSource, IrqStat};
+
 super::define_arch_mods!();
 
 pub struct IRQ;
@@ -39,3 +73,138 @@ impl IRQVector {
         Self(value)
     }
 }
+
+/// The trigger mode of an irq.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqTrigger {
+    Edge,
+    Level,
+}
+
+/// The polarity of an irq, the active level or the triggering edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqPolarity {
+    /// Active high or rising edge.
+    High,
+    /// Active low or falling edge.
+    Low,
+}
+
+/// The configuration of an irq applied by `IRQ::configure`.
+///
+/// The settings which the interrupt controller doesn't support are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqConfig {
+    pub trigger: IrqTrigger,
+    pub polarity: IrqPolarity,
+    /// The priority, higher is more urgent. It's scaled to the priority
+    /// levels of the interrupt controller.
+    pub priority: u8,
+    /// The CPUs which may receive the irq, bit `n` is the CPU `n`, 0 for
+    /// the current CPU. The controllers which route an irq to a single CPU
+    /// choose the lowest one.
+    pub target_cpus: usize,
+}
+
+impl IrqConfig {
+    /// The default priority, the middle of the range.
+    pub const DEFAULT_PRIORITY: u8 = 0x5f;
+
+    /// A level-triggered, active high irq of the default priority.
+    pub const fn new(target_cpus: usize) -> Self {
+        Self {
+            trigger: IrqTrigger::Level,
+            polarity: IrqPolarity::High,
+            priority: Self::DEFAULT_PRIORITY,
+            target_cpus,
+        }
+    }
+
+    /// The target CPUs, the current CPU if [IrqConfig::target_cpus] is 0.
+    #[inline]
+    pub(crate) fn targets(&self) -> usize {
+        match self.target_cpus {
+            0 => 1 << hart_id(),
+            cpus => cpus,
+        }
+    }
+
+    /// The lowest target CPU.
+    #[allow(dead_code)]
+    #[inline]
+    pub(crate) fn first_target(&self) -> usize {
+        self.targets().trailing_zeros() as usize
+    }
+}
+
+/// The maximum number of the CPUs which can receive the IPIs.
+pub const MAX_IPI_CPUS: usize = usize::BITS as usize;
+
+/// The CPUs to send an IPI to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpiTarget {
+    /// The CPU with the given id.
+    Cpu(usize),
+    /// The CPUs in the bitmap, bit `n` is the CPU `n`.
+    Mask(usize),
+    /// All the CPUs except the current one.
+    AllButSelf,
+}
+
+/// The pending IPI kinds of each CPU, bit `k` is the kind `k`.
+static IPI_PENDING: [AtomicUsize; MAX_IPI_CPUS] = [const { AtomicUsize::new(0) }; MAX_IPI_CPUS];
+
+/// Send an IPI of `kind` to the target CPUs.
+///
+/// The `kind` is defined by the kernel and must be less than `usize::BITS - 1`,
+/// the last kind is reserved for `multicore::call_on`.
+/// The IPIs of the same kind are merged until the target CPU takes them, it
+/// receives `TrapType::Ipi` with the bitmap of the pending kinds.
+pub fn send_ipi(target: IpiTarget, kind: usize) {
+    assert!(kind < CALL_IPI_KIND, "invalid IPI kind {}", kind);
+    let mask = match target {
+        IpiTarget::Cpu(cpu) => 1usize.checked_shl(cpu as u32).unwrap_or(0),
+        IpiTarget::Mask(mask) => mask,
+        IpiTarget::AllButSelf => !1usize.checked_shl(hart_id() as u32).unwrap_or(0),
+    };
+    send_ipi_mask(mask, kind);
+}
+
+/// The bitmap of the CPUs which can receive the IPIs, the first
+/// `MAX_IPI_CPUS` CPUs at most.
+fn ipi_cpus() -> usize {
+    match crate::common::get_cpu_num().min(MAX_IPI_CPUS) {
+        0 => 0,
+        num => usize::MAX >> (MAX_IPI_CPUS - num),
+    }
+}
+
+/// Send an IPI of `kind` to the CPUs in `mask`, the CPUs not present are ignored.
+pub(crate) fn send_ipi_mask(mask: usize, kind: usize) {
+    let mask = mask & ipi_cpus();
+    for (cpu, pending) in IPI_PENDING.iter().enumerate() {
+        if mask & (1 << cpu) != 0 {
+            pending.fetch_or(1 << kind, Ordering::Release);
+            send_ipi_to(cpu);
+        }
+    }
+}
+
+/// Acknowledge the IPI on the current CPU and take the bitmap of the pending kinds.
+///
+/// It's called by the trap handler when the IPI is received. The calls queued by
+/// `multicore::call_on` are run here, the bitmap is 0 if there are only the calls.
+pub fn take_ipi() -> usize {
+    ack_ipi();
+    // The CPU can't be the target of the IPIs, it's raised by someone else.
+    let Some(pending) = IPI_PENDING.get(hart_id()) else {
+        return 0;
+    };
+    let pending = pending.swap(0, Ordering::Acquire);
+    if pending & (1 << CALL_IPI_KIND) != 0 {
+        handle_calls();
+    }
+    pending & !(1 << CALL_IPI_KIND)
+}
+
+ph_ctor!(IRQ_INIT_STATS, CtorType::Cpu, stats::init);
@@ -0,0 +1,105 @@
+//! Message signaled interrupts.
+//!
+//! The vectors are allocated from the MSI controller of each architecture:
+//! the IDT vectors of the local APIC on x86_64, the GICv2m frame or the
+//! LPIs of the GICv3 ITS on aarch64, the identities of the IMSIC on riscv64
+//! and the vectors of the PCH-MSI on loongarch64.
+//!
+//! The vectors are received as `TrapType::Irq`, the irq number of the
+//! [IRQVector](super::IRQVector) is in [MsiDescriptor::vectors].
+
+use core::ops::Range;
+
+use super::IRQ;
+use crate::multicore::call_on;
+use crate::utils::MutexNoIrq;
+use crate::PhysAddr;
+
+/// The MSI vectors allocated by [alloc_msi].
+///
+/// The vector `vectors.start + i` is raised by writing `data + i` to `address`
+/// in 32 bits, it fits both the multiple message MSI and the MSI-X table.
+#[derive(Debug, Clone)]
+pub struct MsiDescriptor {
+    pub address: PhysAddr,
+    pub data: u32,
+    /// The irq numbers of the vectors.
+    pub vectors: Range<usize>,
+    /// The CPU which receives the vectors.
+    pub affinity: usize,
+}
+
+/// Allocate `count` MSI vectors delivered to the CPU `affinity`.
+///
+/// The vectors are contiguous and aligned to the power of two of `count`,
+/// as required by the multiple message MSI. They are enabled on the CPU
+/// `affinity` before returning, mask them in the device if needed.
+/// Return `None` if the MSIs aren't supported, the vectors are exhausted or
+/// the CPU `affinity` isn't initialized.
+///
+/// On aarch64 with the GICv3 ITS, the vectors must be bound to the device
+/// by `bind_msi_device` before they are raised.
+pub fn alloc_msi(count: usize, affinity: usize) -> Option<MsiDescriptor> {
+    if count == 0 {
+        return None;
+    }
+    let desc = super::alloc_msi_vectors(count, affinity)?;
+    let vectors = desc.vectors.clone();
+    if !call_on(affinity, &|| vectors.clone().for_each(IRQ::irq_enable)) {
+        super::free_msi_vectors(&desc);
+        return None;
+    }
+    Some(desc)
+}
+
+/// Disable and free the MSI vectors allocated by [alloc_msi].
+pub fn free_msi(desc: MsiDescriptor) {
+    let vectors = desc.vectors.clone();
+    call_on(desc.affinity, &|| {
+        vectors.clone().for_each(IRQ::irq_disable)
+    });
+    super::free_msi_vectors(&desc);
+}
+
+/// A bitmap allocator of the vectors, `WORDS * 64` vectors at most.
+pub(crate) struct VectorAllocator<const WORDS: usize> {
+    allocated: MutexNoIrq<[u64; WORDS]>,
+}
+
+impl<const WORDS: usize> VectorAllocator<WORDS> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            allocated: MutexNoIrq::new([0; WORDS]),
+        }
+    }
+
+    /// Allocate `count` contiguous vectors in `range`, aligned to the power of two of `count`.
+    pub(crate) fn alloc(&self, range: Range<usize>, count: usize) -> Option<Range<usize>> {
+        let align = count.next_power_of_two();
+        let end = range.end.min(WORDS * 64);
+        let mut allocated = self.allocated.lock();
+        let is_free = |vector: usize| allocated[vector / 64] & (1 << (vector % 64)) == 0;
+        let start = (range.start.next_multiple_of(align)..end)
+            .step_by(align)
+            .take_while(|start| start + count <= end)
+            .find(|start| (*start..start + count).all(is_free))?;
+        for vector in start..start + count {
+            allocated[vector / 64] |= 1 << (vector % 64);
+        }
+        Some(start..start + count)
+    }
+
+    /// Check if the vector is allocated.
+    #[allow(dead_code)]
+    pub(crate) fn is_allocated(&self, vector: usize) -> bool {
+        vector < WORDS * 64 && self.allocated.lock()[vector / 64] & (1 << (vector % 64)) != 0
+    }
+
+    /// Free the vectors.
+    pub(crate) fn free(&self, vectors: Range<usize>) {
+        let mut allocated = self.allocated.lock();
+        for vector in vectors.filter(|vector| *vector < WORDS * 64) {
+            allocated[vector / 64] &= !(1 << (vector % 64));
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Translate the interrupts of the device tree nodes.
+//!
+//! The interrupt specifier of a device is found by its `interrupts-extended`,
+//! or by its `interrupts` and the `interrupt-parent` of the node or its
+//! ancestors. The cells are translated by the driver of the interrupt
+//! controller to the irq number of the [IRQ](super::IRQ) interface.
+
+use arrayvec::ArrayVec;
+use fdt_parser::Node;
+
+use super::{IRQVector, IrqConfig, IrqPolarity, IrqTrigger};
+use crate::mem::get_fdt;
+
+/// The maximum number of the cells of an interrupt specifier.
+const MAX_INTERRUPT_CELLS: usize = 4;
+
+/// The cells of an interrupt specifier.
+pub(crate) type IrqCells = ArrayVec<u32, MAX_INTERRUPT_CELLS>;
+
+/// The `#interrupt-cells` of the interrupt controller.
+fn interrupt_cells(controller: &Node) -> Option<usize> {
+    let cells = controller.find_property("#interrupt-cells")?.u32() as usize;
+    (1..=MAX_INTERRUPT_CELLS).contains(&cells).then_some(cells)
+}
+
+/// Read the big-endian cells.
+fn read_cells(raw: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    raw.chunks_exact(4)
+        .map(|cell| u32::from_be_bytes(cell.try_into().unwrap()))
+}
+
+/// Find the `index`th interrupt of the node, translate it by the driver of its controller.
+///
+/// Return the irq number and the flags of the trigger, 0 if the controller has no flags.
+fn of_irq_parse(node: &Node, index: usize) -> Option<(usize, u32)> {
+    if let Some(extended) = node.find_property("interrupts-extended") {
+        // The phandle of the controller precedes each specifier.
+        let fdt = get_fdt().ok()?;
+        let mut cells = read_cells(extended.raw_value());
+        for current in 0..=index {
+            let controller = fdt.get_node_by_phandle(cells.next()?.into())?;
+            let count = interrupt_cells(&controller)?;
+            let spec: IrqCells = cells.by_ref().take(count).collect();
+            if spec.len() != count {
+                return None;
+            }
+            if current == index {
+                return super::translate_of_irq(&controller, &spec);
+            }
+        }
+        return None;
+    }
+    let controller = node.interrupt_parent()?.node;
+    let count = interrupt_cells(&controller)?;
+    let raw = node.find_property("interrupts")?.raw_value();
+    let spec = raw.chunks_exact(count * 4).nth(index)?;
+    super::translate_of_irq(&controller, &read_cells(spec).collect())
+}
+
+/// Decode the common flags of the trigger, the `IRQ_TYPE_*` of the device tree bindings.
+fn irq_type(flags: u32) -> Option<(IrqTrigger, IrqPolarity)> {
+    match flags & 0xf {
+        1 => Some((IrqTrigger::Edge, IrqPolarity::High)),
+        2 => Some((IrqTrigger::Edge, IrqPolarity::Low)),
+        4 => Some((IrqTrigger::Level, IrqPolarity::High)),
+        8 => Some((IrqTrigger::Level, IrqPolarity::Low)),
+        _ => None,
+    }
+}
+
+/// Get the `index`th interrupt of the device tree node.
+///
+/// Return `None` if the node has no such interrupt, or its controller isn't
+/// the one driven by polyhal.
+pub fn of_irq_get(node: &Node, index: usize) -> Option<IRQVector> {
+    of_irq_parse(node, index).map(|(irq, _)| IRQVector(irq))
+}
+
+/// Get the `index`th interrupt of the device tree node and its configuration.
+///
+/// The trigger and the polarity are taken from the specifier, the others are
+/// the defaults of [IrqConfig::new]. Pass it to `IRQ::configure` to set up the irq.
+pub fn of_irq_config(
+    node: &Node,
+    index: usize,
+    target_cpus: usize,
+) -> Option<(IRQVector, IrqConfig)> {
+    let (irq, flags) = of_irq_parse(node, index)?;
+    let mut config = IrqConfig::new(target_cpus);
+    if let Some((trigger, polarity)) = irq_type(flags) {
+        config.trigger = trigger;
+        config.polarity = polarity;
+    }
+    Some((IRQVector(irq), config))
+}
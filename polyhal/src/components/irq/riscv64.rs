@@ -1,19 +1,172 @@
+mod aia;
+mod plic;
+
+use arrayvec::ArrayVec;
+use fdt_parser::{Fdt, Node};
+use lazyinit::LazyInit;
+use riscv::register::{sie, sip};
 use riscv::register::sstatus::{self, clear_sie, set_sie};
 
-use crate::components::irq::{IRQVector, IRQ};
+use crate::arch::hart_id;
+use crate::components::irq::of::IrqCells;
+use crate::components::irq::{IRQVector, IrqConfig, IrqPolarity, IrqTrigger, MsiDescriptor, IRQ};
+use crate::components::multicore::call_on;
+use crate::ctor::CtorType;
+use crate::mem::get_fdt;
+use aia::Aia;
+use plic::Plic;
+
+/// The maximum number of the harts served by the interrupt controller.
+const MAX_HARTS: usize = 64;
+
+/// The supervisor external interrupt of the `riscv,cpu-intc`.
+const IRQ_S_EXT: u32 = 9;
+
+/// The external interrupt controller, selected from the device tree.
+#[allow(clippy::large_enum_variant)]
+enum Controller {
+    Plic(Plic),
+    Aia(Aia),
+}
+
+/// The interrupt controller, `None` if there isn't one.
+static CONTROLLER: LazyInit<Option<Controller>> = LazyInit::new();
+
+#[inline]
+fn controller() -> Option<&'static Controller> {
+    CONTROLLER.get().and_then(Option::as_ref)
+}
+
+/// Get the index of the S-mode external interrupt of each hart in the
+/// `interrupts-extended` of the interrupt controller.
+///
+/// The `riscv,cpu-intc` of a hart is the child of its cpu node.
+fn hart_contexts(fdt: &Fdt, node: &Node) -> [Option<usize>; MAX_HARTS] {
+    let mut intcs: ArrayVec<(u32, usize), MAX_HARTS> = ArrayVec::new();
+    let mut hart = None;
+    for node in fdt.all_nodes() {
+        if node.name().starts_with("cpu@") {
+            hart = node.reg().and_then(|mut reg| reg.next()).map(|reg| reg.address);
+        } else if node.compatibles().any(|c| c == "riscv,cpu-intc") {
+            if let (Some(hart), Some(phandle)) = (hart, node.phandle()) {
+                intcs.try_push((phandle.as_usize() as u32, hart as usize)).ok();
+            }
+        }
+    }
+
+    let mut contexts = [None; MAX_HARTS];
+    let Some(extended) = node.find_property("interrupts-extended") else {
+        return contexts;
+    };
+    for (context, entry) in extended.raw_value().chunks_exact(8).enumerate() {
+        let phandle = u32::from_be_bytes(entry[..4].try_into().unwrap());
+        let irq = u32::from_be_bytes(entry[4..].try_into().unwrap());
+        if irq != IRQ_S_EXT {
+            continue;
+        }
+        let hart = intcs.iter().find(|(p, _)| *p == phandle).map(|(_, h)| *h);
+        if let Some(slot) = hart.and_then(|hart| contexts.get_mut(hart)) {
+            *slot = Some(context);
+        }
+    }
+    contexts
+}
+
+/// Probe the interrupt controller at the first call, set up the current hart
+/// and enable its software and external interrupts.
+///
+/// The AIA is preferred if the device tree has both.
+pub(crate) fn init() {
+    CONTROLLER.call_once(|| {
+        let fdt = get_fdt().ok()?;
+        let controller = Aia::probe(&fdt)
+            .map(Controller::Aia)
+            .or_else(|| Plic::probe(&fdt).map(Controller::Plic));
+        if controller.is_none() {
+            log::warn!("no interrupt controller, external interrupts are unavailable");
+        }
+        controller
+    });
+    // The IPIs are delivered as the supervisor software interrupt by the SBI.
+    unsafe { sie::set_ssoft() };
+    match controller() {
+        Some(Controller::Plic(plic)) => plic.init_hart(hart_id()),
+        Some(Controller::Aia(aia)) => aia.init_hart(hart_id()),
+        None => return,
+    }
+    // The irqs of the controller are delivered as the supervisor external interrupt.
+    unsafe { sie::set_sext() };
+}
 
 /// Implement IRQ operations for the IRQ interface.
 impl IRQ {
     /// Enable irq for the given IRQ number.
+    ///
+    /// The irq is routed to the current hart.
     #[inline]
-    pub fn irq_enable(_irq_num: usize) {
-        log::warn!("irq not implemented in riscv platform yet");
+    pub fn irq_enable(irq_num: usize) {
+        match controller() {
+            Some(Controller::Plic(plic)) => {
+                if plic.priority(irq_num) == 0 {
+                    plic.set_priority(irq_num, 1);
+                }
+                plic.set_enable(hart_id(), irq_num, true);
+            }
+            Some(Controller::Aia(aia)) => aia.set_enable(hart_id(), irq_num, true),
+            None => log::warn!("no interrupt controller to enable irq {}", irq_num),
+        }
     }
 
     /// Disable irq for the given IRQ number.
     #[inline]
-    pub fn irq_disable(_irq_num: usize) {
-        log::warn!("irq not implemented in riscv platform yet");
+    pub fn irq_disable(irq_num: usize) {
+        match controller() {
+            Some(Controller::Plic(plic)) => plic.set_enable(hart_id(), irq_num, false),
+            Some(Controller::Aia(aia)) => aia.set_enable(hart_id(), irq_num, false),
+            None => {}
+        }
+    }
+
+    /// Check if the irq is enabled on the current hart.
+    pub fn irq_enabled(irq_num: usize) -> bool {
+        match controller() {
+            Some(Controller::Plic(plic)) => plic.is_enabled(hart_id(), irq_num),
+            Some(Controller::Aia(aia)) => aia.is_enabled(irq_num),
+            None => false,
+        }
+    }
+
+    /// Check if the irq is pending, the MSIs of the current hart.
+    pub fn is_pending(irq_num: usize) -> bool {
+        match controller() {
+            Some(Controller::Plic(plic)) => plic.is_pending(irq_num),
+            Some(Controller::Aia(aia)) => aia.is_pending(irq_num),
+            None => false,
+        }
+    }
+
+    /// Configure the irq and enable it on the target harts.
+    ///
+    /// The PLIC enables it on the contexts of all the targets, the trigger and the
+    /// polarity aren't configurable. The AIA delivers it to the lowest target, the
+    /// priority is the order of the identities.
+    pub fn configure(irq_num: usize, config: IrqConfig) {
+        match controller() {
+            Some(Controller::Plic(plic)) => {
+                plic.configure(irq_num, config.priority, config.targets())
+            }
+            Some(Controller::Aia(aia)) => {
+                aia.set_source_mode(
+                    irq_num,
+                    config.trigger == IrqTrigger::Edge,
+                    config.polarity == IrqPolarity::Low,
+                );
+                // The identity is enabled in the interrupt file of the target hart.
+                let hart = config.first_target();
+                call_on(hart, &|| aia.set_enable(hart, irq_num, true));
+            }
+            None => log::warn!("no interrupt controller to configure irq {}", irq_num),
+        }
     }
 
     /// Enable interrupts.
@@ -39,13 +192,89 @@ impl IRQ {
 impl IRQVector {
     /// Get the irq number in this vector
     #[inline]
-    pub fn irq_num(&self) -> usize {
-        log::warn!("ack not implemented in riscv platform yet");
+    pub const fn irq_num(&self) -> usize {
         self.0
     }
 
+    /// Check if the irq is the IPI sent through the IMSIC.
+    #[inline]
+    pub fn is_ipi(&self) -> bool {
+        matches!(controller(), Some(Controller::Aia(aia)) if aia.is_ipi(self.0))
+    }
+
     /// Acknowledge the irq
     pub fn ack(&self) {
-        log::warn!("ack not implemented in riscv platform yet");
+        match controller() {
+            Some(Controller::Plic(plic)) => plic.complete(hart_id(), self.0),
+            Some(Controller::Aia(aia)) => aia.complete(self.0),
+            None => {}
+        }
+    }
+}
+
+/// Send the IPI to the hart, through the IMSIC if it's available or the SBI.
+pub(super) fn send_ipi_to(hart: usize) {
+    if let Some(Controller::Aia(aia)) = controller() {
+        if aia.send_ipi(hart) {
+            return;
+        }
+    }
+    sbi_rt::send_ipi(1, hart);
+}
+
+/// Clear the software interrupt raised by the SBI.
+#[inline]
+pub(super) fn ack_ipi() {
+    unsafe { sip::clear_ssoft() };
+}
+
+/// Claim the pending external irq of the current hart.
+///
+/// The irq number is 0 if there is no pending irq.
+#[inline]
+pub fn get_irq() -> IRQVector {
+    IRQVector(match controller() {
+        Some(Controller::Plic(plic)) => plic.claim(hart_id()),
+        Some(Controller::Aia(aia)) => aia.claim(),
+        None => 0,
+    })
+}
+
+/// Allocate the identities of the IMSIC, written to the interrupt file of the `affinity` hart.
+pub(super) fn alloc_msi_vectors(count: usize, affinity: usize) -> Option<MsiDescriptor> {
+    let Some(Controller::Aia(aia)) = controller() else {
+        return None;
+    };
+    let address = aia.msi_address(affinity)?;
+    let vectors = aia.alloc_msi(count)?;
+    Some(MsiDescriptor {
+        address,
+        data: vectors.start as u32,
+        vectors,
+        affinity,
+    })
+}
+
+/// Free the identities allocated by [alloc_msi_vectors].
+pub(super) fn free_msi_vectors(desc: &MsiDescriptor) {
+    if let Some(Controller::Aia(aia)) = controller() {
+        aia.free_msi(desc.vectors.clone());
     }
 }
+
+/// Translate the interrupt specifier of the PLIC, `<source>`, or the APLIC, `<source flags>`.
+///
+/// The local interrupts of the `riscv,cpu-intc` aren't the irqs of the controller.
+pub(super) fn translate_of_irq(controller: &Node, cells: &IrqCells) -> Option<(usize, u32)> {
+    let external = controller
+        .compatibles()
+        .any(|c| c == "riscv,aplic" || Plic::COMPATIBLE.contains(&c));
+    match cells.as_slice() {
+        [source, rest @ ..] if external && *source != 0 => {
+            Some((*source as usize, rest.first().copied().unwrap_or(0)))
+        }
+        _ => None,
+    }
+}
+
+ph_ctor!(RISCV64_INIT_IRQ, CtorType::Cpu, init);
@@ -0,0 +1,390 @@
+//! Advanced Interrupt Architecture.
+//!
+//! The APLIC of the S-mode domain converts the wired interrupts to MSIs,
+//! which are delivered to the S-mode interrupt file of the IMSIC of a hart.
+//! The MSIs of the PCI devices are written to the interrupt files directly.
+//!
+//! The wired source `n` is delivered as the interrupt identity `n`, the
+//! identities above the wired sources are allocated to the MSIs.
+//!
+//! <https://github.com/riscv/riscv-aia>
+
+use core::arch::asm;
+use core::ops::Range;
+
+use fdt_parser::Fdt;
+use riscv::register::sstatus::{self, clear_sie, set_sie};
+
+use super::{hart_contexts, MAX_HARTS};
+use crate::components::irq::msi::VectorAllocator;
+use crate::PhysAddr;
+
+const APLIC_DOMAINCFG: usize = 0x0000;
+const APLIC_SOURCECFG: usize = 0x0004;
+const APLIC_SETIP: usize = 0x1c00;
+const APLIC_SETIPNUM: usize = 0x1cdc;
+const APLIC_SETIE: usize = 0x1e00;
+const APLIC_SETIENUM: usize = 0x1edc;
+const APLIC_CLRIENUM: usize = 0x1fdc;
+const APLIC_TARGET: usize = 0x3004;
+
+/// Enable the domain and deliver the interrupts as MSIs.
+const DOMAINCFG_IE: u32 = 1 << 8;
+const DOMAINCFG_DM: u32 = 1 << 2;
+
+const SOURCECFG_SM_INACTIVE: u32 = 0;
+const SOURCECFG_SM_EDGE_RISE: u32 = 4;
+const SOURCECFG_SM_EDGE_FALL: u32 = 5;
+const SOURCECFG_SM_LEVEL_HIGH: u32 = 6;
+const SOURCECFG_SM_LEVEL_LOW: u32 = 7;
+
+/// The AIA CSRs, which are unknown to the assembler without the Ssaia extension.
+const CSR_SISELECT: usize = 0x150;
+const CSR_SIREG: usize = 0x151;
+const CSR_STOPEI: usize = 0x15c;
+
+/// The indirect registers of the S-mode interrupt file.
+const IMSIC_EIDELIVERY: usize = 0x70;
+const IMSIC_EITHRESHOLD: usize = 0x72;
+const IMSIC_EIP0: usize = 0x80;
+const IMSIC_EIE0: usize = 0xc0;
+
+/// The maximum number of the interrupt identities.
+const MAX_IDS: usize = 2048;
+
+/// The size of an interrupt file.
+const IMSIC_FILE_SHIFT: usize = 12;
+
+/// Run `f` with the interrupts disabled, `siselect` can't be changed by a trap handler.
+#[inline]
+fn without_irq<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = sstatus::read().sie();
+    unsafe { clear_sie() };
+    let ret = f();
+    if enabled {
+        unsafe { set_sie() };
+    }
+    ret
+}
+
+/// Write the indirect register of the interrupt file of the current hart.
+#[inline]
+fn imsic_write(reg: usize, value: usize) {
+    without_irq(|| unsafe { asm!(
+            "csrw {sel}, {reg}",
+            "csrw {ireg}, {value}",
+            sel = const CSR_SISELECT,
+            ireg = const CSR_SIREG,
+            reg = in(reg) reg,
+            value = in(reg) value,
+        ) })
+}
+
+/// Read the indirect register of the interrupt file of the current hart.
+#[inline]
+fn imsic_read(reg: usize) -> usize {
+    without_irq(|| {
+        let value: usize;
+        unsafe {
+            asm!(
+                "csrw {sel}, {reg}",
+                "csrr {value}, {ireg}",
+                sel = const CSR_SISELECT,
+                ireg = const CSR_SIREG,
+                reg = in(reg) reg,
+                value = out(reg) value,
+            )
+        };
+        value
+    })
+}
+
+/// Check the bit of the identity in the `eip` or `eie` registers of the current hart.
+#[inline]
+fn imsic_test(base: usize, id: usize) -> bool {
+    imsic_read(base + (id / 64) * 2) & (1 << (id % 64)) != 0
+}
+
+/// Set or clear the bit of the identity in the `eie` registers of the current hart.
+///
+/// The odd registers don't exist on RV64, `eie0` holds the identities 0-63.
+#[inline]
+fn imsic_set_enable(id: usize, enable: bool) {
+    let reg = IMSIC_EIE0 + (id / 64) * 2;
+    let mask = 1usize << (id % 64);
+    without_irq(|| unsafe {
+        match enable {
+            true => asm!(
+                "csrw {sel}, {reg}",
+                "csrs {ireg}, {mask}",
+                sel = const CSR_SISELECT,
+                ireg = const CSR_SIREG,
+                reg = in(reg) reg,
+                mask = in(reg) mask,
+            ),
+            false => asm!(
+                "csrw {sel}, {reg}",
+                "csrc {ireg}, {mask}",
+                sel = const CSR_SISELECT,
+                ireg = const CSR_SIREG,
+                reg = in(reg) reg,
+                mask = in(reg) mask,
+            ),
+        }
+    })
+}
+
+pub(super) struct Aia {
+    /// The S-mode domain of the APLIC, `None` if only the MSIs are used.
+    aplic: Option<usize>,
+    /// The number of the wired sources, `riscv,num-sources`.
+    sources: usize,
+    /// The number of the interrupt identities, `riscv,num-ids`.
+    ids: usize,
+    /// The identity of the IPIs, 0 if there isn't a free one.
+    ipi: usize,
+    /// The index of the interrupt file of each hart.
+    files: [Option<usize>; MAX_HARTS],
+    /// The physical address of the interrupt file of each hart.
+    addrs: [Option<PhysAddr>; MAX_HARTS],
+    /// The allocated interrupt identities of the MSIs.
+    allocated: VectorAllocator<{ MAX_IDS / 64 }>,
+}
+
+impl Aia {
+    /// Probe the S-mode IMSIC and the APLIC domain sending MSIs to it.
+    pub(super) fn probe(fdt: &Fdt) -> Option<Self> {
+        let (imsic, files) = fdt
+            .find_compatible(&["riscv,imsics"])
+            .map(|node| {
+                let files = hart_contexts(fdt, &node);
+                (node, files)
+            })
+            .find(|(_, files)| files.iter().any(Option::is_some))?;
+        let ids = imsic
+            .find_property("riscv,num-ids")
+            .map_or(63, |ids| ids.u32() as usize)
+            .min(MAX_IDS - 1);
+        let guest_bits = imsic
+            .find_property("riscv,guest-index-bits")
+            .map_or(0, |bits| bits.u32() as usize);
+        let stride = 1 << (IMSIC_FILE_SHIFT + guest_bits);
+
+        // The interrupt files are laid out in the regions in order.
+        let mut addrs = [None; MAX_HARTS];
+        for (addr, file) in addrs.iter_mut().zip(files.iter()) {
+            let Some(mut index) = *file else {
+                continue;
+            };
+            for reg in imsic.reg()? {
+                let count = reg.size.unwrap_or(stride) / stride;
+                if index < count {
+                    *addr = Some(PhysAddr::new(reg.address as usize + index * stride));
+                    break;
+                }
+                index -= count;
+            }
+        }
+
+        let phandle = imsic.phandle();
+        let aplic = fdt.find_compatible(&["riscv,aplic"]).find(|node| {
+            node.find_property("msi-parent")
+                .is_some_and(|parent| Some(parent.u32() as usize) == phandle.map(|p| p.as_usize()))
+        });
+        let sources = aplic
+            .as_ref()
+            .and_then(|node| node.find_property("riscv,num-sources"))
+            .map_or(0, |sources| sources.u32() as usize)
+            .min(ids);
+        let aplic = aplic
+            .and_then(|node| node.reg()?.next())
+            .map(|reg| PhysAddr::new(reg.address as usize));
+
+        let ipi = match sources < ids {
+            true => sources + 1,
+            false => 0,
+        };
+        log::info!(
+            "AIA: APLIC @ {:#x?}, {} sources, IMSIC {} ids",
+            aplic.map(|addr| addr.raw()),
+            sources,
+            ids
+        );
+        let aia = Self {
+            aplic: aplic.map(|addr| addr.get_mut_ptr::<u8>() as usize),
+            sources,
+            ids,
+            ipi,
+            files,
+            addrs,
+            allocated: VectorAllocator::new(),
+        };
+        aia.aplic_write(APLIC_DOMAINCFG, DOMAINCFG_IE | DOMAINCFG_DM);
+        Some(aia)
+    }
+
+    #[inline]
+    fn aplic_read(&self, offset: usize) -> u32 {
+        self.aplic
+            .map_or(0, |base| unsafe { ((base + offset) as *const u32).read_volatile() })
+    }
+
+    #[inline]
+    fn aplic_write(&self, offset: usize, value: u32) {
+        if let Some(base) = self.aplic {
+            unsafe { ((base + offset) as *mut u32).write_volatile(value) }
+        }
+    }
+
+    /// Check if the identity is a wired source of the APLIC.
+    #[inline]
+    fn is_wired(&self, irq: usize) -> bool {
+        irq != 0 && irq <= self.sources
+    }
+
+    #[inline]
+    fn valid(&self, irq: usize) -> bool {
+        irq != 0 && irq <= self.ids
+    }
+
+    /// Enable the interrupt delivery of the interrupt file of the current hart.
+    pub(super) fn init_hart(&self, hart: usize) {
+        if self.files.get(hart).copied().flatten().is_none() {
+            log::warn!("IMSIC has no S-mode interrupt file for hart {}", hart);
+            return;
+        }
+        imsic_write(IMSIC_EITHRESHOLD, 0);
+        imsic_write(IMSIC_EIDELIVERY, 1);
+        if self.ipi != 0 {
+            imsic_set_enable(self.ipi, true);
+        }
+    }
+
+    /// Enable or disable the identity on the current hart.
+    ///
+    /// A wired source is routed to the interrupt file of `hart`, which must be
+    /// the current hart, and is level-triggered unless it was configured.
+    pub(super) fn set_enable(&self, hart: usize, irq: usize, enable: bool) {
+        if !self.valid(irq) {
+            return;
+        }
+        if self.is_wired(irq) {
+            let Some(file) = self.files.get(hart).copied().flatten() else {
+                return;
+            };
+            let sourcecfg = APLIC_SOURCECFG + (irq - 1) * 4;
+            match enable {
+                true => {
+                    if self.aplic_read(sourcecfg) == SOURCECFG_SM_INACTIVE {
+                        self.aplic_write(sourcecfg, SOURCECFG_SM_LEVEL_HIGH);
+                    }
+                    self.aplic_write(APLIC_TARGET + (irq - 1) * 4, ((file << 18) | irq) as u32);
+                    self.aplic_write(APLIC_SETIENUM, irq as u32);
+                }
+                false => self.aplic_write(APLIC_CLRIENUM, irq as u32),
+            }
+        }
+        imsic_set_enable(irq, enable);
+    }
+
+    /// Set the source mode of a wired source, the identities of the MSIs are edge-triggered.
+    pub(super) fn set_source_mode(&self, irq: usize, edge: bool, low: bool) {
+        if !self.is_wired(irq) {
+            return;
+        }
+        let sm = match (edge, low) {
+            (true, false) => SOURCECFG_SM_EDGE_RISE,
+            (true, true) => SOURCECFG_SM_EDGE_FALL,
+            (false, false) => SOURCECFG_SM_LEVEL_HIGH,
+            (false, true) => SOURCECFG_SM_LEVEL_LOW,
+        };
+        self.aplic_write(APLIC_SOURCECFG + (irq - 1) * 4, sm);
+    }
+
+    /// Check if the identity is enabled on the current hart, and in the APLIC if it's wired.
+    pub(super) fn is_enabled(&self, irq: usize) -> bool {
+        if !self.valid(irq) {
+            return false;
+        }
+        let wired = !self.is_wired(irq)
+            || self.aplic_read(APLIC_SETIE + (irq / 32) * 4) & (1 << (irq % 32)) != 0;
+        wired && imsic_test(IMSIC_EIE0, irq)
+    }
+
+    /// Check if the identity is pending in the APLIC or on the current hart.
+    pub(super) fn is_pending(&self, irq: usize) -> bool {
+        if !self.valid(irq) {
+            return false;
+        }
+        (self.is_wired(irq) && self.aplic_read(APLIC_SETIP + (irq / 32) * 4) & (1 << (irq % 32)) != 0)
+            || imsic_test(IMSIC_EIP0, irq)
+    }
+
+    /// Claim the highest priority pending identity of the current hart, 0 if there is none.
+    #[inline]
+    pub(super) fn claim(&self) -> usize {
+        let topei: usize;
+        unsafe { asm!("csrrw {}, {}, zero", out(reg) topei, const CSR_STOPEI) };
+        topei >> 16
+    }
+
+    /// Complete the claimed identity.
+    ///
+    /// The pending bit of a level-triggered source was cleared when the MSI was sent,
+    /// it is set again if the source is still asserted.
+    #[inline]
+    pub(super) fn complete(&self, irq: usize) {
+        if self.is_wired(irq) {
+            let sm = self.aplic_read(APLIC_SOURCECFG + (irq - 1) * 4);
+            if matches!(sm, SOURCECFG_SM_LEVEL_HIGH | SOURCECFG_SM_LEVEL_LOW) {
+                self.aplic_write(APLIC_SETIPNUM, irq as u32);
+            }
+        }
+    }
+
+    /// Check if the identity is the IPI.
+    #[inline]
+    pub(super) fn is_ipi(&self, irq: usize) -> bool {
+        irq != 0 && irq == self.ipi
+    }
+
+    /// Send the IPI to the interrupt file of the hart.
+    ///
+    /// Return `false` if the IPI can't be sent through the IMSIC.
+    pub(super) fn send_ipi(&self, hart: usize) -> bool {
+        match (self.ipi, self.msi_address(hart)) {
+            (0, _) | (_, None) => false,
+            (ipi, Some(addr)) => {
+                // Write the identity to `seteipnum_le`.
+                addr.write_volatile(ipi as u32);
+                true
+            }
+        }
+    }
+
+    /// The identities which can be allocated to the MSIs.
+    ///
+    /// The identity 0, the identities of the wired sources and the IPI are never allocated.
+    #[inline]
+    fn msi_ids(&self) -> Range<usize> {
+        self.sources.max(self.ipi) + 1..self.ids + 1
+    }
+
+    /// Allocate `count` contiguous identities for the MSIs.
+    pub(super) fn alloc_msi(&self, count: usize) -> Option<Range<usize>> {
+        self.allocated.alloc(self.msi_ids(), count)
+    }
+
+    /// Free the identities of the MSIs.
+    pub(super) fn free_msi(&self, ids: Range<usize>) {
+        let msi_ids = self.msi_ids();
+        self.allocated
+            .free(ids.start.max(msi_ids.start)..ids.end.min(msi_ids.end));
+    }
+
+    /// The address of the interrupt file of the hart, the target of the MSI writes.
+    #[inline]
+    pub(super) fn msi_address(&self, hart: usize) -> Option<PhysAddr> {
+        self.addrs.get(hart).copied().flatten()
+    }
+}
@@ -0,0 +1,178 @@
+//! Platform-Level Interrupt Controller.
+//!
+//! <https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc>
+
+use fdt_parser::Fdt;
+
+use super::{hart_contexts, MAX_HARTS};
+
+use crate::utils::MutexNoIrq;
+use crate::PhysAddr;
+
+const PRIORITY_OFFSET: usize = 0;
+const PENDING_OFFSET: usize = 0x1000;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CONTEXT_THRESHOLD: usize = 0;
+const CONTEXT_CLAIM: usize = 4;
+
+pub(super) struct Plic {
+    base: usize,
+    /// The number of the interrupt sources, `riscv,ndev`.
+    ndev: usize,
+    /// The highest priority implemented.
+    max_priority: u32,
+    /// The S-mode context of each hart.
+    contexts: [Option<usize>; MAX_HARTS],
+    /// Serialize the read-modify-write of the enable bits.
+    lock: MutexNoIrq<()>,
+}
+
+impl Plic {
+    pub(super) const COMPATIBLE: &[&str] = &["riscv,plic0", "sifive,plic-1.0.0"];
+
+    /// Probe the PLIC from the device tree.
+    ///
+    /// The S-mode context of each hart is the index of its entry in `interrupts-extended`.
+    pub(super) fn probe(fdt: &Fdt) -> Option<Self> {
+        let node = fdt.find_compatible(Self::COMPATIBLE).next()?;
+        let base = PhysAddr::new(node.reg()?.next()?.address as usize);
+        let ndev = node
+            .find_property("riscv,ndev")
+            .map_or(1023, |ndev| ndev.u32() as usize);
+
+        let contexts = hart_contexts(fdt, &node);
+        let mut plic = Self {
+            base: base.get_mut_ptr::<u8>() as usize,
+            ndev,
+            max_priority: 1,
+            contexts,
+            lock: MutexNoIrq::new(()),
+        };
+        // The priority registers are WARL, the unimplemented bits read as 0.
+        let priority = plic.priority(1);
+        plic.set_priority(1, u32::MAX);
+        plic.max_priority = plic.priority(1).max(1);
+        plic.set_priority(1, priority);
+        log::info!(
+            "PLIC @ {:#x}, {} sources, {} priorities",
+            base.raw(),
+            ndev,
+            plic.max_priority
+        );
+        Some(plic)
+    }
+
+    #[inline]
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    #[inline]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.reg(offset).read_volatile() }
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { self.reg(offset).write_volatile(value) }
+    }
+
+    #[inline]
+    fn context(&self, hart: usize) -> Option<usize> {
+        self.contexts.get(hart).copied().flatten()
+    }
+
+    #[inline]
+    fn valid(&self, irq: usize) -> bool {
+        irq != 0 && irq <= self.ndev
+    }
+
+    /// Accept all the priorities on the S-mode context of the hart.
+    pub(super) fn init_hart(&self, hart: usize) {
+        match self.context(hart) {
+            Some(context) => self.write(
+                CONTEXT_OFFSET + context * CONTEXT_STRIDE + CONTEXT_THRESHOLD,
+                0,
+            ),
+            None => log::warn!("PLIC has no S-mode context for hart {}", hart),
+        }
+    }
+
+    /// Set the priority of the source, 0 never interrupts.
+    pub(super) fn set_priority(&self, irq: usize, priority: u32) {
+        if self.valid(irq) {
+            self.write(PRIORITY_OFFSET + irq * 4, priority);
+        }
+    }
+
+    #[inline]
+    pub(super) fn priority(&self, irq: usize) -> u32 {
+        match self.valid(irq) {
+            true => self.read(PRIORITY_OFFSET + irq * 4),
+            false => 0,
+        }
+    }
+
+    /// Enable or disable the source on the S-mode context of the hart.
+    pub(super) fn set_enable(&self, hart: usize, irq: usize, enable: bool) {
+        let Some(context) = self.context(hart).filter(|_| self.valid(irq)) else {
+            return;
+        };
+        let offset = ENABLE_OFFSET + context * ENABLE_STRIDE + (irq / 32) * 4;
+        let _guard = self.lock.lock();
+        let value = self.read(offset);
+        match enable {
+            true => self.write(offset, value | (1 << (irq % 32))),
+            false => self.write(offset, value & !(1 << (irq % 32))),
+        }
+    }
+
+    /// Check if the source is enabled on the S-mode context of the hart.
+    pub(super) fn is_enabled(&self, hart: usize, irq: usize) -> bool {
+        self.context(hart).filter(|_| self.valid(irq)).is_some_and(|context| {
+            self.read(ENABLE_OFFSET + context * ENABLE_STRIDE + (irq / 32) * 4) & (1 << (irq % 32))
+                != 0
+        })
+    }
+
+    /// Set the priority of the source and enable it only on the S-mode contexts of the harts in `harts`.
+    ///
+    /// The priority is scaled to the implemented priorities, 0 is the lowest but still interrupts.
+    pub(super) fn configure(&self, irq: usize, priority: u8, harts: usize) {
+        if !self.valid(irq) {
+            return;
+        }
+        self.set_priority(irq, 1 + priority as u32 * (self.max_priority - 1) / 255);
+        for hart in 0..MAX_HARTS {
+            self.set_enable(hart, irq, harts & (1 << hart) != 0);
+        }
+    }
+
+    /// Claim the highest priority pending source of the hart, 0 if there is none.
+    #[inline]
+    pub(super) fn claim(&self, hart: usize) -> usize {
+        self.context(hart).map_or(0, |context| {
+            self.read(CONTEXT_OFFSET + context * CONTEXT_STRIDE + CONTEXT_CLAIM) as usize
+        })
+    }
+
+    /// Complete the source claimed by the hart.
+    #[inline]
+    pub(super) fn complete(&self, hart: usize, irq: usize) {
+        if let Some(context) = self.context(hart).filter(|_| self.valid(irq)) {
+            self.write(
+                CONTEXT_OFFSET + context * CONTEXT_STRIDE + CONTEXT_CLAIM,
+                irq as u32,
+            );
+        }
+    }
+
+    /// Check if the source is pending.
+    #[inline]
+    pub(super) fn is_pending(&self, irq: usize) -> bool {
+        self.valid(irq) && self.read(PENDING_OFFSET + (irq / 32) * 4) & (1 << (irq % 32)) != 0
+    }
+}
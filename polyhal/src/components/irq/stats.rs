@@ -0,0 +1,154 @@
+//! The statistics of the interrupts.
+//!
+//! Each CPU counts its interrupts in its per-CPU area, the counters are
+//! registered when the CPU is initialized so [irq_stats] can read the
+//! counters of all the CPUs. The trap handler counts the interrupts by
+//! [count_irq] when they are dispatched.
+
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use super::MAX_IPI_CPUS;
+use crate::arch::hart_id;
+
+/// The maximum number of the distinct irqs counted by a CPU, the others are dropped.
+const MAX_STAT_IRQS: usize = 128;
+
+/// The source of an interrupt in the statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqSource {
+    /// An external irq, the irq number of the `IRQVector`.
+    Irq(usize),
+    Timer,
+    Ipi,
+    /// An interrupt which was gone when it was claimed, like the GIC interrupt 1023
+    /// or the spurious vector of the local APIC.
+    Spurious,
+}
+
+/// The number of the interrupts of a source received by a CPU.
+#[derive(Debug, Clone, Copy)]
+pub struct IrqStat {
+    pub cpu: usize,
+    pub source: IrqSource,
+    pub count: usize,
+}
+
+struct IrqCounter {
+    /// The irq number plus 1, 0 if the counter is free.
+    irq: AtomicUsize,
+    count: AtomicUsize,
+}
+
+struct IrqCounters {
+    irqs: [IrqCounter; MAX_STAT_IRQS],
+    timer: AtomicUsize,
+    ipi: AtomicUsize,
+    spurious: AtomicUsize,
+}
+
+impl IrqCounters {
+    const fn new() -> Self {
+        Self {
+            irqs: [const {
+                IrqCounter {
+                    irq: AtomicUsize::new(0),
+                    count: AtomicUsize::new(0),
+                }
+            }; MAX_STAT_IRQS],
+            timer: AtomicUsize::new(0),
+            ipi: AtomicUsize::new(0),
+            spurious: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count the irq, only the owner CPU counts so the free counter isn't raced.
+    fn count_irq(&self, irq: usize) {
+        for counter in self.irqs.iter() {
+            match counter.irq.load(Ordering::Relaxed) {
+                0 => {
+                    counter.count.fetch_add(1, Ordering::Relaxed);
+                    counter.irq.store(irq + 1, Ordering::Release);
+                    return;
+                }
+                key if key == irq + 1 => {
+                    counter.count.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The counts of the sources which were received.
+    fn iter(&self) -> impl Iterator<Item = (IrqSource, usize)> + '_ {
+        let irqs =
+            self.irqs
+                .iter()
+                .map_while(|counter| match counter.irq.load(Ordering::Acquire) {
+                    0 => None,
+                    key => Some((
+                        IrqSource::Irq(key - 1),
+                        counter.count.load(Ordering::Relaxed),
+                    )),
+                });
+        [
+            (IrqSource::Timer, &self.timer),
+            (IrqSource::Ipi, &self.ipi),
+            (IrqSource::Spurious, &self.spurious),
+        ]
+        .into_iter()
+        .map(|(source, count)| (source, count.load(Ordering::Relaxed)))
+        .chain(irqs)
+        .filter(|(_, count)| *count != 0)
+    }
+}
+
+#[polyhal_macro::percpu]
+static IRQ_COUNTERS: IrqCounters = IrqCounters::new();
+
+/// The counters of each CPU, null if the CPU isn't initialized.
+static CPU_COUNTERS: [AtomicPtr<IrqCounters>; MAX_IPI_CPUS] =
+    [const { AtomicPtr::new(null_mut()) }; MAX_IPI_CPUS];
+
+/// Register the counters of the current CPU.
+pub(crate) fn init() {
+    if let Some(counters) = CPU_COUNTERS.get(hart_id()) {
+        counters.store(IRQ_COUNTERS.get_mut_ptr(), Ordering::Release);
+    }
+}
+
+/// Count the interrupt on the current CPU, it's called by the trap handler.
+pub fn count_irq(source: IrqSource) {
+    let counters = &*IRQ_COUNTERS;
+    match source {
+        IrqSource::Irq(irq) => counters.count_irq(irq),
+        IrqSource::Timer => {
+            counters.timer.fetch_add(1, Ordering::Relaxed);
+        }
+        IrqSource::Ipi => {
+            counters.ipi.fetch_add(1, Ordering::Relaxed);
+        }
+        IrqSource::Spurious => {
+            counters.spurious.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The nonzero counters of all the CPUs, like `/proc/interrupts`.
+///
+/// The irqs of a CPU are in the order they were first received.
+pub fn irq_stats() -> impl Iterator<Item = IrqStat> {
+    CPU_COUNTERS
+        .iter()
+        .enumerate()
+        .filter_map(|(cpu, counters)| {
+            let counters = unsafe { counters.load(Ordering::Acquire).as_ref()? };
+            Some((cpu, counters))
+        })
+        .flat_map(|(cpu, counters)| {
+            counters
+                .iter()
+                .map(move |(source, count)| IrqStat { cpu, source, count })
+        })
+}
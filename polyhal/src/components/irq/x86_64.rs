@@ -1,21 +1,100 @@
-use crate::arch::apic::{io_apic, local_apic};
-use crate::components::irq::{IRQVector, IRQ};
+use crate::arch::apic::vectors::{APIC_IPI_VECTOR, MSI_VECTOR_END, MSI_VECTOR_START};
+use crate::arch::apic::{
+    apic_id, io_apic, io_apic_inputs, isa_irq_input, local_apic, local_apic_pending, raw_apic_id,
+};
+use crate::components::irq::msi::VectorAllocator;
+use crate::components::irq::of::IrqCells;
+use crate::components::irq::{IRQVector, IrqConfig, IrqPolarity, IrqTrigger, MsiDescriptor, IRQ};
+use crate::PhysAddr;
+use fdt_parser::Node;
+use x2apic::ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry};
+
+/// The MSI address of the local APIC, the destination APIC id in bits 19:12.
+const MSI_ADDRESS: usize = 0xFEE0_0000;
+
+/// The allocated vectors of the MSIs.
+static MSI_ALLOCATED: VectorAllocator<4> = VectorAllocator::new();
+
+/// Access the I/O APIC input, `None` if the input isn't programmed at init.
+///
+/// Only the first 16 inputs are supported, the vectors above them are MSIs.
+fn with_io_apic_input<T>(input: usize, f: impl FnOnce(&mut IoApic, u8) -> T) -> Option<T> {
+    let mut io_apic = io_apic().lock();
+    let inputs = io_apic_inputs(&mut io_apic);
+    (input < inputs as usize).then(|| f(&mut io_apic, input as u8))
+}
+
+/// Read the redirection entry of the I/O APIC input, `None` if there isn't the input.
+fn io_apic_entry(input: usize) -> Option<RedirectionTableEntry> {
+    with_io_apic_input(input, |io_apic, input| unsafe { io_apic.table_entry(input) })
+}
 
 /// Implement IRQ operations for the IRQ interface.
 impl IRQ {
     /// Enable irq for the given IRQ number.
+    ///
+    /// The MSI vectors are always enabled, they are masked in the device.
     #[inline]
     pub fn irq_enable(irq_num: usize) {
-        unsafe {
-            io_apic().lock().enable_irq(irq_num as _);
-        }
+        with_io_apic_input(irq_num, |io_apic, input| unsafe { io_apic.enable_irq(input) });
     }
 
     /// Disable irq for the given IRQ number.
     #[inline]
     pub fn irq_disable(irq_num: usize) {
-        unsafe {
-            io_apic().lock().disable_irq(irq_num as _);
+        with_io_apic_input(irq_num, |io_apic, input| unsafe { io_apic.disable_irq(input) });
+    }
+
+    /// Check if the irq is enabled, a MSI vector is enabled if it's allocated.
+    pub fn irq_enabled(irq_num: usize) -> bool {
+        match irq_num < MSI_VECTOR_START as usize {
+            true => io_apic_entry(irq_num).is_some_and(|x| !x.flags().contains(IrqFlags::MASKED)),
+            false => MSI_ALLOCATED.is_allocated(irq_num),
+        }
+    }
+
+    /// Check if the irq is requested to the local APIC of the current CPU.
+    pub fn is_pending(irq_num: usize) -> bool {
+        let vector = match irq_num < MSI_VECTOR_START as usize {
+            true => match io_apic_entry(irq_num) {
+                Some(entry) => entry.vector(),
+                None => return false,
+            },
+            false => match u8::try_from(irq_num) {
+                Ok(vector) => vector,
+                Err(_) => return false,
+            },
+        };
+        local_apic_pending(vector)
+    }
+
+    /// Configure the redirection entry of the I/O APIC and enable the irq.
+    ///
+    /// The irq is delivered to the lowest target CPU, the priority is
+    /// implied by the vector. Only the first 16 inputs of the I/O APIC
+    /// can be configured, the MSI vectors can't be.
+    pub fn configure(irq_num: usize, config: IrqConfig) {
+        let mut flags = IrqFlags::empty();
+        if config.trigger == IrqTrigger::Level {
+            flags |= IrqFlags::LEVEL_TRIGGERED;
+        }
+        if config.polarity == IrqPolarity::Low {
+            flags |= IrqFlags::LOW_ACTIVE;
+        }
+        // The physical destination of the I/O APIC is an 8-bit APIC id.
+        let Ok(dest) = u8::try_from(apic_id(config.first_target())) else {
+            log::error!("the I/O APIC can't deliver irq {} to cpu {}", irq_num, config.first_target());
+            return;
+        };
+        let configured = with_io_apic_input(irq_num, |io_apic, input| unsafe {
+            let mut entry = io_apic.table_entry(input);
+            entry.set_mode(IrqMode::Fixed);
+            entry.set_flags(flags);
+            entry.set_dest(dest);
+            io_apic.set_table_entry(input, entry);
+        });
+        if configured.is_none() {
+            log::error!("irq {} isn't an I/O APIC input", irq_num);
         }
     }
 
@@ -51,3 +130,61 @@ impl IRQVector {
         unsafe { local_apic().end_of_interrupt() };
     }
 }
+
+/// Send the IPI to the CPU through the ICR of the local APIC.
+pub(super) fn send_ipi_to(cpu: usize) {
+    unsafe { local_apic().send_ipi(APIC_IPI_VECTOR, raw_apic_id(cpu as u8)) };
+}
+
+/// Signal the end of the IPI to the local APIC.
+#[inline]
+pub(super) fn ack_ipi() {
+    unsafe { local_apic().end_of_interrupt() };
+}
+
+/// Allocate the IDT vectors, delivered to the local APIC of the `affinity` CPU.
+///
+/// The irq number of a MSI vector is the IDT vector. The CPU must have an
+/// 8-bit APIC id, as the MSI address has no room for the others.
+pub(super) fn alloc_msi_vectors(count: usize, affinity: usize) -> Option<MsiDescriptor> {
+    let dest = u8::try_from(apic_id(affinity)).ok()?;
+    let vectors =
+        MSI_ALLOCATED.alloc(MSI_VECTOR_START as usize..MSI_VECTOR_END as usize + 1, count)?;
+    Some(MsiDescriptor {
+        address: PhysAddr::new(MSI_ADDRESS | ((dest as usize) << 12)),
+        data: vectors.start as u32,
+        vectors,
+        affinity,
+    })
+}
+
+/// Free the vectors allocated by [alloc_msi_vectors].
+pub(super) fn free_msi_vectors(desc: &MsiDescriptor) {
+    MSI_ALLOCATED.free(desc.vectors.clone());
+}
+
+/// Get the irq of the ISA irq, which may be moved to another input of the
+/// I/O APIC by the interrupt source overrides of the MADT.
+pub fn isa_irq_get(irq: u8) -> IRQVector {
+    IRQVector(isa_irq_input(irq))
+}
+
+/// Translate the interrupt specifier of the I/O APIC, `<input type>`.
+///
+/// The type is 0 for rising edge, 1 for active low, 2 for active high and 3 for falling edge.
+pub(super) fn translate_of_irq(controller: &Node, cells: &IrqCells) -> Option<(usize, u32)> {
+    if !controller.compatibles().any(|c| c == "intel,ce4100-ioapic") {
+        return None;
+    }
+    let [input, kind] = cells.as_slice() else {
+        return None;
+    };
+    let flags = match kind {
+        0 => 1,
+        1 => 8,
+        2 => 4,
+        3 => 2,
+        _ => 0,
+    };
+    (*input < MSI_VECTOR_START as u32).then_some((*input as usize, flags))
+}
@@ -1,5 +1,5 @@
 use crate::components::kcontext::KContextArgs;
-use crate::PageTable;
+use crate::{PageTable, VirtAddr};
 use core::arch::naked_asm;
 use core::ops::{Index, IndexMut};
 
@@ -39,6 +39,87 @@ macro_rules! restore_callee_regs {
     };
 }
 
+/// FP/SIMD registers `q0`-`q31`, `fpcr` and `fpsr`.
+#[repr(C, align(16))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FpState {
+    pub q: [u128; 32],
+    pub fpcr: usize,
+    pub fpsr: usize,
+}
+
+impl FpState {
+    /// Save the FP/SIMD registers into the state.
+    #[inline]
+    pub fn save(&mut self) {
+        unsafe { fp_save(self) }
+    }
+
+    /// Restore the FP/SIMD registers from the state.
+    #[inline]
+    pub fn restore(&self) {
+        unsafe { fp_restore(self) }
+    }
+}
+
+#[naked]
+unsafe extern "C" fn fp_save(_state: *mut FpState) {
+    naked_asm!(
+        ".arch_extension fp
+        .arch_extension simd
+        stp     q0, q1, [x0, 0 * 16]
+        stp     q2, q3, [x0, 2 * 16]
+        stp     q4, q5, [x0, 4 * 16]
+        stp     q6, q7, [x0, 6 * 16]
+        stp     q8, q9, [x0, 8 * 16]
+        stp     q10, q11, [x0, 10 * 16]
+        stp     q12, q13, [x0, 12 * 16]
+        stp     q14, q15, [x0, 14 * 16]
+        stp     q16, q17, [x0, 16 * 16]
+        stp     q18, q19, [x0, 18 * 16]
+        stp     q20, q21, [x0, 20 * 16]
+        stp     q22, q23, [x0, 22 * 16]
+        stp     q24, q25, [x0, 24 * 16]
+        stp     q26, q27, [x0, 26 * 16]
+        stp     q28, q29, [x0, 28 * 16]
+        stp     q30, q31, [x0, 30 * 16]
+        mrs     x9, fpcr
+        mrs     x10, fpsr
+        add     x11, x0, 32 * 16
+        stp     x9, x10, [x11]
+        ret"
+    )
+}
+
+#[naked]
+unsafe extern "C" fn fp_restore(_state: *const FpState) {
+    naked_asm!(
+        ".arch_extension fp
+        .arch_extension simd
+        ldp     q0, q1, [x0, 0 * 16]
+        ldp     q2, q3, [x0, 2 * 16]
+        ldp     q4, q5, [x0, 4 * 16]
+        ldp     q6, q7, [x0, 6 * 16]
+        ldp     q8, q9, [x0, 8 * 16]
+        ldp     q10, q11, [x0, 10 * 16]
+        ldp     q12, q13, [x0, 12 * 16]
+        ldp     q14, q15, [x0, 14 * 16]
+        ldp     q16, q17, [x0, 16 * 16]
+        ldp     q18, q19, [x0, 18 * 16]
+        ldp     q20, q21, [x0, 20 * 16]
+        ldp     q22, q23, [x0, 22 * 16]
+        ldp     q24, q25, [x0, 24 * 16]
+        ldp     q26, q27, [x0, 26 * 16]
+        ldp     q28, q29, [x0, 28 * 16]
+        ldp     q30, q31, [x0, 30 * 16]
+        add     x11, x0, 32 * 16
+        ldp     x9, x10, [x11]
+        msr     fpcr, x9
+        msr     fpsr, x10
+        ret"
+    )
+}
+
 /// Kernel Context
 ///
 /// Kernel Context is used to switch context between kernel task.
@@ -53,6 +134,9 @@ pub struct KContext {
     _regs: [usize; 11],
     /// Kernel Program Counter, Will return to this address.
     kpc: usize,
+    /// FP/SIMD state, saved by [save_fp_regs].
+    #[cfg(feature = "fp_simd")]
+    fp_state: FpState,
 }
 
 impl KContext {
@@ -63,8 +147,45 @@ impl KContext {
             ktp: 0,
             _regs: [0; 11],
             kpc: 0,
+            #[cfg(feature = "fp_simd")]
+            fp_state: FpState::default(),
         }
     }
+
+    /// Create a Kernel Context for a new kernel thread.
+    ///
+    /// The thread starts at `entry` with `arg` as its argument, on the stack
+    /// below `stack_top` and with the thread pointer `tls`. Interrupts are
+    /// enabled before `entry` is called.
+    pub fn new_thread(
+        entry: extern "C" fn(usize) -> !,
+        arg: usize,
+        stack_top: VirtAddr,
+        tls: usize,
+    ) -> Self {
+        let mut kcontext = Self::blank();
+        // The stack pointer must be aligned to 16 bytes.
+        kcontext.ksp = stack_top.raw() & !0xf;
+        kcontext.ktp = tls;
+        kcontext._regs[0] = entry as usize;
+        kcontext._regs[1] = arg;
+        kcontext.kpc = kernel_thread_entry as usize;
+        kcontext
+    }
+}
+
+/// The first function of the kernel thread, `x19` is the entry and `x20` is the argument.
+#[naked]
+unsafe extern "C" fn kernel_thread_entry() -> ! {
+    naked_asm!(
+        "
+            mov     x0, x20
+            msr     daifclr, #2
+            blr     x19
+            bl      {thread_returned}
+        ",
+        thread_returned = sym super::kernel_thread_returned,
+    )
 }
 
 /// Indexing operations for KContext
@@ -172,6 +293,26 @@ unsafe extern "C" fn context_switch_pt_impl(
     )
 }
 
+/// Save the FP/SIMD registers of the current task.
+///
+/// # Safety
+///
+/// `from` must point to a valid [KContext].
+#[cfg(feature = "fp_simd")]
+pub unsafe extern "C" fn save_fp_regs(from: *mut KContext) {
+    (*from).fp_state.save();
+}
+
+/// Restore the FP/SIMD registers of the next task.
+///
+/// # Safety
+///
+/// `to` must point to a valid [KContext].
+#[cfg(feature = "fp_simd")]
+pub unsafe extern "C" fn restore_fp_regs(to: *const KContext) {
+    (*to).fp_state.restore();
+}
+
 /// Read thread pointer currently.
 #[naked]
 pub extern "C" fn read_current_tp() -> usize {
@@ -4,7 +4,9 @@ use core::{
     ops::{Index, IndexMut},
 };
 
-use crate::{components::kcontext::KContextArgs, pagetable::PageTable};
+use crate::{components::kcontext::KContextArgs, pagetable::PageTable, VirtAddr};
+#[cfg(feature = "fp_lazy")]
+use loongArch64::register::euen;
 
 /// Save the task context registers.
 macro_rules! save_callee_regs {
@@ -48,7 +50,6 @@ macro_rules! restore_callee_regs {
     };
 }
 
-#[cfg(feature = "fp_simd")]
 macro_rules! save_fp_regs {
     () => {
         "
@@ -116,7 +117,6 @@ macro_rules! save_fp_regs {
     };
 }
 
-#[cfg(feature = "fp_simd")]
 macro_rules! restore_fp_regs {
     () => {
         "
@@ -185,10 +185,9 @@ macro_rules! restore_fp_regs {
 }
 
 /// Floating-point registers of LoongArch64.
-#[cfg(feature = "fp_simd")]
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
-pub struct FpStatus {
+pub struct FpState {
     /// Floating-point registers (f0-f31)
     pub fp: [u64; 32],
     /// Floating-point Condition Code register
@@ -197,6 +196,30 @@ pub struct FpStatus {
     pub fcsr: usize,
 }
 
+impl FpState {
+    /// Save the floating-point registers into the state.
+    #[inline]
+    pub fn save(&mut self) {
+        unsafe { fp_save(self) }
+    }
+
+    /// Restore the floating-point registers from the state.
+    #[inline]
+    pub fn restore(&self) {
+        unsafe { fp_restore(self) }
+    }
+}
+
+#[naked]
+unsafe extern "C" fn fp_save(_state: *mut FpState) {
+    naked_asm!(save_fp_regs!(), "ret")
+}
+
+#[naked]
+unsafe extern "C" fn fp_restore(_state: *const FpState) {
+    naked_asm!(restore_fp_regs!(), "ret")
+}
+
 /// Kernel Context
 ///
 /// Kernel Context is used to switch context between kernel task.
@@ -211,9 +234,9 @@ pub struct KContext {
     _sregs: [usize; 10],
     /// Kernel Program Counter, Will return to this address.
     kpc: usize,
+    /// Floating-point state, saved by [save_fp_regs].
     #[cfg(feature = "fp_simd")]
-    /// Floating Point Status
-    fp_status: FpStatus,
+    fp_state: FpState,
 }
 
 impl KContext {
@@ -225,9 +248,45 @@ impl KContext {
             _sregs: [0; 10],
             kpc: 0,
             #[cfg(feature = "fp_simd")]
-            fp_status: FpStatus::default(),
+            fp_state: FpState::default(),
         }
     }
+
+    /// Create a Kernel Context for a new kernel thread.
+    ///
+    /// The thread starts at `entry` with `arg` as its argument, on the stack
+    /// below `stack_top` and with the thread pointer `tls`. Interrupts are
+    /// enabled before `entry` is called.
+    pub fn new_thread(
+        entry: extern "C" fn(usize) -> !,
+        arg: usize,
+        stack_top: VirtAddr,
+        tls: usize,
+    ) -> Self {
+        let mut kcontext = Self::blank();
+        // The stack pointer must be aligned to 16 bytes.
+        kcontext.ksp = stack_top.raw() & !0xf;
+        kcontext.ktp = tls;
+        kcontext._sregs[1] = entry as usize;
+        kcontext._sregs[2] = arg;
+        kcontext.kpc = kernel_thread_entry as usize;
+        kcontext
+    }
+}
+
+/// The first function of the kernel thread, `s0` is the entry and `s1` is the argument.
+#[naked]
+unsafe extern "C" fn kernel_thread_entry() -> ! {
+    naked_asm!(
+        "
+            move        $a0, $s1
+            li.w        $t0, 0x4
+            csrxchg     $t0, $t0, 0x0
+            jirl        $ra, $s0, 0
+            bl          {thread_returned}
+        ",
+        thread_returned = sym super::kernel_thread_returned,
+    )
 }
 
 /// Indexing operations for KContext
@@ -343,7 +402,7 @@ pub unsafe extern "C" fn save_fp_regs(from: *mut KContext) {
         "addi.d    $a0, $a0, {fp_offset}",
         save_fp_regs!(),
         "ret",
-        fp_offset = const offset_of!(KContext, fp_status)
+        fp_offset = const offset_of!(KContext, fp_state)
     )
 }
 
@@ -354,10 +413,29 @@ pub unsafe extern "C" fn restore_fp_regs(to: *const KContext) {
         "addi.d    $a0, $a0, {fp_offset}",
         restore_fp_regs!(),
         "ret",
-        fp_offset = const offset_of!(KContext, fp_status)
+        fp_offset = const offset_of!(KContext, fp_state)
     )
 }
 
+/// Check whether the floating-point unit is enabled.
+#[cfg(feature = "fp_lazy")]
+pub(crate) fn fp_enabled() -> bool {
+    euen::read().fpe()
+}
+
+/// Enable the floating-point unit.
+#[cfg(feature = "fp_lazy")]
+pub(crate) fn fp_enable() {
+    euen::set_fpe(true);
+}
+
+/// Disable the floating-point unit, the next floating-point instruction
+/// raises a floating-point unavailable exception.
+#[cfg(feature = "fp_lazy")]
+pub(crate) fn fp_disable() {
+    euen::set_fpe(false);
+}
+
 #[naked]
 pub extern "C" fn read_current_tp() -> usize {
     unsafe {
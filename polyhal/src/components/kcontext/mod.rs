@@ -21,6 +21,65 @@ pub enum KContextArgs {
 
 pub_use_arch!(context_switch, context_switch_pt);
 
+/// Called if the entry of a kernel thread created by `KContext::new_thread` returned.
+extern "C" fn kernel_thread_returned() -> ! {
+    panic!("kernel thread returned from its entry");
+}
+
 #[cfg(feature = "fp_simd")]
-#[cfg(target_arch = "loongarch64")]
 pub_use_arch!(save_fp_regs, restore_fp_regs);
+
+#[cfg(target_arch = "riscv64")]
+pub_use_arch!(save_vector_regs, restore_vector_regs);
+
+/// The context whose floating-point state is waiting to be restored.
+#[cfg(all(
+    feature = "fp_lazy",
+    any(target_arch = "x86_64", target_arch = "loongarch64")
+))]
+#[polyhal_macro::percpu]
+static FP_PENDING: usize = 0;
+
+/// Switch the floating-point state lazily, call it before [context_switch].
+///
+/// Save the state of `from` if it used the floating-point unit, then disable
+/// the unit. The state of `to` is restored by [handle_fp_trap] when the first
+/// floating-point instruction is trapped.
+///
+/// It's only on x86_64 and loongarch64. The riscv64 and aarch64 kernels are
+/// soft-float, the state of the user task is switched with its `TrapFrame`.
+///
+/// # Safety
+///
+/// `from` and `to` must point to valid [KContext]s, and `to` must be alive
+/// until it is switched out.
+#[cfg(all(
+    feature = "fp_lazy",
+    any(target_arch = "x86_64", target_arch = "loongarch64")
+))]
+pub unsafe fn switch_fp_lazy(from: *mut KContext, to: *const KContext) {
+    // The unit is still disabled if `from` didn't use it, its state is intact.
+    if fp_enabled() {
+        save_fp_regs(from);
+    }
+    FP_PENDING.write(to as usize);
+    fp_disable();
+}
+
+/// Handle the trap raised by the disabled floating-point unit.
+///
+/// Return `true` if the pending state was restored, the trapped
+/// instruction should be executed again.
+#[cfg(all(
+    feature = "fp_lazy",
+    any(target_arch = "x86_64", target_arch = "loongarch64")
+))]
+pub fn handle_fp_trap() -> bool {
+    let to = core::mem::take(FP_PENDING.ref_mut());
+    if to == 0 {
+        return false;
+    }
+    fp_enable();
+    unsafe { restore_fp_regs(to as *const KContext) };
+    true
+}
@@ -1,9 +1,13 @@
 use core::{
-    arch::naked_asm,
+    arch::{asm, naked_asm},
     ops::{Index, IndexMut},
 };
 
-use crate::PageTable;
+#[cfg(feature = "fp_simd")]
+use riscv::register::sstatus::{self, FS};
+
+use crate::vector::{VectorState, VS};
+use crate::{PageTable, VirtAddr};
 
 use crate::components::kcontext::KContextArgs;
 
@@ -60,6 +64,104 @@ macro_rules! ret {
     };
 }
 
+/// Floating-point registers `f0`-`f31` and `fcsr`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FpState {
+    pub f: [u64; 32],
+    pub fcsr: usize,
+}
+
+impl FpState {
+    /// Save the floating-point registers into the state.
+    #[inline]
+    pub fn save(&mut self) {
+        unsafe {
+            asm!(
+                "fsd    f0, 0({0})",
+                "fsd    f1, 8({0})",
+                "fsd    f2, 16({0})",
+                "fsd    f3, 24({0})",
+                "fsd    f4, 32({0})",
+                "fsd    f5, 40({0})",
+                "fsd    f6, 48({0})",
+                "fsd    f7, 56({0})",
+                "fsd    f8, 64({0})",
+                "fsd    f9, 72({0})",
+                "fsd    f10, 80({0})",
+                "fsd    f11, 88({0})",
+                "fsd    f12, 96({0})",
+                "fsd    f13, 104({0})",
+                "fsd    f14, 112({0})",
+                "fsd    f15, 120({0})",
+                "fsd    f16, 128({0})",
+                "fsd    f17, 136({0})",
+                "fsd    f18, 144({0})",
+                "fsd    f19, 152({0})",
+                "fsd    f20, 160({0})",
+                "fsd    f21, 168({0})",
+                "fsd    f22, 176({0})",
+                "fsd    f23, 184({0})",
+                "fsd    f24, 192({0})",
+                "fsd    f25, 200({0})",
+                "fsd    f26, 208({0})",
+                "fsd    f27, 216({0})",
+                "fsd    f28, 224({0})",
+                "fsd    f29, 232({0})",
+                "fsd    f30, 240({0})",
+                "fsd    f31, 248({0})",
+                "frcsr  {1}",
+                in(reg) self.f.as_mut_ptr(),
+                out(reg) self.fcsr,
+            )
+        }
+    }
+
+    /// Restore the floating-point registers from the state.
+    #[inline]
+    pub fn restore(&self) {
+        unsafe {
+            asm!(
+                "fld    f0, 0({0})",
+                "fld    f1, 8({0})",
+                "fld    f2, 16({0})",
+                "fld    f3, 24({0})",
+                "fld    f4, 32({0})",
+                "fld    f5, 40({0})",
+                "fld    f6, 48({0})",
+                "fld    f7, 56({0})",
+                "fld    f8, 64({0})",
+                "fld    f9, 72({0})",
+                "fld    f10, 80({0})",
+                "fld    f11, 88({0})",
+                "fld    f12, 96({0})",
+                "fld    f13, 104({0})",
+                "fld    f14, 112({0})",
+                "fld    f15, 120({0})",
+                "fld    f16, 128({0})",
+                "fld    f17, 136({0})",
+                "fld    f18, 144({0})",
+                "fld    f19, 152({0})",
+                "fld    f20, 160({0})",
+                "fld    f21, 168({0})",
+                "fld    f22, 176({0})",
+                "fld    f23, 184({0})",
+                "fld    f24, 192({0})",
+                "fld    f25, 200({0})",
+                "fld    f26, 208({0})",
+                "fld    f27, 216({0})",
+                "fld    f28, 224({0})",
+                "fld    f29, 232({0})",
+                "fld    f30, 240({0})",
+                "fld    f31, 248({0})",
+                "fscsr  {1}",
+                in(reg) self.f.as_ptr(),
+                in(reg) self.fcsr,
+            )
+        }
+    }
+}
+
 /// Kernel Context
 ///
 /// Kernel Context is used to switch context between kernel task.
@@ -75,6 +177,11 @@ pub struct KContext {
     _sregs: [usize; 12],
     /// Kernel Program Counter, Will return to this address.
     kpc: usize,
+    /// Floating-point state, saved by [save_fp_regs].
+    #[cfg(feature = "fp_simd")]
+    fp_state: FpState,
+    /// Vector state, saved by [save_vector_regs].
+    vector: VectorState,
 }
 
 impl KContext {
@@ -85,8 +192,46 @@ impl KContext {
             ktp: 0,
             _sregs: [0; 12],
             kpc: 0,
+            #[cfg(feature = "fp_simd")]
+            fp_state: FpState::default(),
+            vector: VectorState::new(),
         }
     }
+
+    /// Create a Kernel Context for a new kernel thread.
+    ///
+    /// The thread starts at `entry` with `arg` as its argument, on the stack
+    /// below `stack_top` and with the thread pointer `tls`. Interrupts are
+    /// enabled before `entry` is called.
+    pub fn new_thread(
+        entry: extern "C" fn(usize) -> !,
+        arg: usize,
+        stack_top: VirtAddr,
+        tls: usize,
+    ) -> Self {
+        let mut kcontext = Self::blank();
+        // The stack pointer must be aligned to 16 bytes.
+        kcontext.ksp = stack_top.raw() & !0xf;
+        kcontext.ktp = tls;
+        kcontext._sregs[1] = entry as usize;
+        kcontext._sregs[2] = arg;
+        kcontext.kpc = kernel_thread_entry as usize;
+        kcontext
+    }
+}
+
+/// The first function of the kernel thread, `s1` is the entry and `s2` is the argument.
+#[naked]
+unsafe extern "C" fn kernel_thread_entry() -> ! {
+    naked_asm!(
+        "
+            mv      a0, s2
+            csrsi   sstatus, 2
+            jalr    s1
+            call    {thread_returned}
+        ",
+        thread_returned = sym super::kernel_thread_returned,
+    )
 }
 
 /// Indexing operations for KContext
@@ -197,6 +342,56 @@ unsafe extern "C" fn context_switch_pt_impl(
     )
 }
 
+/// Save the floating-point registers of the current task.
+///
+/// # Safety
+///
+/// `from` must point to a valid [KContext].
+#[cfg(feature = "fp_simd")]
+pub unsafe extern "C" fn save_fp_regs(from: *mut KContext) {
+    if sstatus::read().fs() != FS::Off {
+        (*from).fp_state.save();
+    }
+}
+
+/// Restore the floating-point registers of the next task.
+///
+/// # Safety
+///
+/// `to` must point to a valid [KContext].
+#[cfg(feature = "fp_simd")]
+pub unsafe extern "C" fn restore_fp_regs(to: *const KContext) {
+    if sstatus::read().fs() != FS::Off {
+        (*to).fp_state.restore();
+    }
+}
+
+/// Save the vector registers of the current task.
+///
+/// The registers are saved only if `sstatus.VS` is dirty.
+///
+/// # Safety
+///
+/// `from` must point to a valid [KContext].
+pub unsafe extern "C" fn save_vector_regs(from: *mut KContext) {
+    if VS::read() == VS::Dirty {
+        (*from).vector.save();
+        VS::Clean.write();
+    }
+}
+
+/// Restore the vector registers of the next task.
+///
+/// # Safety
+///
+/// `to` must point to a valid [KContext].
+pub unsafe extern "C" fn restore_vector_regs(to: *const KContext) {
+    if VS::read() != VS::Off {
+        (*to).vector.restore();
+        VS::Clean.write();
+    }
+}
+
 #[naked]
 pub extern "C" fn read_current_tp() -> usize {
     unsafe {
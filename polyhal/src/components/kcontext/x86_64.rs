@@ -1,9 +1,14 @@
 use crate::components::kcontext::KContextArgs;
-use crate::PageTable;
+use crate::{PageTable, VirtAddr};
 use core::arch::naked_asm;
 use core::ops::{Index, IndexMut};
+#[cfg(feature = "fp_lazy")]
+use x86_64::registers::control::{Cr0, Cr0Flags};
 use x86_64::registers::model_specific::FsBase;
 
+/// Floating-point and SIMD state, saved by `XSAVE` or `FXSAVE`.
+pub type FpState = crate::xsave::ExtendedState;
+
 /// Save the task context registers.
 macro_rules! save_callee_regs {
     () => {
@@ -69,6 +74,9 @@ pub struct KContext {
     r15: usize,
     /// Kernel Program Counter, Will return to this address.
     kpc: usize,
+    /// Floating-point and SIMD state, saved by [save_fp_regs].
+    #[cfg(feature = "fp_simd")]
+    fp_state: FpState,
 }
 
 impl KContext {
@@ -84,8 +92,45 @@ impl KContext {
             r14: 0,
             r15: 0,
             kpc: 0,
+            #[cfg(feature = "fp_simd")]
+            fp_state: FpState::new(),
         }
     }
+
+    /// Create a Kernel Context for a new kernel thread.
+    ///
+    /// The thread starts at `entry` with `arg` as its argument, on the stack
+    /// below `stack_top` and with the thread pointer `tls`. Interrupts are
+    /// enabled before `entry` is called.
+    pub fn new_thread(
+        entry: extern "C" fn(usize) -> !,
+        arg: usize,
+        stack_top: VirtAddr,
+        tls: usize,
+    ) -> Self {
+        let mut kcontext = Self::blank();
+        // The stack pointer must be aligned to 16 bytes before the call.
+        kcontext.ksp = stack_top.raw() & !0xf;
+        kcontext.ktp = tls;
+        kcontext.rbx = entry as usize;
+        kcontext.r12 = arg;
+        kcontext.kpc = kernel_thread_entry as usize;
+        kcontext
+    }
+}
+
+/// The first function of the kernel thread, `rbx` is the entry and `r12` is the argument.
+#[naked]
+unsafe extern "C" fn kernel_thread_entry() -> ! {
+    naked_asm!(
+        "
+            mov     rdi, r12
+            sti
+            call    rbx
+            call    {thread_returned}
+        ",
+        thread_returned = sym super::kernel_thread_returned,
+    )
 }
 
 /// Indexing operations for KContext
@@ -214,6 +259,45 @@ unsafe extern "C" fn context_switch_pt_impl(
     )
 }
 
+/// Save the floating-point and SIMD registers of the current task.
+///
+/// # Safety
+///
+/// `from` must point to a valid [KContext].
+#[cfg(feature = "fp_simd")]
+pub unsafe extern "C" fn save_fp_regs(from: *mut KContext) {
+    (*from).fp_state.save();
+}
+
+/// Restore the floating-point and SIMD registers of the next task.
+///
+/// # Safety
+///
+/// `to` must point to a valid [KContext].
+#[cfg(feature = "fp_simd")]
+pub unsafe extern "C" fn restore_fp_regs(to: *const KContext) {
+    (*to).fp_state.restore();
+}
+
+/// Check whether the floating-point unit is enabled.
+#[cfg(feature = "fp_lazy")]
+pub(crate) fn fp_enabled() -> bool {
+    !Cr0::read().contains(Cr0Flags::TASK_SWITCHED)
+}
+
+/// Enable the floating-point unit.
+#[cfg(feature = "fp_lazy")]
+pub(crate) fn fp_enable() {
+    unsafe { Cr0::update(|x| x.remove(Cr0Flags::TASK_SWITCHED)) }
+}
+
+/// Disable the floating-point unit, the next floating-point instruction
+/// raises a device not available exception.
+#[cfg(feature = "fp_lazy")]
+pub(crate) fn fp_disable() {
+    unsafe { Cr0::update(|x| x.insert(Cr0Flags::TASK_SWITCHED)) }
+}
+
 /// Read thread pointer currently.
 #[inline]
 pub fn read_current_tp() -> usize {
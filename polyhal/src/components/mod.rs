@@ -2,6 +2,9 @@
 //!
 //!
 
+#[cfg(feature = "backtrace")]
+pub mod backtrace;
+pub mod breakpoint;
 pub mod common;
 pub mod instruction;
 pub mod irq;
@@ -9,6 +12,7 @@ pub mod kcontext;
 pub mod mem;
 pub mod multicore;
 pub mod percpu;
+pub mod stack;
 pub mod timer;
 
 use polyhal_macro::define_arch_mods;
@@ -0,0 +1,137 @@
+//! Run the functions on the other CPUs.
+//!
+//! Each CPU has a queue of the call requests in its per-CPU area, the queue
+//! is registered when the CPU is initialized. The caller pushes the request
+//! to the queue of the target CPU and sends the [CALL_IPI_KIND] IPI, the
+//! target runs the queued functions when it takes the IPI.
+//!
+//! The caller runs its own queue while it waits, so two CPUs calling each
+//! other don't deadlock. The target must have the interrupts enabled.
+
+use core::hint::spin_loop;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use arrayvec::ArrayVec;
+
+use crate::arch::hart_id;
+use crate::components::irq::{send_ipi_mask, MAX_IPI_CPUS};
+use crate::utils::MutexNoIrq;
+
+/// The IPI kind reserved for the calls, which isn't passed to the kernel.
+pub(crate) const CALL_IPI_KIND: usize = usize::BITS as usize - 1;
+
+/// The maximum number of the pending calls of a CPU.
+const CALL_QUEUE_SIZE: usize = 16;
+
+/// A function to be called on another CPU.
+#[derive(Clone, Copy)]
+struct CallRequest {
+    func: *const (dyn Fn() + Sync),
+    /// Decremented after the call, null if nobody waits.
+    pending: *const AtomicUsize,
+}
+
+unsafe impl Send for CallRequest {}
+
+type CallQueue = MutexNoIrq<ArrayVec<CallRequest, CALL_QUEUE_SIZE>>;
+
+#[polyhal_macro::percpu]
+static CALL_QUEUE: CallQueue = MutexNoIrq::new(ArrayVec::new_const());
+
+/// The call queue of each CPU, null if the CPU isn't initialized.
+static CALL_QUEUES: [AtomicPtr<CallQueue>; MAX_IPI_CPUS] =
+    [const { AtomicPtr::new(null_mut()) }; MAX_IPI_CPUS];
+
+/// Register the call queue of the current CPU.
+pub(crate) fn init() {
+    if let Some(queue) = CALL_QUEUES.get(hart_id()) {
+        queue.store(CALL_QUEUE.get_mut_ptr(), Ordering::Release);
+    }
+}
+
+/// Run the queued calls of the current CPU.
+pub(crate) fn handle_calls() {
+    loop {
+        // Don't hold the lock while calling, the function may call other CPUs.
+        let Some(request) = CALL_QUEUE.lock().pop_at(0) else {
+            break;
+        };
+        unsafe {
+            (*request.func)();
+            if let Some(pending) = request.pending.as_ref() {
+                pending.fetch_sub(1, Ordering::Release);
+            }
+        }
+    }
+}
+
+/// Queue the call on the CPUs in `mask`, the CPUs which aren't initialized are skipped.
+///
+/// Wait until all the calls are completed if `pending` is given.
+/// Return the mask of the CPUs which were called.
+fn call_on_mask(
+    mask: usize,
+    func: *const (dyn Fn() + Sync),
+    pending: Option<&AtomicUsize>,
+) -> usize {
+    let request = CallRequest {
+        func,
+        pending: pending.map_or(core::ptr::null(), |pending| pending as _),
+    };
+    let mut sent = 0;
+    for (cpu, queue) in CALL_QUEUES.iter().enumerate() {
+        if mask & (1 << cpu) == 0 {
+            continue;
+        }
+        let Some(queue) = (unsafe { queue.load(Ordering::Acquire).as_ref() }) else {
+            continue;
+        };
+        if let Some(pending) = pending {
+            pending.fetch_add(1, Ordering::Relaxed);
+        }
+        while queue.lock().try_push(request).is_err() {
+            handle_calls();
+            spin_loop();
+        }
+        sent |= 1 << cpu;
+    }
+    send_ipi_mask(sent, CALL_IPI_KIND);
+    if let Some(pending) = pending {
+        while pending.load(Ordering::Acquire) != 0 {
+            handle_calls();
+            spin_loop();
+        }
+    }
+    sent
+}
+
+/// Call `f` on the CPU and wait until it returns.
+///
+/// It's called directly if `cpu` is the current CPU.
+/// Return `false` if the CPU isn't initialized, `f` isn't called.
+pub fn call_on(cpu: usize, f: &(dyn Fn() + Sync)) -> bool {
+    if cpu == hart_id() {
+        f();
+        return true;
+    }
+    let pending = AtomicUsize::new(0);
+    // The function outlives the call as it's waited.
+    let func: *const (dyn Fn() + Sync + '_) = f;
+    call_on_mask(
+        1usize.checked_shl(cpu as u32).unwrap_or(0),
+        unsafe {
+            core::mem::transmute::<*const (dyn Fn() + Sync + '_), *const (dyn Fn() + Sync)>(func)
+        },
+        Some(&pending),
+    ) != 0
+}
+
+/// Call `f` on all the other CPUs, wait until all of them return if `wait` is true.
+///
+/// The function must be `'static` as the call may outlive the caller if it
+/// doesn't wait, use [call_on] to call a borrowing closure.
+pub fn call_on_all(f: &'static (dyn Fn() + Sync), wait: bool) {
+    let pending = AtomicUsize::new(0);
+    call_on_mask(usize::MAX & !(1 << hart_id()), f, wait.then_some(&pending));
+}
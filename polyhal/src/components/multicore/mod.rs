@@ -12,10 +12,27 @@
 //! boot_core(hart_id, sp_top);
 //! ```
 //!
+//! Run a function on the other CPUs through the IPIs.
+//!
+//! ```rust
+//! // Wait until the CPU 1 flushes its TLB.
+//! call_on(1, &|| flush_tlb());
+//! // Stop the other CPUs without waiting.
+//! call_on_all(&|| loop {}, false);
+//! ```
+//!
 //! Here will have more functionality about multicore in the future.
 //!
 
+mod call;
+
+use crate::ctor::CtorType;
 use crate::pub_use_arch;
 
+pub use call::{call_on, call_on_all};
+pub(crate) use call::{handle_calls, CALL_IPI_KIND};
+
 super::define_arch_mods!();
 pub_use_arch!(boot_core);
+
+ph_ctor!(MULTICORE_INIT_CALL, CtorType::Cpu, call::init);
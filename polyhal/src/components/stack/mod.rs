@@ -0,0 +1,82 @@
+//! Kernel stack guard module.
+//!
+//! Every kernel stack allocated by polyhal has a guard region of
+//! [STACK_GUARD_SIZE] bytes right below it. The guard region of a stack
+//! is registered through [register_stack_guard].
+//!
+//! On x86_64 the guard pages are unmapped, so an overflow raises a page
+//! fault that can't be delivered on the stack and escalates to a double
+//! fault which runs on its own IST stack. Other architectures check the
+//! stack pointer against the guard regions in the kernel trap entry.
+//!
+//! Kernel can register the guard regions of the stacks which are
+//! allocated by itself, the guard memory must not be used for anything else.
+//!
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{arch::consts::VIRT_ADDR_START, pagetable::PAGE_SIZE};
+
+/// The size of the guard region below the kernel stack.
+pub const STACK_GUARD_SIZE: usize = 4 * PAGE_SIZE;
+
+/// The maximum number of the guard regions that can be registered.
+pub const STACK_GUARD_CAPACITY: usize = 0x40;
+
+/// The bottoms of the stacks which own a guard region, 0 is a free slot.
+static STACK_GUARDS: [AtomicUsize; STACK_GUARD_CAPACITY] =
+    [const { AtomicUsize::new(0) }; STACK_GUARD_CAPACITY];
+
+/// Register the guard region below the stack.
+///
+/// # Arguments
+///
+/// - `stack_bottom` is the lowest usable address of the stack, the guard
+///   region is `[stack_bottom - STACK_GUARD_SIZE, stack_bottom)`. It should
+///   be page aligned.
+///
+/// Return `false` if there is no free slot for the guard region.
+pub fn register_stack_guard(stack_bottom: usize) -> bool {
+    let stack_bottom = stack_bottom | VIRT_ADDR_START;
+    let registered = STACK_GUARDS.iter().any(|x| {
+        x.compare_exchange(0, stack_bottom, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    });
+    #[cfg(target_arch = "x86_64")]
+    if registered {
+        x86_64::protect_guard(stack_bottom - STACK_GUARD_SIZE, true);
+    }
+    registered
+}
+
+/// Unregister the guard region below the stack.
+///
+/// The guard region can be used as normal memory after this.
+/// Return `false` if the guard region was not registered.
+pub fn unregister_stack_guard(stack_bottom: usize) -> bool {
+    let stack_bottom = stack_bottom | VIRT_ADDR_START;
+    let unregistered = STACK_GUARDS.iter().any(|x| {
+        x.compare_exchange(stack_bottom, 0, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    });
+    #[cfg(target_arch = "x86_64")]
+    if unregistered {
+        x86_64::protect_guard(stack_bottom - STACK_GUARD_SIZE, false);
+    }
+    unregistered
+}
+
+/// Check whether the stack pointer falls into a registered guard region.
+///
+/// Return the bottom of the overflowed stack if it does.
+#[inline]
+pub fn stack_guard_hit(sp: usize) -> Option<usize> {
+    let sp = sp | VIRT_ADDR_START;
+    STACK_GUARDS
+        .iter()
+        .map(|x| x.load(Ordering::Acquire))
+        .find(|&bottom| bottom != 0 && (bottom - STACK_GUARD_SIZE..bottom).contains(&sp))
+}
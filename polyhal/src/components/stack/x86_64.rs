@@ -0,0 +1,61 @@
+use crate::{
+    common::frame_alloc_early,
+    pagetable::{PTEFlags, PAGE_SIZE, PTE, TLB},
+    PageTable, VirtAddr,
+};
+
+use super::STACK_GUARD_SIZE;
+
+/// Split the huge page at the given level into a page table of the lower level.
+fn split_huge_page(pte: &mut PTE, level: usize) {
+    let table = frame_alloc_early();
+    let flags = match level {
+        1 => pte.flags() - PTEFlags::PS,
+        _ => pte.flags(),
+    }
+    .bits();
+    let size = PAGE_SIZE << (9 * (level - 1));
+    PageTable::get_pte_list(table)
+        .iter_mut()
+        .enumerate()
+        .for_each(|(i, x)| {
+            *x = PTE::new_page(
+                pte.address() + i * size,
+                PTEFlags::from_bits_truncate(flags),
+            )
+        });
+    *pte = PTE::new_table(table);
+}
+
+/// Get the 4K page table entry of the kernel address, split the huge pages if needed.
+fn get_kernel_pte(vaddr: VirtAddr) -> Option<&'static mut PTE> {
+    let mut pte_list = PageTable::get_pte_list(PageTable::current().root());
+    for level in (1..PageTable::PAGE_LEVEL).rev() {
+        let pte = &mut pte_list[vaddr.pn_index(level)];
+        if !pte.is_valid() {
+            return None;
+        }
+        if !pte.is_table() {
+            split_huge_page(pte, level);
+        }
+        pte_list = PageTable::get_pte_list(pte.address());
+    }
+    Some(&mut pte_list[vaddr.pn_index(0)])
+}
+
+/// Unmap the guard pages if `protect` is true, map them back otherwise.
+///
+/// The kernel mapping is shared by all page tables, so this works for all cores.
+pub(super) fn protect_guard(start: usize, protect: bool) {
+    (start..start + STACK_GUARD_SIZE)
+        .step_by(PAGE_SIZE)
+        .for_each(|addr| {
+            let vaddr = va!(addr);
+            match get_kernel_pte(vaddr) {
+                Some(pte) if protect => pte.0 &= !(PTEFlags::P.bits() as usize),
+                Some(pte) => pte.0 |= PTEFlags::P.bits() as usize,
+                None => log::warn!("stack guard page {:#x} is not mapped", addr),
+            }
+            TLB::flush_vaddr(vaddr);
+        });
+}
@@ -54,9 +54,7 @@ pub fn init() {
     tcfg::set_init_val(0); // set timer initial value
     tcfg::set_en(true); // enable timer
 
-    let inter = LineBasedInterrupt::TIMER
-        | LineBasedInterrupt::SWI0
-        | LineBasedInterrupt::SWI1
-        | LineBasedInterrupt::HWI0;
-    ecfg::set_lie(inter);
+    // HWI0 is enabled by the irq module.
+    let inter = LineBasedInterrupt::TIMER | LineBasedInterrupt::SWI0 | LineBasedInterrupt::SWI1;
+    ecfg::set_lie(ecfg::read().lie() | inter);
 }
@@ -1,7 +1,7 @@
-use aarch64_cpu::registers::{Writeable, TTBR0_EL1};
+use aarch64_cpu::registers::{Writeable, TTBR0_EL1, TTBR1_EL1};
 
 use super::{MappingFlags, PageTable, PTE, TLB};
-use crate::{PhysAddr, VirtAddr};
+use crate::{arch::consts::VIRT_ADDR_START, PhysAddr, VirtAddr};
 
 impl PTE {
     #[inline]
@@ -150,6 +150,22 @@ impl PageTable {
         Self(PhysAddr::new(TTBR0_EL1.get_baddr() as _))
     }
 
+    /// The root of the walk translating `vaddr`, the kernel space is
+    /// translated by the page table in `TTBR1_EL1`.
+    #[inline]
+    pub(crate) fn translate_root(&self, vaddr: VirtAddr) -> PhysAddr {
+        match vaddr.raw() >= VIRT_ADDR_START {
+            true => PhysAddr::new(TTBR1_EL1.get_baddr() as _),
+            false => self.0,
+        }
+    }
+
+    /// Translate `vaddr` in the direct mapping window, there isn't such a window.
+    #[inline]
+    pub(crate) const fn translate_window(_vaddr: VirtAddr) -> Option<PhysAddr> {
+        None
+    }
+
     #[inline]
     pub fn restore(&self) {
         self.release();
@@ -130,6 +130,23 @@ impl PageTable {
         Self(PhysAddr::new(pgdl::read().base()))
     }
 
+    /// The root of the walk translating `vaddr`, the kernel space is in
+    /// the direct mapping windows.
+    #[inline]
+    pub(crate) fn translate_root(&self, _vaddr: VirtAddr) -> PhysAddr {
+        self.0
+    }
+
+    /// Translate `vaddr` in the direct mapping windows `0x8000...` and `0x9000...`
+    /// set up at boot, the low 48 bits are the physical address.
+    #[inline]
+    pub(crate) const fn translate_window(vaddr: VirtAddr) -> Option<PhysAddr> {
+        match vaddr.raw() >> 60 {
+            0x8 | 0x9 => Some(PhysAddr::new(vaddr.raw() & 0xffff_ffff_ffff)),
+            _ => None,
+        }
+    }
+
     #[inline]
     pub fn change(&self) {
         pgdl::set_base(self.0.raw());
@@ -189,39 +189,64 @@ impl PageTable {
 
     /// Translate a virtual adress to a physical address and mapping flags.
     ///
+    /// The kernel addresses are translated too, through the kernel page table
+    /// or the direct mapping window of the architecture. The offset in a huge
+    /// page or a block is kept.
+    ///
     /// Return None if the vaddr isn't mapped.
     /// vpn: The virtual address will be translated.
     pub fn translate(&self, vaddr: VirtAddr) -> Option<(PhysAddr, MappingFlags)> {
-        let mut pte_list = Self::get_pte_list(self.0);
-        if Self::PAGE_LEVEL == 4 {
-            let pte = &mut pte_list[vaddr.pn_index(3)];
-            if !pte.is_table() {
-                return None;
-            }
-            pte_list = Self::get_pte_list(pte.address());
+        if let Some(paddr) = Self::translate_window(vaddr) {
+            return Some((paddr, MappingFlags::P | MappingFlags::RWX));
         }
-        // level 3
-        {
-            let pte = &mut pte_list[vaddr.pn_index(2)];
-            if !pte.is_table() {
+        let mut pte_list = Self::get_pte_list(self.translate_root(vaddr));
+        for level in (0..Self::PAGE_LEVEL).rev() {
+            let pte = pte_list[vaddr.pn_index(level)];
+            if level > 0 && pte.is_table() {
+                pte_list = Self::get_pte_list(pte.address());
+                continue;
+            }
+            if !pte.is_valid() {
                 return None;
             }
-            pte_list = Self::get_pte_list(pte.address());
+            return Some((
+                PhysAddr::new(pte.address().raw() + vaddr.pn_offest(level)),
+                pte.flags().into(),
+            ));
         }
-        // level 2
-        {
-            let pte = &mut pte_list[vaddr.pn_index(1)];
-            if !pte.is_table() {
-                return None;
+        None
+    }
+
+    /// Walk the pages mapped in the user space address.
+    ///
+    /// `f` is called with the virtual address, the physical address, the size
+    /// and the mapping flags of each page in the ascending order of the address.
+    /// The size of a huge page or a block is the size mapped by its level.
+    pub fn walk_user(&self, mut f: impl FnMut(VirtAddr, PhysAddr, usize, MappingFlags)) {
+        let pte_list = &Self::get_pte_list(self.0)[..Self::GLOBAL_ROOT_PTE_RANGE];
+        Self::walk_level(pte_list, Self::PAGE_LEVEL - 1, 0, &mut f);
+    }
+
+    fn walk_level(
+        pte_list: &[PTE],
+        level: usize,
+        vaddr: usize,
+        f: &mut impl FnMut(VirtAddr, PhysAddr, usize, MappingFlags),
+    ) {
+        let size = 1 << (12 + 9 * level);
+        for (i, pte) in pte_list.iter().enumerate() {
+            let vaddr = vaddr | (i * size);
+            if level > 0 && pte.is_table() {
+                Self::walk_level(Self::get_pte_list(pte.address()), level - 1, vaddr, f);
+            } else if pte.is_valid() {
+                f(
+                    VirtAddr::new(vaddr),
+                    pte.address(),
+                    size,
+                    pte.flags().into(),
+                );
             }
-            pte_list = Self::get_pte_list(pte.address());
         }
-        // level 1, map page
-        let pte = pte_list[vaddr.pn_index(0)];
-        Some((
-            PhysAddr::new(pte.address().raw() + vaddr.pn_offest(0)),
-            pte.flags().into(),
-        ))
     }
 
     /// Release the page table entry.
@@ -143,6 +143,18 @@ impl PageTable {
         ))
     }
 
+    /// The root of the walk translating `vaddr`, the kernel space shares the root.
+    #[inline]
+    pub(crate) fn translate_root(&self, _vaddr: VirtAddr) -> PhysAddr {
+        self.0
+    }
+
+    /// Translate `vaddr` in the direct mapping window, there isn't such a window.
+    #[inline]
+    pub(crate) const fn translate_window(_vaddr: VirtAddr) -> Option<PhysAddr> {
+        None
+    }
+
     #[inline]
     pub fn change(&self) {
         unsafe {